@@ -13,7 +13,7 @@ use arch::{Architecture, Endianity};
 use dwarf_regs::DwarfRegs;
 use maps::Region;
 use range_map::RangeMap;
-use unwind_context::EmptyUnwindContext;
+use unwind_context::{EmptyUnwindContext, UnwindMode, StopReason};
 use binary::BinaryData;
 use symbols::Symbols;
 use frame_descriptions::{FrameDescriptions, ContextCache, UnwindInfo, AddressMapping};
@@ -31,7 +31,8 @@ pub struct Binary< A: Architecture > {
     mappings: Vec< AddressMapping >,
     data: Arc< BinaryData >,
     symbols: Option< Arc< Symbols< BinaryData > > >,
-    frame_descriptions: Option< FrameDescriptions< A::Endianity > >
+    frame_descriptions: Option< Arc< FrameDescriptions< A::Endianity > > >,
+    generation: u64
 }
 
 pub type BinaryHandle< A > = Arc< Binary< A > >;
@@ -45,6 +46,29 @@ impl< A: Architecture > Binary< A > {
         }
     }
 
+    // Checks whether there's an FDE which covers the given address without evaluating it.
+    pub fn has_unwind_info( &self, address: u64 ) -> bool {
+        if let Some( ref frame_descriptions ) = self.frame_descriptions {
+            frame_descriptions.has_unwind_info( &self.mappings, address )
+        } else {
+            false
+        }
+    }
+
+    pub fn is_signal_trampoline( &self, address: u64 ) -> bool {
+        if let Some( ref frame_descriptions ) = self.frame_descriptions {
+            frame_descriptions.is_signal_trampoline( &self.mappings, address )
+        } else {
+            false
+        }
+    }
+
+    // Changes every time a reload changes the mappings or the unwind info of any binary.
+    #[inline]
+    pub fn generation( &self ) -> u64 {
+        self.generation
+    }
+
     pub fn base_address( &self ) -> u64 {
         self.virtual_addresses.base.unwrap()
     }
@@ -128,7 +152,7 @@ impl< 'a, A: Architecture, T: ?Sized + BufferReader + 'a > Memory< 'a, A, T > {
         if let Some( (range, region) ) = self.regions.get( address ) {
             let offset = (region.file_offset() + (address - range.start)) as usize;
             debug!( "Reading from binary '{}' at address 0x{:016X} (+{})", region.binary().name(), address, offset );
-            let slice = &region.binary().data.as_bytes()[ offset..offset + mem::size_of::< V >() ];
+            let slice = region.binary().data.as_bytes().get( offset..offset + mem::size_of::< V >() )?;
             let value = V::read_from_slice( endianness, slice );
             Some( value )
         } else {
@@ -238,12 +262,21 @@ fn calculate_virtual_addr( region: &Region, physical_section_offset: u64 ) -> Op
     }
 }
 
-pub trait IAddressSpace {
+// Unwinds using the state of the address space at the time it was created,
+// so it can be moved to another thread.
+pub trait IUnwinder: Send {
+    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) -> StopReason;
+}
+
+pub trait IAddressSpace: Sync {
     fn reload( &mut self, binaries: HashMap< BinaryId, BinarySource >, regions: Vec< Region >, load: bool ) -> Reloaded;
-    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &BufferReader, output: &mut Vec< UserFrame > );
+    fn create_unwinder( &self ) -> Box< dyn IUnwinder >;
     fn lookup_absolute_symbol_index( &self, binary_id: &BinaryId, address: u64 ) -> Option< usize >;
     fn get_symbol_by_index< 'a >( &'a self, binary_id: &BinaryId, index: usize ) -> (Range< u64 >, &'a str);
     fn set_panic_on_partial_backtrace( &mut self, value: bool );
+    fn set_unwind_mode( &mut self, value: UnwindMode );
+    fn set_stack_scanning( &mut self, value: bool );
+    fn set_debug_binary( &mut self, binary_id: BinaryId, debug_binary: Arc< BinaryData > );
 }
 
 fn load_binary< A: Architecture >( source: BinarySource ) -> io::Result< Arc< BinaryData > > {
@@ -265,11 +298,93 @@ pub struct Reloaded {
     pub regions_mapped: Vec< Region >
 }
 
+// An immutable view of the address space which is cheap to clone
+// and can be shared with other threads to unwind on them.
+pub struct AddressSpaceSnapshot< A: Architecture > {
+    regions: Arc< RangeMap< BinaryRegion< A > > >,
+    panic_on_partial_backtrace: bool,
+    unwind_mode: UnwindMode,
+    stack_scanning: bool
+}
+
+impl< A: Architecture > Clone for AddressSpaceSnapshot< A > {
+    fn clone( &self ) -> Self {
+        AddressSpaceSnapshot {
+            regions: self.regions.clone(),
+            panic_on_partial_backtrace: self.panic_on_partial_backtrace,
+            unwind_mode: self.unwind_mode,
+            stack_scanning: self.stack_scanning
+        }
+    }
+}
+
+// The mutable state needed to unwind; every thread which unwinds needs its own.
+pub struct Unwinder< A: Architecture > {
+    empty_ctx: Option< EmptyUnwindContext< A > >
+}
+
+impl< A: Architecture > Unwinder< A > {
+    pub fn new() -> Self {
+        Unwinder {
+            empty_ctx: Some( EmptyUnwindContext::< A >::new() )
+        }
+    }
+
+    pub fn unwind( &mut self, snapshot: &AddressSpaceSnapshot< A >, regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) -> StopReason {
+        output.clear();
+
+        let stack_address = match A::get_stack_pointer( regs ) {
+            Some( address ) => address,
+            None => return StopReason::Other
+        };
+
+        let memory = Memory {
+            regions: &snapshot.regions,
+            stack,
+            stack_address
+        };
+
+        let mut empty_ctx = self.empty_ctx.take().unwrap();
+        empty_ctx.set_panic_on_partial_backtrace( snapshot.panic_on_partial_backtrace );
+        empty_ctx.set_unwind_mode( snapshot.unwind_mode );
+        empty_ctx.set_stack_scanning( snapshot.stack_scanning );
+
+        let mut ctx = empty_ctx.start( &memory, regs );
+        loop {
+            let frame = UserFrame {
+                address: ctx.current_address(),
+                initial_address: ctx.current_initial_address(),
+                is_guessed: ctx.is_current_frame_guessed()
+            };
+            output.push( frame );
+            match ctx.unwind( &memory ) {
+                Ok( next_ctx ) => ctx = next_ctx,
+                Err( empty_ctx ) => {
+                    let stop_reason = empty_ctx.stop_reason();
+                    self.empty_ctx = Some( empty_ctx );
+                    return stop_reason;
+                }
+            }
+        }
+    }
+}
+
+struct SnapshotUnwinder< A: Architecture > {
+    snapshot: AddressSpaceSnapshot< A >,
+    unwinder: Unwinder< A >
+}
+
+impl< A: Architecture > IUnwinder for SnapshotUnwinder< A > {
+    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &dyn BufferReader, output: &mut Vec< UserFrame > ) -> StopReason {
+        self.unwinder.unwind( &self.snapshot, regs, stack, output )
+    }
+}
+
 pub struct AddressSpace< A: Architecture > {
-    empty_ctx: Option< EmptyUnwindContext< A > >,
     binary_map: HashMap< BinaryId, BinaryHandle< A > >,
-    regions: RangeMap< BinaryRegion< A > >,
-    panic_on_partial_backtrace: bool
+    debug_binary_map: HashMap< BinaryId, Arc< BinaryData > >,
+    snapshot: AddressSpaceSnapshot< A >,
+    generation: u64
 }
 
 impl< A: Architecture > IAddressSpace for AddressSpace< A > {
@@ -281,8 +396,9 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
             addresses: BinaryAddresses,
             mappings: Vec< AddressMapping >,
             old_addresses: Option< BinaryAddresses >,
+            old_mappings: Option< Vec< AddressMapping > >,
             symbols: Option< Arc< Symbols< BinaryData > > >,
-            frame_descriptions: Option< FrameDescriptions< E > >,
+            frame_descriptions: Option< Arc< FrameDescriptions< E > > >,
             regions: Vec< (Region, bool) >
         }
 
@@ -291,13 +407,15 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
         let mut old_binary_map = HashMap::new();
         mem::swap( &mut old_binary_map, &mut self.binary_map );
 
-        let mut old_region_map = RangeMap::new();
-        mem::swap( &mut old_region_map, &mut self.regions );
+        // The old regions might still be referenced by snapshots
+        // which are used for unwinding on other threads.
+        let old_region_map = mem::replace( &mut self.snapshot.regions, Arc::new( RangeMap::new() ) );
 
         let mut old_regions = HashSet::new();
-        for (_, region) in old_region_map {
-            old_regions.insert( region.memory_region );
+        for region in old_region_map.values() {
+            old_regions.insert( region.memory_region.clone() );
         }
+        mem::drop( old_region_map );
         let old_region_count = old_regions.len();
 
         let mut new_binary_map = HashMap::new();
@@ -316,21 +434,14 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
 
             if !new_binary_map.contains_key( &id ) {
                 if let Some( binary ) = old_binary_map.remove( &id ) {
-                    let (binary_data, virtual_addresses, symbols, frame_descriptions) = match Arc::try_unwrap( binary ) {
-                        Ok( binary ) => (binary.data, binary.virtual_addresses, binary.symbols, binary.frame_descriptions),
-                        Err( arc ) => {
-                            assert!( false );
-                            (arc.data.clone(), arc.virtual_addresses.clone(), None, None)
-                        }
-                    };
-
                     new_binary_map.insert( id.clone(), Data {
-                        binary_data,
+                        binary_data: binary.data.clone(),
                         addresses: BinaryAddresses::default(),
                         mappings: Default::default(),
-                        old_addresses: Some( virtual_addresses ),
-                        symbols,
-                        frame_descriptions,
+                        old_addresses: Some( binary.virtual_addresses.clone() ),
+                        old_mappings: Some( binary.mappings.clone() ),
+                        symbols: binary.symbols.clone(),
+                        frame_descriptions: binary.frame_descriptions.clone(),
                         regions: Vec::new()
                     });
                 } else if let Some( source ) = binaries.remove( &id ) {
@@ -340,6 +451,7 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
                             addresses: BinaryAddresses::default(),
                             mappings: Default::default(),
                             old_addresses: None,
+                            old_mappings: None,
                             symbols: None,
                             frame_descriptions: None,
                             regions: Vec::new()
@@ -390,12 +502,17 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
             data.regions.push( (region, is_new) );
         }
 
-        let mut new_regions = Vec::new();
+        // The unwinding caches are keyed by absolute addresses, so they only need
+        // to be invalidated when a binary is added, removed or remapped, or when
+        // its unwind info gets loaded.
+        let mut is_changed = !old_binary_map.is_empty();
+        let mut new_binaries = Vec::new();
         for (id, data) in new_binary_map {
             if data.addresses.base.is_none() {
                 warn!( "No base address found for '{}'!", data.binary_data.name() );
                 if let Some( old_addresses ) = data.old_addresses {
                     reloaded.binaries_unmapped.push( (data.binary_data.id().clone(), old_addresses.base.unwrap()) );
+                    is_changed = true;
                 }
 
                 for (region, is_new) in data.regions {
@@ -424,21 +541,36 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
             };
 
 
+            let had_frame_descriptions = data.frame_descriptions.is_some();
             let frame_descriptions = match data.frame_descriptions {
                 Some( frame_descriptions ) => Some( frame_descriptions ),
-                None if load => FrameDescriptions::load( &data.binary_data ),
+                None if load => FrameDescriptions::load( &data.binary_data, self.debug_binary_map.get( &id ) ).map( Arc::new ),
                 None => None
             };
 
+            if data.old_mappings.as_ref() != Some( &data.mappings ) || had_frame_descriptions != frame_descriptions.is_some() {
+                is_changed = true;
+            }
+
+            new_binaries.push( (id, data.binary_data, data.addresses, data.mappings, symbols, frame_descriptions, data.regions) );
+        }
+
+        if is_changed {
+            self.generation += 1;
+        }
+
+        let mut new_regions = Vec::new();
+        for (id, binary_data, addresses, mappings, symbols, frame_descriptions, regions) in new_binaries {
             let binary = Arc::new( Binary {
-                data: data.binary_data,
-                virtual_addresses: data.addresses,
-                mappings: data.mappings,
+                data: binary_data,
+                virtual_addresses: addresses,
+                mappings,
                 symbols,
-                frame_descriptions
+                frame_descriptions,
+                generation: self.generation
             });
 
-            for (region, is_new) in data.regions {
+            for (region, is_new) in regions {
                 let start = region.start;
                 let end = region.end;
                 let binary_region = BinaryRegion {
@@ -457,8 +589,8 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
         }
 
         let new_region_count = new_regions.len();
-        self.regions = RangeMap::from_vec( new_regions );
-        assert_eq!( new_region_count, self.regions.len() );
+        self.snapshot.regions = Arc::new( RangeMap::from_vec( new_regions ) );
+        assert_eq!( new_region_count, self.snapshot.regions.len() );
 
         for (_, binary) in old_binary_map {
             reloaded.binaries_unmapped.push( (binary.id().clone(), binary.virtual_addresses.base.unwrap()) );
@@ -470,38 +602,11 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
         reloaded
     }
 
-    fn unwind( &mut self, regs: &mut DwarfRegs, stack: &BufferReader, output: &mut Vec< UserFrame > ) {
-        output.clear();
-
-        let stack_address = match A::get_stack_pointer( regs ) {
-            Some( address ) => address,
-            None => return
-        };
-
-        let memory = Memory {
-            regions: &self.regions,
-            stack,
-            stack_address
-        };
-
-        let mut empty_ctx = self.empty_ctx.take().unwrap();
-        empty_ctx.set_panic_on_partial_backtrace( self.panic_on_partial_backtrace );
-
-        let mut ctx = empty_ctx.start( &memory, regs );
-        loop {
-            let frame = UserFrame {
-                address: ctx.current_address(),
-                initial_address: ctx.current_initial_address()
-            };
-            output.push( frame );
-            match ctx.unwind( &memory ) {
-                Ok( next_ctx ) => ctx = next_ctx,
-                Err( empty_ctx ) => {
-                    self.empty_ctx = Some( empty_ctx );
-                    return;
-                }
-            }
-        }
+    fn create_unwinder( &self ) -> Box< dyn IUnwinder > {
+        Box::new( SnapshotUnwinder {
+            snapshot: self.snapshot(),
+            unwinder: Unwinder::new()
+        })
     }
 
     fn lookup_absolute_symbol_index( &self, binary_id: &BinaryId, address: u64 ) -> Option< usize > {
@@ -515,19 +620,41 @@ impl< A: Architecture > IAddressSpace for AddressSpace< A > {
     }
 
     fn set_panic_on_partial_backtrace( &mut self, value: bool ) {
-        self.panic_on_partial_backtrace = value;
+        self.snapshot.panic_on_partial_backtrace = value;
+    }
+
+    fn set_unwind_mode( &mut self, value: UnwindMode ) {
+        self.snapshot.unwind_mode = value;
+    }
+
+    fn set_stack_scanning( &mut self, value: bool ) {
+        self.snapshot.stack_scanning = value;
+    }
+
+    fn set_debug_binary( &mut self, binary_id: BinaryId, debug_binary: Arc< BinaryData > ) {
+        self.debug_binary_map.insert( binary_id, debug_binary );
     }
 }
 
 impl< A: Architecture > AddressSpace< A > {
     pub fn new() -> Self {
         AddressSpace {
-            empty_ctx: Some( EmptyUnwindContext::< A >::new() ),
             binary_map: HashMap::new(),
-            regions: RangeMap::new(),
-            panic_on_partial_backtrace: false
+            debug_binary_map: HashMap::new(),
+            snapshot: AddressSpaceSnapshot {
+                regions: Arc::new( RangeMap::new() ),
+                panic_on_partial_backtrace: false,
+                unwind_mode: UnwindMode::Dwarf,
+                stack_scanning: false
+            },
+            generation: 0
         }
     }
+
+    // Snapshots are not affected by any later reloads.
+    pub fn snapshot( &self ) -> AddressSpaceSnapshot< A > {
+        self.snapshot.clone()
+    }
 }
 
 #[test]
@@ -587,12 +714,20 @@ fn test_reload() {
     assert_eq!( res.binaries_mapped.len(), 2 );
     assert_eq!( res.regions_unmapped.len(), 0 );
     assert_eq!( res.regions_mapped.len(), 2 );
+    let generation = address_space.generation;
 
     let res = address_space.reload( binaries.clone(), regions.clone(), false );
     assert_eq!( res.binaries_unmapped.len(), 0 );
     assert_eq!( res.binaries_mapped.len(), 0 );
     assert_eq!( res.regions_unmapped.len(), 0 );
     assert_eq!( res.regions_mapped.len(), 0 );
+    assert_eq!( address_space.generation, generation );
+
+    // Anonymous mappings don't affect any of the binaries.
+    let mut anonymous = regions.clone();
+    anonymous.push( region( 0x5000, 0, "[heap]" ) );
+    address_space.reload( binaries.clone(), anonymous, false );
+    assert_eq!( address_space.generation, generation );
 
     regions.push( region( 0x3000, 3, "file_3" ) );
 
@@ -601,6 +736,8 @@ fn test_reload() {
     assert_eq!( res.binaries_mapped.len(), 1 );
     assert_eq!( res.regions_unmapped.len(), 0 );
     assert_eq!( res.regions_mapped.len(), 1 );
+    assert_ne!( address_space.generation, generation );
+    let generation = address_space.generation;
 
     regions.push( region( 0x4000, 3, "file_3" ) );
 
@@ -609,6 +746,8 @@ fn test_reload() {
     assert_eq!( res.binaries_mapped.len(), 0 );
     assert_eq!( res.regions_unmapped.len(), 0 );
     assert_eq!( res.regions_mapped.len(), 1 );
+    assert_ne!( address_space.generation, generation );
+    let generation = address_space.generation;
 
     regions.pop();
     regions.pop();
@@ -618,4 +757,722 @@ fn test_reload() {
     assert_eq!( res.binaries_mapped.len(), 0 );
     assert_eq!( res.regions_unmapped.len(), 2 );
     assert_eq!( res.regions_mapped.len(), 0 );
+    assert_ne!( address_space.generation, generation );
+}
+
+#[cfg(test)]
+fn unwind_synthetic_stack< A: Architecture >( unwind_mode: UnwindMode, binary: &'static [u8], base_address: u64, stack: &[u8], regs: &mut DwarfRegs ) -> Vec< u64 > {
+    let (frames, _) = unwind_synthetic_stack_with_scanning::< A >( unwind_mode, false, binary, base_address, stack, regs );
+    frames.iter().map( |frame| frame.address ).collect()
+}
+
+#[cfg(test)]
+fn unwind_synthetic_stack_with_scanning< A: Architecture >( unwind_mode: UnwindMode, stack_scanning: bool, binary: &'static [u8], base_address: u64, stack: &[u8], regs: &mut DwarfRegs ) -> (Vec< UserFrame >, StopReason) {
+    use stack_reader::StackReader;
+
+    let mut address_space = synthetic_address_space::< A >( binary, base_address );
+    address_space.set_unwind_mode( unwind_mode );
+    address_space.set_stack_scanning( stack_scanning );
+
+    let reader = StackReader { stack: stack.into() };
+    let mut output = Vec::new();
+    let stop_reason = address_space.create_unwinder().unwind( regs, &reader, &mut output );
+    (output, stop_reason)
+}
+
+#[cfg(test)]
+fn synthetic_address_space< A: Architecture >( binary: &'static [u8], base_address: u64 ) -> AddressSpace< A > {
+    let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+    let mut binaries = HashMap::new();
+    binaries.insert( id.clone(), BinarySource::StaticSlice( b"file", id, binary ) );
+
+    let regions = vec![
+        Region {
+            start: base_address,
+            end: base_address + 0x1000,
+            is_read: true,
+            is_write: false,
+            is_executable: true,
+            is_shared: false,
+            file_offset: 0,
+            major: 0,
+            minor: 0,
+            inode: 1,
+            name: "file".to_owned()
+        }
+    ];
+
+    let mut address_space = AddressSpace::< A >::new();
+    address_space.reload( binaries, regions, true );
+    address_space
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_amd64() {
+    use byteorder::LittleEndian;
+    use arch::amd64::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 0.. ], stack_address + 16 );
+    LittleEndian::write_u64( &mut stack[ 8.. ], 0x400427 );
+    LittleEndian::write_u64( &mut stack[ 16.. ], 0 );
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x400459 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x40051e );
+    regs.append( dwarf::RSP, stack_address );
+    regs.append( dwarf::RBP, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/amd64-usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x400000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x40051e, 0x400427, 0x400459 ] );
+}
+
+#[test]
+#[should_panic(expected = "Partial backtrace!")]
+fn test_panic_on_partial_backtrace_with_frame_pointer_fallback_amd64() {
+    use stack_reader::StackReader;
+    use arch::amd64::{Arch, dwarf};
+
+    // There's no unwind info at the very start of the binary,
+    // and there's no frame pointer to fall back to either.
+    let stack = [ 0; 64 ];
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x400000 );
+    regs.append( dwarf::RSP, 0x7fff0000 );
+
+    let binary = include_bytes!( "../test-data/bin/amd64-usleep_in_a_loop_no_fp" );
+    let mut address_space = synthetic_address_space::< Arch >( binary, 0x400000 );
+    address_space.set_unwind_mode( UnwindMode::DwarfThenFramePointer );
+    address_space.set_panic_on_partial_backtrace( true );
+
+    let reader = StackReader { stack: stack[..].into() };
+    let mut output = Vec::new();
+    address_space.create_unwinder().unwind( &mut regs, &reader, &mut output );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_mips64() {
+    use byteorder::BigEndian;
+    use arch::mips64::{Arch, dwarf};
+
+    // function() -> main()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    BigEndian::write_u64( &mut stack[ 16.. ], stack_address + 32 );
+    BigEndian::write_u64( &mut stack[ 24.. ], 0x120000908 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x120000a9c );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R30, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/mips64-usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x120000000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x120000a9c, 0x120000908 ] );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_mips() {
+    use byteorder::BigEndian;
+    use arch::mips::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    BigEndian::write_u32( &mut stack[ 8.. ], stack_address as u32 + 16 );
+    BigEndian::write_u32( &mut stack[ 12.. ], 0x102ac );
+    BigEndian::write_u32( &mut stack[ 32.. ], stack_address as u32 + 40 );
+    BigEndian::write_u32( &mut stack[ 36.. ], 0x102d8 );
+    BigEndian::write_u32( &mut stack[ 56.. ], 0 );
+    BigEndian::write_u32( &mut stack[ 60.. ], 0x10210 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10280 );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R30, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/mips-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10280, 0x102ac, 0x102d8, 0x10210 ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_mips() {
+    use byteorder::BigEndian;
+    use arch::mips::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    BigEndian::write_u32( &mut stack[ 28.. ], 0x10298 );
+    BigEndian::write_u32( &mut stack[ 52.. ], 0x10200 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10264 );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R31, 0x1027c );
+
+    let binary = include_bytes!( "../test-data/bin/mips-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10264, 0x1027c, 0x10298, 0x10200 ] );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_mipsel() {
+    use byteorder::LittleEndian;
+    use arch::mipsel::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    LittleEndian::write_u32( &mut stack[ 8.. ], stack_address as u32 + 16 );
+    LittleEndian::write_u32( &mut stack[ 12.. ], 0x102ac );
+    LittleEndian::write_u32( &mut stack[ 32.. ], stack_address as u32 + 40 );
+    LittleEndian::write_u32( &mut stack[ 36.. ], 0x102d8 );
+    LittleEndian::write_u32( &mut stack[ 56.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 60.. ], 0x10210 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10280 );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R30, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/mipsel-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10280, 0x102ac, 0x102d8, 0x10210 ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_mipsel() {
+    use byteorder::LittleEndian;
+    use arch::mipsel::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x10298 );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x10200 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10264 );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R31, 0x1027c );
+
+    let binary = include_bytes!( "../test-data/bin/mipsel-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10264, 0x1027c, 0x10298, 0x10200 ] );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_mips64el() {
+    use byteorder::LittleEndian;
+    use arch::mips64el::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    LittleEndian::write_u64( &mut stack[ 16.. ], stack_address + 32 );
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x103a8 );
+    LittleEndian::write_u64( &mut stack[ 32.. ], stack_address + 48 );
+    LittleEndian::write_u64( &mut stack[ 40.. ], 0x103d8 );
+    LittleEndian::write_u64( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u64( &mut stack[ 56.. ], 0x102f0 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10378 );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R30, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/mips64el-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10378, 0x103a8, 0x103d8, 0x102f0 ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_mips64el() {
+    use byteorder::LittleEndian;
+    use arch::mips64el::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> __start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x10398 );
+    LittleEndian::write_u64( &mut stack[ 40.. ], 0x102e0 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x1035c );
+    regs.append( dwarf::R29, stack_address );
+    regs.append( dwarf::R31, 0x10378 );
+
+    let binary = include_bytes!( "../test-data/bin/mips64el-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x1035c, 0x10378, 0x10398, 0x102e0 ] );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_aarch64() {
+    use byteorder::LittleEndian;
+    use arch::aarch64::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 0.. ], stack_address + 16 );
+    LittleEndian::write_u64( &mut stack[ 8.. ], 0x200258 );
+    LittleEndian::write_u64( &mut stack[ 16.. ], 0 );
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x2001dc );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x200244 );
+    regs.append( dwarf::SP, stack_address );
+    regs.append( dwarf::X29, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/aarch64-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x200000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x200244, 0x200258, 0x2001dc ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_aarch64() {
+    use byteorder::LittleEndian;
+    use arch::aarch64::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 0.. ], 0x200230 );
+    LittleEndian::write_u64( &mut stack[ 16.. ], 0x2001cc );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x200220 );
+    regs.append( dwarf::SP, stack_address );
+    regs.append( dwarf::X30, 0x200220 );
+
+    let binary = include_bytes!( "../test-data/bin/aarch64-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x200000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x200220, 0x200230, 0x2001cc ] );
+}
+
+#[test]
+fn test_unwind_with_saved_fp_registers_aarch64() {
+    use byteorder::LittleEndian;
+    use arch::aarch64::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    //
+    // The `main` saves `d8`, which is outside of the registers we keep track of.
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 0.. ], 0 );
+    LittleEndian::write_u64( &mut stack[ 8.. ], 0x200150 );
+    LittleEndian::write_u64( &mut stack[ 16.. ], 0x3ff0000000000000 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x20016c );
+    regs.append( dwarf::SP, stack_address );
+    regs.append( dwarf::X30, 0x200168 );
+
+    let binary = include_bytes!( "../test-data/bin/aarch64-saves_fp_registers" );
+    let (frames, stop_reason) = unwind_synthetic_stack_with_scanning::< Arch >( UnwindMode::Dwarf, false, binary, 0x200000, &stack, &mut regs );
+    let addresses: Vec< _ > = frames.iter().map( |frame| frame.address ).collect();
+    assert_eq!( addresses, vec![ 0x20016c, 0x200168, 0x200150 ] );
+    assert_eq!( stop_reason, StopReason::EndOfStack );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_x86() {
+    use byteorder::LittleEndian;
+    use arch::x86::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 0.. ], stack_address as u32 + 16 );
+    LittleEndian::write_u32( &mut stack[ 4.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 16.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 20.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x80480dd );
+    regs.append( dwarf::ESP, stack_address );
+    regs.append( dwarf::EBP, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x8048000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x80480dd, 0x80480b5, 0x80480be ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_x86() {
+    use byteorder::LittleEndian;
+    use arch::x86::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x80480da );
+    regs.append( dwarf::ESP, stack_address );
+    regs.append( dwarf::EBP, stack_address + 48 );
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x8048000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x80480da, 0x80480b5, 0x80480be ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_fallback_arm() {
+    use byteorder::LittleEndian;
+    use arch::arm::{Arch, dwarf};
+
+    // This binary has no `.ARM.exidx`, only `.eh_frame`.
+    // usleep() -> function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 12.. ], 0x101c4 );
+    LittleEndian::write_u32( &mut stack[ 20.. ], 0x101d0 );
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x10164 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::R15, 0x101b0 );
+    regs.append( dwarf::R13, stack_address );
+    regs.append( dwarf::R7, 0 );
+
+    let binary = include_bytes!( "../test-data/bin/arm-minimal_usleep_in_a_loop_dwarf_only" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x101b0, 0x101c4, 0x101d0, 0x10164 ] );
+}
+
+#[test]
+fn test_unwind_with_saved_fp_registers_arm() {
+    use byteorder::LittleEndian;
+    use arch::arm::{Arch, dwarf};
+
+    // This binary has no `.ARM.exidx`, and its `main` saves `d8`.
+    // The `_start` has a rule for `r4` which we can't satisfy, but it
+    // doesn't affect the return address so we still reach the end of the stack.
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 0.. ], 0x3ff0000000000000 );
+    LittleEndian::write_u32( &mut stack[ 8.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 12.. ], 0x10108 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::R15, 0x10120 );
+    regs.append( dwarf::R13, stack_address );
+    regs.append( dwarf::R14, 0x1011c );
+
+    let binary = include_bytes!( "../test-data/bin/arm-saves_fp_registers" );
+    let (frames, stop_reason) = unwind_synthetic_stack_with_scanning::< Arch >( UnwindMode::Dwarf, false, binary, 0x10000, &stack, &mut regs );
+    let addresses: Vec< _ > = frames.iter().map( |frame| frame.address ).collect();
+    assert_eq!( addresses, vec![ 0x10120, 0x1011c, 0x10108 ] );
+    assert_eq!( stop_reason, StopReason::EndOfStack );
+}
+
+#[test]
+fn test_unwind_with_stack_scanning_x86() {
+    use byteorder::LittleEndian;
+    use arch::x86::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> _start()
+    //
+    // The return address of `usleep` is corrupted, so we have to scan the stack
+    // to find the frame of `main`, and then continue unwinding normally from there.
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    LittleEndian::write_u32( &mut stack[ 20.. ], 0xdeadbeef );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 56.. ], stack_address as u32 + 64 );
+    LittleEndian::write_u32( &mut stack[ 60.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x8048115 );
+    regs.append( dwarf::ESP, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x8048000, &stack, &mut regs.clone() );
+    assert_eq!( addresses, vec![ 0x8048115 ] );
+
+    let (frames, _) = unwind_synthetic_stack_with_scanning::< Arch >( UnwindMode::Dwarf, true, binary, 0x8048000, &stack, &mut regs );
+    let frames: Vec< _ > = frames.iter().map( |frame| (frame.address, frame.is_guessed) ).collect();
+    assert_eq!( frames, vec![ (0x8048115, false), (0x80480b5, true), (0x80480be, true) ] );
+}
+
+#[test]
+fn test_unwind_stop_reason_x86() {
+    use byteorder::LittleEndian;
+    use arch::x86::{Arch, dwarf};
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x80480da );
+    regs.append( dwarf::ESP, stack_address );
+    regs.append( dwarf::EBP, stack_address + 48 );
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+    let unwind = |stack: &[u8]| {
+        let (frames, stop_reason) = unwind_synthetic_stack_with_scanning::< Arch >( UnwindMode::Dwarf, false, binary, 0x8048000, stack, &mut regs.clone() );
+        (frames.len(), stop_reason)
+    };
+
+    assert_eq!( unwind( &stack ), (3, StopReason::EndOfStack) );
+    assert_eq!( unwind( &stack[ ..40 ] ), (2, StopReason::StackExhausted) );
+
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0xdeadbeef );
+    assert_eq!( unwind( &stack ), (1, StopReason::MissingBinary) );
+
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x8048120 );
+    assert_eq!( unwind( &stack ), (2, StopReason::MissingUnwindInfo) );
+}
+
+#[test]
+fn test_unwind_with_cached_rules_x86() {
+    use byteorder::LittleEndian;
+    use stack_reader::StackReader;
+    use arch::x86::{Arch, dwarf};
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+    let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+    let region = Region {
+        start: 0x8048000,
+        end: 0x8049000,
+        is_read: true,
+        is_write: false,
+        is_executable: true,
+        is_shared: false,
+        file_offset: 0,
+        major: 0,
+        minor: 0,
+        inode: 1,
+        name: "file".to_owned()
+    };
+
+    let mut address_space = AddressSpace::< Arch >::new();
+    let reload = |address_space: &mut AddressSpace< Arch >| {
+        let mut binaries = HashMap::new();
+        binaries.insert( id.clone(), BinarySource::StaticSlice( b"file", id.clone(), binary ) );
+        address_space.reload( binaries, vec![ region.clone() ], true );
+    };
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x80480da );
+    regs.append( dwarf::ESP, stack_address );
+    regs.append( dwarf::EBP, stack_address + 48 );
+
+    let unwind = |address_space: &mut AddressSpace< Arch >, stack: &[u8]| {
+        let reader = StackReader { stack: stack.into() };
+        let mut output = Vec::new();
+        let stop_reason = address_space.create_unwinder().unwind( &mut regs.clone(), &reader, &mut output );
+        let frames: Vec< _ > = output.iter().map( |frame| (frame.address, frame.initial_address) ).collect();
+        (frames, stop_reason)
+    };
+
+    reload( &mut address_space );
+    let expected = (
+        vec![
+            (0x80480da, Some( 0x80480d0 )),
+            (0x80480b5, Some( 0x80480a0 )),
+            (0x80480be, Some( 0x80480b7 ))
+        ],
+        StopReason::EndOfStack
+    );
+
+    // The second time around the rules come from the cache.
+    assert_eq!( unwind( &mut address_space, &stack ), expected );
+    assert_eq!( unwind( &mut address_space, &stack ), expected );
+    assert_eq!( unwind( &mut address_space, &stack[ ..40 ] ).1, StopReason::StackExhausted );
+
+    reload( &mut address_space );
+    assert_eq!( unwind( &mut address_space, &stack ), expected );
+}
+
+#[test]
+fn test_unwind_with_snapshot_on_another_thread_x86() {
+    use std::thread;
+    use byteorder::LittleEndian;
+    use stack_reader::StackReader;
+    use arch::x86::{Arch, dwarf};
+
+    let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+    let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+    let region = Region {
+        start: 0x8048000,
+        end: 0x8049000,
+        is_read: true,
+        is_write: false,
+        is_executable: true,
+        is_shared: false,
+        file_offset: 0,
+        major: 0,
+        minor: 0,
+        inode: 1,
+        name: "file".to_owned()
+    };
+
+    let mut address_space = AddressSpace::< Arch >::new();
+    let reload = |address_space: &mut AddressSpace< Arch >, regions: Vec< Region >| {
+        let mut binaries = HashMap::new();
+        binaries.insert( id.clone(), BinarySource::StaticSlice( b"file", id.clone(), binary ) );
+        address_space.reload( binaries, regions, true );
+    };
+
+    // function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u32( &mut stack[ 28.. ], 0x80480b5 );
+    LittleEndian::write_u32( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480be );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x80480da );
+    regs.append( dwarf::ESP, stack_address );
+    regs.append( dwarf::EBP, stack_address + 48 );
+
+    reload( &mut address_space, vec![ region.clone() ] );
+    let snapshot = address_space.snapshot();
+
+    // Neither reloading while the binary is still in use
+    // nor unmapping it should affect the snapshot.
+    reload( &mut address_space, vec![ region.clone() ] );
+    reload( &mut address_space, Vec::new() );
+
+    let mut output = Vec::new();
+    let stop_reason = address_space.create_unwinder().unwind( &mut regs.clone(), &StackReader { stack: (&stack[..]).into() }, &mut output );
+    assert_eq!( (output.len(), stop_reason), (1, StopReason::MissingBinary) );
+
+    let handle = thread::spawn( move || {
+        let mut unwinder = Unwinder::new();
+        let mut output = Vec::new();
+        let stop_reason = unwinder.unwind( &snapshot, &mut regs, &StackReader { stack: (&stack[..]).into() }, &mut output );
+        let frames: Vec< _ > = output.iter().map( |frame| frame.address ).collect();
+        (frames, stop_reason)
+    });
+
+    assert_eq!( handle.join().unwrap(), (vec![ 0x80480da, 0x80480b5, 0x80480be ], StopReason::EndOfStack) );
+}
+
+#[test]
+fn test_unwind_with_frame_pointers_riscv64() {
+    use byteorder::LittleEndian;
+    use arch::riscv64::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 128 ];
+    LittleEndian::write_u64( &mut stack[ 16.. ], stack_address + 48 );
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x102bc );
+    LittleEndian::write_u64( &mut stack[ 32.. ], stack_address + 64 );
+    LittleEndian::write_u64( &mut stack[ 40.. ], 0x102e4 );
+    LittleEndian::write_u64( &mut stack[ 48.. ], 0 );
+    LittleEndian::write_u64( &mut stack[ 56.. ], 0x10228 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10290 );
+    regs.append( dwarf::X2, stack_address );
+    regs.append( dwarf::X8, stack_address + 32 );
+
+    let binary = include_bytes!( "../test-data/bin/riscv64-minimal_usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::FramePointer, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10290, 0x102bc, 0x102e4, 0x10228 ] );
+}
+
+#[test]
+fn test_unwind_with_dwarf_riscv64() {
+    use byteorder::LittleEndian;
+    use arch::riscv64::{Arch, dwarf};
+
+    // usleep() -> function() -> main() -> _start()
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x1029c );
+    LittleEndian::write_u64( &mut stack[ 40.. ], 0x10208 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::PC, 0x10264 );
+    regs.append( dwarf::X2, stack_address );
+    regs.append( dwarf::X1, 0x10280 );
+
+    let binary = include_bytes!( "../test-data/bin/riscv64-minimal_usleep_in_a_loop_no_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x10000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x10264, 0x10280, 0x1029c, 0x10208 ] );
+}
+
+#[test]
+fn test_unwind_with_cfa_expression_amd64() {
+    use byteorder::LittleEndian;
+    use arch::amd64::{Arch, dwarf};
+
+    // usleep@plt -> function() -> main() -> _start()
+    //
+    // The PLT entries use a CFA expression which depends on the offset
+    // of the instruction pointer within the entry.
+    let stack_address = 0x7fff0000;
+    let mut stack = [ 0; 64 ];
+    LittleEndian::write_u64( &mut stack[ 8.. ], 0x40051e );
+    LittleEndian::write_u64( &mut stack[ 16.. ], stack_address + 32 );
+    LittleEndian::write_u64( &mut stack[ 24.. ], 0x400427 );
+    LittleEndian::write_u64( &mut stack[ 40.. ], 0x400459 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, 0x40040b );
+    regs.append( dwarf::RSP, stack_address );
+    regs.append( dwarf::RBP, stack_address + 16 );
+
+    let binary = include_bytes!( "../test-data/bin/amd64-usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x400000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ 0x40040b, 0x40051e, 0x400427, 0x400459 ] );
+}
+
+#[test]
+fn test_unwind_through_signal_frame_amd64() {
+    use byteorder::LittleEndian;
+    use arch::amd64::{Arch, dwarf};
+
+    // __restore_rt() -> function() -> main() -> _start()
+    //
+    // The stack is placed over the binary's mapping so that
+    // it can also hold the signal trampoline's code.
+    let stack_address = 0x400800;
+    let mut stack = [ 0; 320 ];
+    let trampoline = 288;
+    stack[ trampoline..trampoline + 9 ].copy_from_slice( &[ 0x48, 0xc7, 0xc0, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05 ] );
+
+    let mcontext = 40;
+    let frame = 192;
+    LittleEndian::write_u64( &mut stack[ mcontext + 80.. ], stack_address + frame as u64 );
+    LittleEndian::write_u64( &mut stack[ mcontext + 120.. ], stack_address + frame as u64 );
+    LittleEndian::write_u64( &mut stack[ mcontext + 128.. ], 0x40051e );
+
+    LittleEndian::write_u64( &mut stack[ frame.. ], stack_address + frame as u64 + 16 );
+    LittleEndian::write_u64( &mut stack[ frame + 8.. ], 0x400427 );
+    LittleEndian::write_u64( &mut stack[ frame + 24.. ], 0x400459 );
+
+    let mut regs = DwarfRegs::new();
+    regs.append( dwarf::RETURN_ADDRESS, stack_address + trampoline as u64 );
+    regs.append( dwarf::RSP, stack_address );
+
+    let binary = include_bytes!( "../test-data/bin/amd64-usleep_in_a_loop_fp" );
+    let addresses = unwind_synthetic_stack::< Arch >( UnwindMode::Dwarf, binary, 0x400000, &stack, &mut regs );
+    assert_eq!( addresses, vec![ stack_address + trampoline as u64, 0x40051e, 0x400427, 0x400459 ] );
 }
@@ -0,0 +1,288 @@
+use gimli::LittleEndian;
+use dwarf_regs::DwarfRegs;
+use arch::{Architecture, classify_frame};
+use address_space::MemoryReader;
+use unwind_context::UnwindFrame;
+use frame_descriptions::{ContextCache, FrameKind};
+use archive::{Endianness, Bitness};
+use dwarf::dwarf_unwind;
+
+// Source: DWARF for the ARM 64-bit Architecture (AArch64)
+//         http://infocenter.arm.com/help/topic/com.arm.doc.ihi0057b/IHI0057B_aadwarf64.pdf
+pub mod dwarf {
+    pub const X0: u16 = 0;
+    pub const X1: u16 = 1;
+    pub const X2: u16 = 2;
+    pub const X3: u16 = 3;
+    pub const X4: u16 = 4;
+    pub const X5: u16 = 5;
+    pub const X6: u16 = 6;
+    pub const X7: u16 = 7;
+    pub const X8: u16 = 8;
+    pub const X9: u16 = 9;
+    pub const X10: u16 = 10;
+    pub const X11: u16 = 11;
+    pub const X12: u16 = 12;
+    pub const X13: u16 = 13;
+    pub const X14: u16 = 14;
+    pub const X15: u16 = 15;
+    pub const X16: u16 = 16;
+    pub const X17: u16 = 17;
+    pub const X18: u16 = 18;
+    pub const X19: u16 = 19;
+    pub const X20: u16 = 20;
+    pub const X21: u16 = 21;
+    pub const X22: u16 = 22;
+    pub const X23: u16 = 23;
+    pub const X24: u16 = 24;
+    pub const X25: u16 = 25;
+    pub const X26: u16 = 26;
+    pub const X27: u16 = 27;
+    pub const X28: u16 = 28;
+    pub const X29: u16 = 29;
+    pub const X30: u16 = 30;
+    pub const SP: u16 = 31;
+
+    pub const PC: u16 = 32;
+}
+
+#[allow(dead_code)]
+pub struct Arch {}
+
+// mov x8, #__NR_rt_sigreturn; svc #0
+const RT_SIGRETURN_CODE: u32 = 0xd280_1168;
+const SVC_CODE: u32 = 0xd400_0001;
+
+// The offset of `regs` within `struct sigcontext` from the stack pointer
+// at the point when the signal handler returns, based on the kernel's
+// `struct rt_sigframe` from `arch/arm64/kernel/signal.c`.
+const RT_SIGFRAME_REGS_OFFSET: u64 = 312;
+
+fn is_rt_sigreturn< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    memory.get_u32_at_address( Arch::ENDIANNESS, address ) == Some( RT_SIGRETURN_CODE ) &&
+    memory.get_u32_at_address( Arch::ENDIANNESS, address + 4 ) == Some( SVC_CODE )
+}
+
+fn is_signal_frame< M: MemoryReader< Arch > >( memory: &M, current_frame: &UnwindFrame< Arch > ) -> bool {
+    let address = match current_frame.regs.get( dwarf::PC ) {
+        Some( address ) => address,
+        None => return false
+    };
+
+    if let Some( ref binary ) = current_frame.binary {
+        if binary.is_signal_trampoline( address ) {
+            return true;
+        }
+
+        if let Some( (_, "__kernel_rt_sigreturn") ) = binary.lookup_absolute_symbol( address ) {
+            return true;
+        }
+    }
+
+    is_rt_sigreturn( memory, address ) || is_rt_sigreturn( memory, address.wrapping_sub( 4 ) )
+}
+
+fn unwind_signal_frame< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    let sp = match current_frame.regs.get( dwarf::SP ) {
+        Some( sp ) => sp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    // The `sp` and `pc` directly follow the general purpose registers.
+    let base = sp + RT_SIGFRAME_REGS_OFFSET;
+    for register in dwarf::X0..dwarf::PC + 1 {
+        if let Some( value ) = memory.get_u64_at_address( Arch::ENDIANNESS, base + register as u64 * 8 ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value );
+        }
+    }
+
+    current_frame.cfa = Some( sp );
+    if next_frame.regs.get( dwarf::SP ).is_none() || next_frame.regs.get( dwarf::PC ).is_none() {
+        debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+        return false;
+    }
+
+    true
+}
+
+fn is_after_call_instruction< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    match memory.get_u32_at_address( Endianness::LittleEndian, address.wrapping_sub( 4 ) ) {
+        // bl <label>
+        Some( opcode ) if opcode & 0xFC00_0000 == 0x9400_0000 => true,
+        // blr <register>
+        Some( opcode ) if opcode & 0xFFFF_FC1F == 0xD63F_0000 => true,
+        _ => false
+    }
+}
+
+impl Architecture for Arch {
+    const NAME: &'static str = "aarch64";
+    const ENDIANNESS: Endianness = Endianness::LittleEndian;
+    const BITNESS: Bitness = Bitness::B64;
+
+    type Endianity = LittleEndian;
+    type State = ContextCache< LittleEndian >;
+
+    fn register_name_str( register: u16 ) -> Option< &'static str > {
+        use self::dwarf::*;
+
+        let name = match register {
+            X0 => "X0",
+            X1 => "X1",
+            X2 => "X2",
+            X3 => "X3",
+            X4 => "X4",
+            X5 => "X5",
+            X6 => "X6",
+            X7 => "X7",
+            X8 => "X8",
+            X9 => "X9",
+            X10 => "X10",
+            X11 => "X11",
+            X12 => "X12",
+            X13 => "X13",
+            X14 => "X14",
+            X15 => "X15",
+            X16 => "X16",
+            X17 => "X17",
+            X18 => "X18",
+            X19 => "X19",
+            X20 => "X20",
+            X21 => "X21",
+            X22 => "X22",
+            X23 => "X23",
+            X24 => "X24",
+            X25 => "X25",
+            X26 => "X26",
+            X27 => "X27",
+            X28 => "X28",
+            X29 => "FP",
+            X30 => "LR",
+            SP => "SP",
+            PC => "PC",
+            _ => return None
+        };
+
+        Some( name )
+    }
+
+    #[inline]
+    fn get_stack_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::SP )
+    }
+
+    #[inline]
+    fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::PC )
+    }
+
+    #[inline]
+    fn initial_state() -> Self::State {
+        ContextCache::new()
+    }
+
+    #[inline]
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        let kind = classify_frame( state, current_frame, || {
+            if is_signal_frame( memory, current_frame ) { FrameKind::Signal } else { FrameKind::Regular }
+        });
+
+        if kind == FrameKind::Signal {
+            return unwind_signal_frame( nth_frame, memory, current_frame, next_frame );
+        }
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::PC |
+                dwarf::X30 |
+                dwarf::SP => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+            if panic_on_partial_backtrace {
+                panic!( "Partial backtrace!" );
+            }
+            return false;
+        }
+
+        let sp = current_frame.cfa.unwrap();
+        next_frame.regs.append( dwarf::SP, sp );
+        debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Self::register_name( dwarf::SP ), nth_frame + 1, sp );
+
+        // A leaf function doesn't have to save the LR anywhere
+        // so on the first frame it can still be in the register.
+        if let Some( return_address ) = next_frame.regs.get( dwarf::X30 ) {
+            next_frame.regs.append( dwarf::PC, return_address );
+            true
+        } else if let (0, Some( return_address )) = (nth_frame, current_frame.regs.get( dwarf::X30 )) {
+            next_frame.regs.append( dwarf::PC, return_address );
+            true
+        } else {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            current_frame.mark_end_of_stack();
+            false
+        }
+    }
+
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        // The AAPCS64 frame record is `stp x29, x30, [sp, #-N]!; mov x29, sp`, so:
+        //   previous.FP == *(current.FP)
+        //   previous.PC == *(current.FP + 8)
+        //   previous.SP >= current.FP + 16
+        let fp = match current_frame.regs.get( dwarf::X29 ) {
+            Some( fp ) => fp,
+            None => {
+                debug!( "Previous frame not found: FP is unknown for frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        let sp = current_frame.regs.get( dwarf::SP ).unwrap_or( 0 );
+        if fp < sp || !memory.is_stack_address( fp ) {
+            debug!( "Previous frame not found: FP=0x{:016X} of frame #{} doesn't point to the stack", fp, nth_frame );
+            return false;
+        }
+
+        let previous_fp = memory.get_u64_at_address( Self::ENDIANNESS, fp );
+        let return_address = memory.get_u64_at_address( Self::ENDIANNESS, fp + 8 );
+        let (previous_fp, return_address) = match (previous_fp, return_address) {
+            (Some( previous_fp ), Some( return_address )) if return_address != 0 => (previous_fp, return_address),
+            _ => {
+                debug!( "Previous frame not found: failed to read the frame record of frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::PC |
+                dwarf::X29 |
+                dwarf::X30 |
+                dwarf::SP => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        current_frame.cfa = Some( fp + 16 );
+        next_frame.regs.append( dwarf::X29, previous_fp );
+        next_frame.regs.append( dwarf::X30, return_address );
+        next_frame.regs.append( dwarf::SP, fp + 16 );
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    }
+
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        if !is_after_call_instruction( memory, value ) {
+            return false;
+        }
+
+        next_frame.regs.append( dwarf::SP, stack_slot + 8 );
+        next_frame.regs.append( dwarf::PC, value );
+        true
+    }
+}
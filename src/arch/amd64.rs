@@ -3,10 +3,10 @@ use std::mem;
 use gimli::{RegisterRule, CfaRule, LittleEndian};
 
 use dwarf_regs::DwarfRegs;
-use arch::Architecture;
+use arch::{Architecture, classify_frame};
 use address_space::MemoryReader;
 use unwind_context::UnwindFrame;
-use frame_descriptions::ContextCache;
+use frame_descriptions::{ContextCache, FrameKind};
 use archive::{Endianness, Bitness};
 use dwarf::dwarf_unwind;
 
@@ -37,55 +37,197 @@ pub mod dwarf {
 #[allow(dead_code)]
 pub struct Arch {}
 
-fn guess_ebp< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, ctx_cache: &mut ContextCache< LittleEndian >, current_frame: &mut UnwindFrame< Arch > ) -> Option< u64 > {
+// The offsets of the registers within `struct sigcontext` from the kernel's `asm/sigcontext.h`.
+const SIGCONTEXT_REGS: &[(u16, u64)] = &[
+    (dwarf::R8, 0),
+    (dwarf::R9, 8),
+    (dwarf::R10, 16),
+    (dwarf::R11, 24),
+    (dwarf::R12, 32),
+    (dwarf::R13, 40),
+    (dwarf::R14, 48),
+    (dwarf::R15, 56),
+    (dwarf::RDI, 64),
+    (dwarf::RSI, 72),
+    (dwarf::RBP, 80),
+    (dwarf::RBX, 88),
+    (dwarf::RDX, 96),
+    (dwarf::RAX, 104),
+    (dwarf::RCX, 112),
+    (dwarf::RSP, 120),
+    (dwarf::RETURN_ADDRESS, 128)
+];
+
+// The offset of `uc_mcontext` within `struct ucontext`.
+const UCONTEXT_MCONTEXT_OFFSET: u64 = 40;
+
+// mov $0xf, %rax; syscall
+const RT_SIGRETURN_CODE_HEAD: u64 = 0x0f00_0000_0fc0_c748;
+const RT_SIGRETURN_CODE_TAIL: u32 = 0x050f_0000;
+const RT_SIGRETURN_SYSCALL_OFFSET: u64 = 7;
+
+fn is_rt_sigreturn< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    memory.get_u64_at_address( Endianness::LittleEndian, address ) == Some( RT_SIGRETURN_CODE_HEAD ) &&
+    memory.get_u32_at_address( Endianness::LittleEndian, address + 5 ) == Some( RT_SIGRETURN_CODE_TAIL )
+}
+
+fn is_signal_frame< M: MemoryReader< Arch > >( memory: &M, current_frame: &UnwindFrame< Arch > ) -> bool {
+    let address = match current_frame.regs.get( dwarf::RETURN_ADDRESS ) {
+        Some( address ) => address,
+        None => return false
+    };
+
+    if let Some( ref binary ) = current_frame.binary {
+        if binary.is_signal_trampoline( address ) {
+            return true;
+        }
+
+        if let Some( (_, "__restore_rt") ) = binary.lookup_absolute_symbol( address ) {
+            return true;
+        }
+    }
+
+    is_rt_sigreturn( memory, address ) ||
+    is_rt_sigreturn( memory, address.wrapping_sub( RT_SIGRETURN_SYSCALL_OFFSET ) )
+}
+
+fn unwind_signal_frame< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    // When the signal handler returns to `__restore_rt` the RSP
+    // points to the `struct ucontext` saved by the kernel.
+    let rsp = match current_frame.regs.get( dwarf::RSP ) {
+        Some( rsp ) => rsp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let mcontext = rsp + UCONTEXT_MCONTEXT_OFFSET;
+    for &(register, offset) in SIGCONTEXT_REGS {
+        if let Some( value ) = memory.get_u64_at_address( Endianness::LittleEndian, mcontext + offset ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value );
+        }
+    }
+
+    current_frame.cfa = Some( mcontext );
+    if next_frame.regs.get( dwarf::RSP ).is_none() || next_frame.regs.get( dwarf::RETURN_ADDRESS ).is_none() {
+        debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+        return false;
+    }
+
+    true
+}
+
+// This is shared with the 32-bit x86, which has the very same problem with its EBP.
+pub fn guess_ebp< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, ctx_cache: &mut ContextCache< A::Endianity >, current_frame: &mut UnwindFrame< A >, ebp_register: u16, esp_register: u16, ra_register: u16 ) -> Option< u64 > {
     // This is a hacky workaround for the fact that Linux's perf events tend to return us
     // invalid RBP values (all FFs) if the call chain goes through the kernel space -> user space
     // boundary, so we try to figure it out some other way.
 
-    debug!( "Trying to guess RBP for frame #{}...", nth_frame );
+    debug!( "Trying to guess {:?} for frame #{}...", A::register_name( ebp_register ), nth_frame );
 
-    let rip = current_frame.regs.get( dwarf::RETURN_ADDRESS )?;
+    let rip = current_frame.regs.get( ra_register )?;
     let binary = current_frame.binary.as_ref()?.clone();
     let unwind_info = binary.lookup_unwind_row( ctx_cache, rip )?;
 
     let cfa_offset = match unwind_info.cfa() {
-        &CfaRule::RegisterAndOffset { register: cfa_register, offset: cfa_offset } if cfa_register as u16 == dwarf::RBP => cfa_offset,
+        &CfaRule::RegisterAndOffset { register: cfa_register, offset: cfa_offset } if cfa_register as u16 == ebp_register => cfa_offset,
         _ => return None
     };
 
     // What this rule means is that:
     //   previous.RBP == *(current.RBP + rbp_offset)
-    let rbp_offset = match unwind_info.register( dwarf::RBP as _ ) {
+    let rbp_offset = match unwind_info.register( ebp_register as _ ) {
         RegisterRule::Offset( offset ) => offset + cfa_offset,
         _ => return None
     };
 
-    let ra_offset = match unwind_info.register( dwarf::RETURN_ADDRESS as _ ) {
+    let ra_offset = match unwind_info.register( ra_register as _ ) {
         RegisterRule::Offset( offset ) => offset + cfa_offset,
         _ => return None
     };
 
     mem::drop( unwind_info );
 
-    let rsp = current_frame.regs.get( dwarf::RSP )?;
+    let rsp = current_frame.regs.get( esp_register )?;
+    let word_size = match A::BITNESS {
+        Bitness::B32 => 4,
+        Bitness::B64 => 8
+    };
 
     let mut rbp = rsp;
     for _ in 0..32 {
-        let candidate_ra = memory.get_u64_at_address( Endianness::LittleEndian, (rbp as i64 + ra_offset) as u64 )?;
-        let candidate_rbp = memory.get_u64_at_address( Endianness::LittleEndian, (rbp as i64 + rbp_offset) as u64 )?;
+        let candidate_ra = memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, (rbp as i64 + ra_offset) as u64 )?;
+        let candidate_rbp = memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, (rbp as i64 + rbp_offset) as u64 )?;
 
         let valid_ra = memory.get_region_at_address( candidate_ra ).map( |region| region.is_executable() ).unwrap_or( false );
         let valid_rbp = memory.is_stack_address( candidate_rbp );
         if valid_rbp && valid_ra {
-            debug!( "Guessed RBP=0x{:016X} based on stack scanning", rbp );
+            debug!( "Guessed {:?}=0x{:016X} based on stack scanning", A::register_name( ebp_register ), rbp );
             return Some( rbp );
         }
-        rbp += 8;
+        rbp += word_size;
     }
 
     None
 }
 
+// Checks whether the instruction right before `address` is a `call`;
+// this is shared with the 32-bit x86 since the encoding is the same.
+pub fn is_after_call_instruction< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> bool {
+    let mut code = [ 0; 8 ];
+    match (memory.get_u32_at_address( Endianness::LittleEndian, address.wrapping_sub( 8 ) ), memory.get_u32_at_address( Endianness::LittleEndian, address.wrapping_sub( 4 ) )) {
+        (Some( head ), Some( tail )) => {
+            for index in 0..4 {
+                code[ index ] = (head >> (index * 8)) as u8;
+                code[ index + 4 ] = (tail >> (index * 8)) as u8;
+            }
+        },
+        _ => return false
+    }
+
+    // call rel32
+    if code[ 3 ] == 0xE8 {
+        return true;
+    }
+
+    // call r/m, which is `FF /2` followed by an optional SIB byte and a displacement.
+    for length in 2..8 {
+        let offset = 8 - length;
+        if code[ offset ] != 0xFF || (code[ offset + 1 ] >> 3) & 7 != 2 {
+            continue;
+        }
+
+        let modrm = code[ offset + 1 ];
+        let mode = modrm >> 6;
+        let rm = modrm & 7;
+        let mut expected_length = 2;
+        if mode != 3 && rm == 4 {
+            if length < 3 {
+                continue;
+            }
+
+            expected_length += 1;
+            if mode == 0 && code[ offset + 2 ] & 7 == 5 {
+                expected_length += 4;
+            }
+        }
+
+        match mode {
+            0 if rm == 5 => expected_length += 4,
+            1 => expected_length += 1,
+            2 => expected_length += 4,
+            _ => {}
+        }
+
+        if expected_length == length {
+            return true;
+        }
+    }
+
+    false
+}
+
 impl Architecture for Arch {
     const NAME: &'static str = "amd64";
     const ENDIANNESS: Endianness = Endianness::LittleEndian;
@@ -141,8 +283,16 @@ impl Architecture for Arch {
 
     #[inline]
     fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        let kind = classify_frame( state, current_frame, || {
+            if is_signal_frame( memory, current_frame ) { FrameKind::Signal } else { FrameKind::Regular }
+        });
+
+        if kind == FrameKind::Signal {
+            return unwind_signal_frame( nth_frame, memory, current_frame, next_frame );
+        }
+
         if current_frame.regs.get( dwarf::RBP ).is_none() {
-            if let Some( rbp ) = guess_ebp( nth_frame, memory, state, current_frame ) {
+            if let Some( rbp ) = guess_ebp( nth_frame, memory, state, current_frame, dwarf::RBP, dwarf::RSP, dwarf::RETURN_ADDRESS ) {
                 current_frame.regs.append( dwarf::RBP, rbp );
             }
         }
@@ -170,7 +320,64 @@ impl Architecture for Arch {
             true
         } else {
             debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            current_frame.mark_end_of_stack();
             false
         }
     }
+
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        // With frame pointers every function starts with `push rbp; mov rbp, rsp`, so:
+        //   previous.RBP == *(current.RBP)
+        //   previous.RIP == *(current.RBP + 8)
+        //   previous.RSP == current.RBP + 16
+        let rbp = match current_frame.regs.get( dwarf::RBP ) {
+            Some( rbp ) => rbp,
+            None => {
+                debug!( "Previous frame not found: RBP is unknown for frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        let rsp = current_frame.regs.get( dwarf::RSP ).unwrap_or( 0 );
+        if rbp < rsp || !memory.is_stack_address( rbp ) {
+            debug!( "Previous frame not found: RBP=0x{:016X} of frame #{} doesn't point to the stack", rbp, nth_frame );
+            return false;
+        }
+
+        let previous_rbp = memory.get_u64_at_address( Self::ENDIANNESS, rbp );
+        let return_address = memory.get_u64_at_address( Self::ENDIANNESS, rbp + 8 );
+        let (previous_rbp, return_address) = match (previous_rbp, return_address) {
+            (Some( previous_rbp ), Some( return_address )) if return_address != 0 => (previous_rbp, return_address),
+            _ => {
+                debug!( "Previous frame not found: failed to read the frame record of frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::RBP |
+                dwarf::RSP |
+                dwarf::RETURN_ADDRESS => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        current_frame.cfa = Some( rbp + 16 );
+        next_frame.regs.append( dwarf::RBP, previous_rbp );
+        next_frame.regs.append( dwarf::RSP, rbp + 16 );
+        next_frame.regs.append( dwarf::RETURN_ADDRESS, return_address );
+        true
+    }
+
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        if !is_after_call_instruction( memory, value ) {
+            return false;
+        }
+
+        // The `call` pushes the return address, so the caller's RSP is right above it.
+        next_frame.regs.append( dwarf::RSP, stack_slot + 8 );
+        next_frame.regs.append( dwarf::RETURN_ADDRESS, value );
+        true
+    }
 }
@@ -1,11 +1,13 @@
 use gimli::LittleEndian;
 use dwarf_regs::DwarfRegs;
-use arch::Architecture;
+use arch::{Architecture, classify_frame};
 use address_space::MemoryReader;
-use unwind_context::UnwindFrame;
+use unwind_context::{UnwindFrame, StopReason};
+use frame_descriptions::{ContextCache, FrameKind};
 use archive::{Endianness, Bitness};
 use arm_extab::VirtualMachine as EhVm;
 use arm_extab::Error as EhError;
+use dwarf::dwarf_unwind;
 
 // Source: DWARF for the ARM Architecture
 //         http://infocenter.arm.com/help/topic/com.arm.doc.ihi0040b/IHI0040B_aadwarf.pdf
@@ -31,13 +33,224 @@ pub mod dwarf {
 #[allow(dead_code)]
 pub struct Arch {}
 
+// mov r7, #__NR_sigreturn; swi 0
+const SIGRETURN_CODE: u32 = 0xe3a0_7077;
+// mov r7, #__NR_rt_sigreturn; swi 0
+const RT_SIGRETURN_CODE: u32 = 0xe3a0_70ad;
+const SWI_CODE: u32 = 0xef00_0000;
+
+// The offsets of `arm_r0` from the stack pointer at the point
+// when the signal handler returns, based on the kernel's `struct sigframe`
+// and `struct rt_sigframe` from `arch/arm/kernel/signal.c`.
+const SIGFRAME_REGS_OFFSET: u64 = 32;
+const RT_SIGFRAME_REGS_OFFSET: u64 = 160;
+
+fn get_sigreturn_kind< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> Option< u32 > {
+    let code = memory.get_u32_at_address( Arch::ENDIANNESS, address )?;
+    if code != SIGRETURN_CODE && code != RT_SIGRETURN_CODE {
+        return None;
+    }
+
+    if memory.get_u32_at_address( Arch::ENDIANNESS, address + 4 )? != SWI_CODE {
+        return None;
+    }
+
+    Some( code )
+}
+
+fn get_signal_frame_regs_offset< M: MemoryReader< Arch > >( memory: &M, current_frame: &UnwindFrame< Arch > ) -> Option< u64 > {
+    let address = current_frame.regs.get( dwarf::R15 )?;
+
+    if let Some( ref binary ) = current_frame.binary {
+        match binary.lookup_absolute_symbol( address ) {
+            Some( (_, "__default_rt_sa_restorer") ) => return Some( RT_SIGFRAME_REGS_OFFSET ),
+            Some( (_, "__default_sa_restorer") ) => return Some( SIGFRAME_REGS_OFFSET ),
+            _ => {}
+        }
+    }
+
+    let code = get_sigreturn_kind( memory, address ).or_else( || get_sigreturn_kind( memory, address.wrapping_sub( 4 ) ) )?;
+    if code == RT_SIGRETURN_CODE {
+        Some( RT_SIGFRAME_REGS_OFFSET )
+    } else {
+        Some( SIGFRAME_REGS_OFFSET )
+    }
+}
+
+fn unwind_signal_frame< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, regs_offset: u64, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    let sp = match current_frame.regs.get( dwarf::R13 ) {
+        Some( sp ) => sp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let base = sp + regs_offset;
+    for register in dwarf::R0..dwarf::R15 + 1 {
+        if let Some( value ) = memory.get_u32_at_address( Arch::ENDIANNESS, base + register as u64 * 4 ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", Arch::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value as u64 );
+        }
+    }
+
+    if next_frame.regs.get( dwarf::R13 ).is_none() || next_frame.regs.get( dwarf::R15 ).is_none() {
+        debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+        return false;
+    }
+
+    true
+}
+
+fn unwind_with_exidx< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, is_first_frame: bool, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> Result< (), EhError > {
+    let mut vm = EhVm::new();
+
+    let binary = current_frame.binary.as_ref().unwrap();
+
+    let exidx_range = match binary.arm_exidx_range() {
+        Some( exidx_range ) => exidx_range,
+        None => {
+            debug!( "Binary '{}' is missing .ARM.exidx section", binary.name() );
+            return Err( EhError::UnwindInfoMissing );
+        }
+    };
+
+    let exidx_base = match binary.arm_exidx_address() {
+        Some( exidx_address ) => exidx_address,
+        None => {
+            debug!( "Binary '{}' .ARM.exidx address is not known", binary.name() );
+            return Err( EhError::UnwindInfoMissing );
+        }
+    };
+
+    let extab_base = match binary.arm_extab_address() {
+        Some( extab_address ) => extab_address,
+        None => {
+            if binary.arm_extab_range().is_none() {
+                0
+            } else {
+                debug!( "Binary '{}' .ARM.extab address is not known", binary.name() );
+                return Err( EhError::UnwindInfoMissing );
+            }
+        }
+    };
+
+    let address = current_frame.regs.get( dwarf::R15 ).unwrap() as u32;
+    let exidx = &binary.as_bytes()[ exidx_range ];
+    let extab = if let Some( extab_range ) = binary.arm_extab_range() {
+        &binary.as_bytes()[ extab_range ]
+    } else {
+        b""
+    };
+
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::R15 |
+            dwarf::R13 => continue,
+            _ => next_frame.regs.append( register ,value )
+        }
+    }
+
+    let mut initial_address = None;
+    let result = vm.unwind(
+        memory,
+        &current_frame.regs,
+        &mut initial_address,
+        &mut next_frame.regs,
+        exidx,
+        extab,
+        exidx_base as u32,
+        extab_base as u32,
+        address,
+        is_first_frame
+    );
+
+    if let Some( initial_address ) = initial_address {
+        debug!( "Initial address for frame #{}: 0x{:08X}", nth_frame, initial_address );
+        current_frame.initial_address = Some( initial_address as u64 );
+    }
+
+    result
+}
+
+fn unwind_with_dwarf< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, state: &mut ContextCache< LittleEndian >, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::R15 |
+            dwarf::R14 |
+            dwarf::R13 => continue,
+            _ => next_frame.regs.append( register, value )
+        }
+    }
+
+    if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+        return false;
+    }
+
+    let sp = current_frame.cfa.unwrap();
+    next_frame.regs.append( dwarf::R13, sp );
+    debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", Arch::register_name( dwarf::R13 ), nth_frame + 1, sp );
+
+    // A leaf function doesn't have to save the LR anywhere
+    // so on the first frame it can still be in the register.
+    let return_address = match (next_frame.regs.get( dwarf::R14 ), nth_frame) {
+        (Some( return_address ), _) => return_address,
+        (None, 0) => match current_frame.regs.get( dwarf::R14 ) {
+            Some( return_address ) => {
+                next_frame.regs.append( dwarf::R14, return_address );
+                return_address
+            },
+            None => return false
+        },
+        (None, _) => {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            current_frame.mark_end_of_stack();
+            return false;
+        }
+    };
+
+    // The lowest bit only marks whether the caller is in Thumb mode.
+    next_frame.regs.append( dwarf::R15, return_address & !1 );
+    true
+}
+
+fn is_after_call_instruction< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    if address & 1 != 0 {
+        // The return address points to Thumb code.
+        let code = match memory.get_u32_at_address( Endianness::LittleEndian, (address & !1).wrapping_sub( 4 ) ) {
+            Some( code ) => code,
+            None => return false
+        };
+
+        let first = code as u16;
+        let second = (code >> 16) as u16;
+
+        // blx <register>
+        if second & 0xFF87 == 0x4780 {
+            return true;
+        }
+
+        // bl <label> and blx <label>
+        first & 0xF800 == 0xF000 && (second & 0xD000 == 0xD000 || second & 0xD000 == 0xC000)
+    } else {
+        match memory.get_u32_at_address( Endianness::LittleEndian, address.wrapping_sub( 4 ) ) {
+            // blx <label>
+            Some( opcode ) if opcode & 0xFE00_0000 == 0xFA00_0000 => true,
+            // bl<cond> <label>
+            Some( opcode ) if opcode & 0x0F00_0000 == 0x0B00_0000 && opcode >> 28 != 0xF => true,
+            // blx<cond> <register>
+            Some( opcode ) if opcode & 0x0FFF_FFF0 == 0x012F_FF30 => true,
+            _ => false
+        }
+    }
+}
+
 impl Architecture for Arch {
     const NAME: &'static str = "arm";
     const ENDIANNESS: Endianness = Endianness::LittleEndian;
     const BITNESS: Bitness = Bitness::B32;
 
     type Endianity = LittleEndian;
-    type State = ();
+    type State = ContextCache< LittleEndian >;
 
     fn register_name_str( register: u16 ) -> Option< &'static str > {
         use self::dwarf::*;
@@ -77,91 +290,142 @@ impl Architecture for Arch {
 
     #[inline]
     fn initial_state() -> Self::State {
-        ()
+        ContextCache::new()
     }
 
     #[inline]
-    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, _state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
-        let mut vm = EhVm::new();
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        let kind = classify_frame( state, current_frame, || {
+            get_signal_frame_regs_offset( memory, current_frame ).map( FrameKind::SignalAt ).unwrap_or( FrameKind::Regular )
+        });
 
-        let binary = current_frame.binary.as_ref().unwrap();
+        if let FrameKind::SignalAt( regs_offset ) = kind {
+            return unwind_signal_frame( nth_frame, memory, regs_offset, current_frame, next_frame );
+        }
 
-        let exidx_range = match binary.arm_exidx_range() {
-            Some( exidx_range ) => exidx_range,
-            None => {
-                debug!( "Previous frame not found: binary '{}' is missing .ARM.exidx section", binary.name() );
-                return false
-            }
-        };
+        // If there's DWARF info then we don't want the exidx unwinder to guess on the first frame.
+        let address = current_frame.regs.get( dwarf::R15 ).unwrap();
+        let is_first_frame = nth_frame == 0 && !current_frame.binary.as_ref().unwrap().has_unwind_info( address );
 
-        let exidx_base = match binary.arm_exidx_address() {
-            Some( exidx_address ) => exidx_address,
-            None => {
-                debug!( "Previous frame not found: binary '{}' .ARM.exidx address is not known", binary.name() );
-                return false
-            }
+        let result = unwind_with_exidx( nth_frame, memory, is_first_frame, current_frame, next_frame );
+        let error = match result {
+            Ok( () ) => return true,
+            Err( EhError::EndOfStack ) => {
+                debug!( "Previous frame not found: EndOfStack" );
+                current_frame.stop_reason = Some( StopReason::EndOfStack );
+                return false;
+            },
+            Err( error ) => error
         };
 
-        let extab_base = match binary.arm_extab_address() {
-            Some( extab_address ) => extab_address,
-            None => {
-                if binary.arm_extab_range().is_none() {
-                    0
+        // The `.ARM.exidx` is preferred, but if it doesn't cover the current
+        // function we fall back to the `.debug_frame`/`.eh_frame`, if any.
+        let error = match error {
+            EhError::UnwindInfoMissing |
+            EhError::CantUnwind => {
+                debug!( "Trying DWARF based unwinding for frame #{}", nth_frame );
+                next_frame.clear();
+                if unwind_with_dwarf( nth_frame, memory, state, current_frame, next_frame ) {
+                    return true;
+                }
+
+                if current_frame.stop_reason == Some( StopReason::MissingUnwindInfo ) {
+                    current_frame.stop_reason = None;
+                    error
                 } else {
-                    debug!( "Previous frame not found: binary '{}' .ARM.extab address is not known", binary.name() );
-                    return false
+                    EhError::UnwindingFailed
+                }
+            },
+            error => error
+        };
+
+        let error = match error {
+            EhError::CantUnwind => {
+                debug!( "Previous frame not found: EXIDX_CANTUNWIND" );
+                current_frame.stop_reason = Some( StopReason::CantUnwind );
+                return false;
+            },
+            EhError::UnwindInfoMissing => {
+                current_frame.stop_reason = Some( StopReason::MissingUnwindInfo );
+                EhError::UnwindInfoMissing
+            },
+            EhError::MemoryUnaccessible { address } => {
+                let stack_pointer = current_frame.regs.get( dwarf::R13 ).unwrap_or( 0 );
+                if address as u64 >= stack_pointer && !memory.is_stack_address( address as u64 ) {
+                    current_frame.stop_reason = Some( StopReason::StackExhausted );
                 }
+
+                EhError::MemoryUnaccessible { address }
+            },
+            EhError::UnsupportedPersonality( personality ) => {
+                current_frame.stop_reason = Some( StopReason::UnsupportedRule );
+                EhError::UnsupportedPersonality( personality )
+            },
+            error => error
+        };
+
+        debug!( "Previous frame not found: {:?}", error );
+        if panic_on_partial_backtrace {
+            panic!( "Partial backtrace!" );
+        }
+
+        false
+    }
+
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        // GCC's prologue is `push {fp, lr}; add fp, sp, #4`, so:
+        //   previous.LR == *(current.FP)
+        //   previous.FP == *(current.FP - 4)
+        //   previous.SP == current.FP + 4
+        let fp = match current_frame.regs.get( dwarf::R11 ) {
+            Some( fp ) => fp,
+            None => {
+                debug!( "Previous frame not found: FP is unknown for frame #{}", nth_frame );
+                return false;
             }
         };
 
-        let address = current_frame.regs.get( dwarf::R15 ).unwrap() as u32;
-        let exidx = &binary.as_bytes()[ exidx_range ];
-        let extab = if let Some( extab_range ) = binary.arm_extab_range() {
-            &binary.as_bytes()[ extab_range ]
-        } else {
-            b""
+        let sp = current_frame.regs.get( dwarf::R13 ).unwrap_or( 0 );
+        if fp < sp + 4 || !memory.is_stack_address( fp - 4 ) {
+            debug!( "Previous frame not found: FP=0x{:08X} of frame #{} doesn't point to the stack", fp, nth_frame );
+            return false;
+        }
+
+        let return_address = memory.get_u32_at_address( Self::ENDIANNESS, fp );
+        let previous_fp = memory.get_u32_at_address( Self::ENDIANNESS, fp - 4 );
+        let (previous_fp, return_address) = match (previous_fp, return_address) {
+            (Some( previous_fp ), Some( return_address )) if return_address != 0 => (previous_fp as u64, return_address as u64),
+            _ => {
+                debug!( "Previous frame not found: failed to read the frame record of frame #{}", nth_frame );
+                return false;
+            }
         };
 
         for (register, value) in current_frame.regs.iter() {
             match register {
-                dwarf::R15 |
-                dwarf::R13 => continue,
-                _ => next_frame.regs.append( register ,value )
+                dwarf::R11 |
+                dwarf::R13 |
+                dwarf::R14 |
+                dwarf::R15 => continue,
+                _ => next_frame.regs.append( register, value )
             }
         }
 
-        let mut initial_address = None;
-        let result = vm.unwind(
-            memory,
-            &current_frame.regs,
-            &mut initial_address,
-            &mut next_frame.regs,
-            exidx,
-            extab,
-            exidx_base as u32,
-            extab_base as u32,
-            address,
-            nth_frame == 0
-        );
-
-        if let Some( initial_address ) = initial_address {
-            debug!( "Initial address for frame #{}: 0x{:08X}", nth_frame, initial_address );
-            current_frame.initial_address = Some( initial_address as u64 );
-        }
+        current_frame.cfa = Some( fp + 4 );
+        next_frame.regs.append( dwarf::R11, previous_fp );
+        next_frame.regs.append( dwarf::R13, fp + 4 );
+        next_frame.regs.append( dwarf::R14, return_address );
+        next_frame.regs.append( dwarf::R15, return_address );
+        true
+    }
 
-        match result {
-            Ok( () ) => return true,
-            Err( EhError::EndOfStack ) => {
-                debug!( "Previous frame not found: EndOfStack" );
-                return false;
-            },
-            Err( error ) => {
-                debug!( "Previous frame not found: {:?}", error );
-                if panic_on_partial_backtrace {
-                    panic!( "Partial backtrace!" );
-                }
-                return false;
-            }
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        if !is_after_call_instruction( memory, value ) {
+            return false;
         }
+
+        next_frame.regs.append( dwarf::R13, stack_slot + 4 );
+        next_frame.regs.append( dwarf::R15, value & !1 );
+        true
     }
 }
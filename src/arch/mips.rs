@@ -0,0 +1,418 @@
+use gimli::BigEndian;
+use dwarf_regs::DwarfRegs;
+use arch::{Architecture, classify_frame};
+use address_space::MemoryReader;
+use unwind_context::UnwindFrame;
+use frame_descriptions::{ContextCache, FrameKind};
+use archive::{Endianness, Bitness};
+use dwarf::dwarf_unwind;
+
+// The o32 ABI uses the same register numbering as MIPS64; this module
+// also contains the unwinding code shared with the little endian `mipsel`.
+pub mod dwarf {
+    pub const R0: u16 = 0;
+    pub const R1: u16 = 1;
+    pub const R2: u16 = 2;
+    pub const R3: u16 = 3;
+    pub const R4: u16 = 4;
+    pub const R5: u16 = 5;
+    pub const R6: u16 = 6;
+    pub const R7: u16 = 7;
+    pub const R8: u16 = 8;
+    pub const R9: u16 = 9;
+    pub const R10: u16 = 10;
+    pub const R11: u16 = 11;
+    pub const R12: u16 = 12;
+    pub const R13: u16 = 13;
+    pub const R14: u16 = 14;
+    pub const R15: u16 = 15;
+    pub const R16: u16 = 16;
+    pub const R17: u16 = 17;
+    pub const R18: u16 = 18;
+    pub const R19: u16 = 19;
+    pub const R20: u16 = 20;
+    pub const R21: u16 = 21;
+    pub const R22: u16 = 22;
+    pub const R23: u16 = 23;
+    pub const R24: u16 = 24;
+    pub const R25: u16 = 25;
+    pub const R26: u16 = 26;
+    pub const R27: u16 = 27;
+    pub const R28: u16 = 28;
+    pub const R29: u16 = 29;
+    pub const R30: u16 = 30;
+    pub const R31: u16 = 31;
+
+    pub const PC: u16 = 34;
+}
+
+#[allow(dead_code)]
+pub struct Arch {}
+
+const MAX_PROLOGUE_SCAN_LENGTH: u64 = 4096;
+
+// li $v0, __NR_sigreturn; syscall
+const SIGRETURN_CODE: u32 = 0x2402_1017;
+// li $v0, __NR_rt_sigreturn; syscall
+const RT_SIGRETURN_CODE: u32 = 0x2402_1061;
+const SYSCALL_CODE: u32 = 0x0000_000c;
+
+// The offsets of `sc_pc` from the stack pointer at the point when the signal
+// handler returns, based on the kernel's `struct sigframe` and `struct rt_sigframe`
+// from `arch/mips/kernel/signal.c`. The 32-bit registers are stored as 64-bit
+// values in `sc_regs` which directly follows `sc_pc`.
+const SIGFRAME_PC_OFFSET: u64 = 32;
+const RT_SIGFRAME_PC_OFFSET: u64 = 184;
+
+fn get_sigreturn_kind< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> Option< u32 > {
+    let code = memory.get_u32_at_address( A::ENDIANNESS, address )?;
+    if code != SIGRETURN_CODE && code != RT_SIGRETURN_CODE {
+        return None;
+    }
+
+    if memory.get_u32_at_address( A::ENDIANNESS, address + 4 )? != SYSCALL_CODE {
+        return None;
+    }
+
+    Some( code )
+}
+
+fn get_signal_frame_pc_offset< A: Architecture, M: MemoryReader< A > >( memory: &M, current_frame: &UnwindFrame< A > ) -> Option< u64 > {
+    let address = current_frame.regs.get( dwarf::PC )?;
+
+    if let Some( ref binary ) = current_frame.binary {
+        match binary.lookup_absolute_symbol( address ) {
+            Some( (_, "__vdso_rt_sigreturn") ) => return Some( RT_SIGFRAME_PC_OFFSET ),
+            Some( (_, "__vdso_sigreturn") ) => return Some( SIGFRAME_PC_OFFSET ),
+            _ => {}
+        }
+    }
+
+    let code = get_sigreturn_kind( memory, address ).or_else( || get_sigreturn_kind( memory, address.wrapping_sub( 4 ) ) )?;
+    if code == RT_SIGRETURN_CODE {
+        Some( RT_SIGFRAME_PC_OFFSET )
+    } else {
+        Some( SIGFRAME_PC_OFFSET )
+    }
+}
+
+fn unwind_signal_frame< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, pc_offset: u64, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
+    let sp = match current_frame.regs.get( dwarf::R29 ) {
+        Some( sp ) => sp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let base = sp + pc_offset + 8;
+    for register in dwarf::R0..dwarf::R31 + 1 {
+        if let Some( value ) = memory.get_u64_at_address( A::ENDIANNESS, base + register as u64 * 8 ) {
+            let value = value as u32 as u64;
+            debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", A::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value );
+        }
+    }
+
+    let pc = match memory.get_u64_at_address( A::ENDIANNESS, sp + pc_offset ) {
+        Some( pc ) => pc as u32 as u64,
+        None => {
+            debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+            return false;
+        }
+    };
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", A::register_name( dwarf::PC ), nth_frame + 1, pc );
+    next_frame.regs.append( dwarf::PC, pc );
+    current_frame.cfa = Some( sp );
+    next_frame.regs.get( dwarf::R29 ).is_some()
+}
+
+struct Prologue {
+    frame_size: u64,
+    ra_offset: Option< u64 >,
+    fp_offset: Option< u64 >,
+    sets_fp: bool
+}
+
+fn is_sp_adjustment( instruction: u32 ) -> bool {
+    // addiu $sp, $sp, -imm
+    (instruction >> 16) == 0x27bd && (instruction & 0x8000) != 0
+}
+
+// Just as on MIPS64 there is no fixed frame layout, so we look for the function's
+// prologue and figure out where it has saved the RA and FP registers.
+fn analyze_prologue< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> Option< Prologue > {
+    let mut start = address;
+    loop {
+        if address - start >= MAX_PROLOGUE_SCAN_LENGTH {
+            return None;
+        }
+
+        let instruction = memory.get_u32_at_address( A::ENDIANNESS, start )?;
+        if is_sp_adjustment( instruction ) {
+            break;
+        }
+
+        // jr $ra; we've hit the end of the previous function.
+        if instruction == 0x03e00008 && start != address {
+            return None;
+        }
+
+        start = start.checked_sub( 4 )?;
+    }
+
+    let instruction = memory.get_u32_at_address( A::ENDIANNESS, start )?;
+    let mut prologue = Prologue {
+        frame_size: (-((instruction & 0xffff) as i16 as i64)) as u64,
+        ra_offset: None,
+        fp_offset: None,
+        sets_fp: false
+    };
+
+    let mut current = start + 4;
+    while current < address {
+        let instruction = memory.get_u32_at_address( A::ENDIANNESS, current )?;
+        let offset = (instruction & 0xffff) as i16 as u64;
+        match instruction >> 16 {
+            // sw $ra, offset($sp)
+            0xafbf => prologue.ra_offset = Some( offset ),
+            // sw $fp, offset($sp)
+            0xafbe => prologue.fp_offset = Some( offset ),
+            _ => {}
+        }
+
+        // move $fp, $sp
+        if instruction == 0x03a0f021 || instruction == 0x03a0f025 {
+            prologue.sets_fp = true;
+        }
+
+        current += 4;
+    }
+
+    Some( prologue )
+}
+
+pub fn register_name_str( register: u16 ) -> Option< &'static str > {
+    use self::dwarf::*;
+
+    let name = match register {
+        R0 => "R0",
+        R1 => "AT",
+        R2 => "V0",
+        R3 => "V1",
+        R4 => "A0",
+        R5 => "A1",
+        R6 => "A2",
+        R7 => "A3",
+        R8 => "T0",
+        R9 => "T1",
+        R10 => "T2",
+        R11 => "T3",
+        R12 => "T4",
+        R13 => "T5",
+        R14 => "T6",
+        R15 => "T7",
+        R16 => "S0",
+        R17 => "S1",
+        R18 => "S2",
+        R19 => "S3",
+        R20 => "S4",
+        R21 => "S5",
+        R22 => "S6",
+        R23 => "S7",
+        R24 => "T8",
+        R25 => "T9",
+        R26 => "K0",
+        R27 => "K1",
+        R28 => "GP",
+        R29 => "SP",
+        R30 => "FP",
+        R31 => "RA",
+        PC => "PC",
+        _ => return None
+    };
+
+    Some( name )
+}
+
+pub fn unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, state: &mut ContextCache< A::Endianity >, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A >, panic_on_partial_backtrace: bool ) -> bool {
+    let kind = classify_frame( state, current_frame, || {
+        get_signal_frame_pc_offset( memory, current_frame ).map( FrameKind::SignalAt ).unwrap_or( FrameKind::Regular )
+    });
+
+    if let FrameKind::SignalAt( pc_offset ) = kind {
+        return unwind_signal_frame( nth_frame, memory, pc_offset, current_frame, next_frame );
+    }
+
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::PC |
+            dwarf::R31 |
+            dwarf::R29 => continue,
+            _ => next_frame.regs.append( register, value )
+        }
+    }
+
+    if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+        if panic_on_partial_backtrace {
+            panic!( "Partial backtrace!" );
+        }
+        return false;
+    }
+
+    let sp = current_frame.cfa.unwrap();
+    next_frame.regs.append( dwarf::R29, sp );
+    debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", A::register_name( dwarf::R29 ), nth_frame + 1, sp );
+
+    // A leaf function doesn't have to save the RA anywhere
+    // so on the first frame it can still be in the register.
+    if let Some( return_address ) = next_frame.regs.get( dwarf::R31 ) {
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    } else if let (0, Some( return_address )) = (nth_frame, current_frame.regs.get( dwarf::R31 )) {
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    } else {
+        debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+        current_frame.mark_end_of_stack();
+        false
+    }
+}
+
+pub fn unwind_with_frame_pointer< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
+    let pc = current_frame.regs.get( dwarf::PC ).unwrap();
+    let prologue = match analyze_prologue( memory, pc ) {
+        Some( prologue ) => prologue,
+        None => {
+            debug!( "Previous frame not found: failed to find the prologue for frame #{}", nth_frame );
+            return false;
+        }
+    };
+
+    // With frame pointers the prologue does `move $fp, $sp` right after
+    // allocating the frame, so the FP points to the bottom of the frame.
+    let base = if prologue.sets_fp {
+        current_frame.regs.get( dwarf::R30 )
+    } else {
+        current_frame.regs.get( dwarf::R29 )
+    };
+
+    let base = match base {
+        Some( base ) if memory.is_stack_address( base ) => base,
+        _ => {
+            debug!( "Previous frame not found: frame #{} has no valid frame base", nth_frame );
+            return false;
+        }
+    };
+
+    let return_address = match prologue.ra_offset {
+        Some( offset ) => memory.get_u32_at_address( A::ENDIANNESS, base.wrapping_add( offset ) ).map( |value| value as u64 ),
+        None if nth_frame == 0 => current_frame.regs.get( dwarf::R31 ),
+        None => None
+    };
+
+    let previous_fp = match prologue.fp_offset {
+        Some( offset ) => memory.get_u32_at_address( A::ENDIANNESS, base.wrapping_add( offset ) ).map( |value| value as u64 ),
+        None => current_frame.regs.get( dwarf::R30 )
+    };
+
+    let return_address = match return_address {
+        Some( return_address ) if return_address != 0 => return_address,
+        _ => {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            return false;
+        }
+    };
+
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::PC |
+            dwarf::R31 |
+            dwarf::R30 |
+            dwarf::R29 => continue,
+            _ => next_frame.regs.append( register, value )
+        }
+    }
+
+    let sp = base + prologue.frame_size;
+    current_frame.cfa = Some( sp );
+    next_frame.regs.append( dwarf::R29, sp );
+    if let Some( previous_fp ) = previous_fp {
+        next_frame.regs.append( dwarf::R30, previous_fp );
+    }
+    next_frame.regs.append( dwarf::R31, return_address );
+    next_frame.regs.append( dwarf::PC, return_address );
+    true
+}
+
+// The return address points right after the delay slot of the call.
+pub fn is_after_call_instruction< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> bool {
+    let opcode = match memory.get_u32_at_address( A::ENDIANNESS, address.wrapping_sub( 8 ) ) {
+        Some( opcode ) => opcode,
+        None => return false
+    };
+
+    match opcode >> 26 {
+        // jal <label>
+        0x03 => true,
+        // jalr <register>
+        0x00 => opcode & 0x3F == 0x09,
+        // bal <label>, bltzal, bgezal and their "likely" variants
+        0x01 => (opcode >> 16) & 0x1C == 0x10,
+        _ => false
+    }
+}
+
+pub fn recover_frame_from_stack< A: Architecture, M: MemoryReader< A > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< A > ) -> bool {
+    if !is_after_call_instruction( memory, value ) {
+        return false;
+    }
+
+    next_frame.regs.append( dwarf::R29, stack_slot + 4 );
+    next_frame.regs.append( dwarf::PC, value );
+    true
+}
+
+impl Architecture for Arch {
+    const NAME: &'static str = "mips";
+    const ENDIANNESS: Endianness = Endianness::BigEndian;
+    const BITNESS: Bitness = Bitness::B32;
+
+    type Endianity = BigEndian;
+    type State = ContextCache< BigEndian >;
+
+    #[inline]
+    fn register_name_str( register: u16 ) -> Option< &'static str > {
+        register_name_str( register )
+    }
+
+    #[inline]
+    fn get_stack_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::R29 )
+    }
+
+    #[inline]
+    fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::PC )
+    }
+
+    #[inline]
+    fn initial_state() -> Self::State {
+        ContextCache::new()
+    }
+
+    #[inline]
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        unwind( nth_frame, memory, state, current_frame, next_frame, panic_on_partial_backtrace )
+    }
+
+    #[inline]
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        unwind_with_frame_pointer( nth_frame, memory, current_frame, next_frame )
+    }
+
+    #[inline]
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        recover_frame_from_stack( memory, stack_slot, value, next_frame )
+    }
+}
@@ -1,12 +1,14 @@
 use gimli::BigEndian;
 use dwarf_regs::DwarfRegs;
-use arch::Architecture;
+use arch::{Architecture, classify_frame};
+use arch::mips;
 use address_space::MemoryReader;
 use unwind_context::UnwindFrame;
-use frame_descriptions::ContextCache;
+use frame_descriptions::{ContextCache, FrameKind};
 use archive::{Endianness, Bitness};
 use dwarf::dwarf_unwind;
 
+// This module also contains the unwinding code shared with the little endian `mips64el`.
 pub mod dwarf {
     pub const R0: u16 = 0;
     pub const R1: u16 = 1;
@@ -47,6 +49,293 @@ pub mod dwarf {
 #[allow(dead_code)]
 pub struct Arch {}
 
+const MAX_PROLOGUE_SCAN_LENGTH: u64 = 4096;
+
+// li $v0, __NR_rt_sigreturn; syscall
+const RT_SIGRETURN_CODE: u32 = 0x2402_145b;
+const SYSCALL_CODE: u32 = 0x0000_000c;
+
+// The offsets of `sc_regs` and `sc_pc` from the stack pointer at the point
+// when the signal handler returns, based on the kernel's `struct rt_sigframe`
+// from `arch/mips/kernel/signal.c` and the n64 `struct sigcontext`.
+const RT_SIGFRAME_REGS_OFFSET: u64 = 192;
+const SIGCONTEXT_PC_OFFSET: u64 = 576;
+
+fn is_rt_sigreturn< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> bool {
+    memory.get_u32_at_address( A::ENDIANNESS, address ) == Some( RT_SIGRETURN_CODE ) &&
+    memory.get_u32_at_address( A::ENDIANNESS, address + 4 ) == Some( SYSCALL_CODE )
+}
+
+fn is_signal_frame< A: Architecture, M: MemoryReader< A > >( memory: &M, current_frame: &UnwindFrame< A > ) -> bool {
+    let address = match current_frame.regs.get( dwarf::PC ) {
+        Some( address ) => address,
+        None => return false
+    };
+
+    if let Some( ref binary ) = current_frame.binary {
+        if binary.is_signal_trampoline( address ) {
+            return true;
+        }
+    }
+
+    is_rt_sigreturn( memory, address ) || is_rt_sigreturn( memory, address.wrapping_sub( 4 ) )
+}
+
+fn unwind_signal_frame< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
+    let sp = match current_frame.regs.get( dwarf::R29 ) {
+        Some( sp ) => sp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let base = sp + RT_SIGFRAME_REGS_OFFSET;
+    for register in dwarf::R0..dwarf::R31 + 1 {
+        if let Some( value ) = memory.get_u64_at_address( A::ENDIANNESS, base + register as u64 * 8 ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", A::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value );
+        }
+    }
+
+    let pc = match memory.get_u64_at_address( A::ENDIANNESS, base + SIGCONTEXT_PC_OFFSET ) {
+        Some( pc ) => pc,
+        None => {
+            debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+            return false;
+        }
+    };
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", A::register_name( dwarf::PC ), nth_frame + 1, pc );
+    next_frame.regs.append( dwarf::PC, pc );
+    current_frame.cfa = Some( sp );
+    next_frame.regs.get( dwarf::R29 ).is_some()
+}
+
+struct Prologue {
+    frame_size: u64,
+    ra_offset: Option< u64 >,
+    fp_offset: Option< u64 >,
+    sets_fp: bool
+}
+
+fn is_sp_adjustment( instruction: u32 ) -> bool {
+    // daddiu $sp, $sp, -imm
+    (instruction >> 16) == 0x67bd && (instruction & 0x8000) != 0
+}
+
+// Since there is no fixed frame layout on MIPS we look for the function's
+// prologue and figure out where it has saved the RA and FP registers.
+fn analyze_prologue< A: Architecture, M: MemoryReader< A > >( memory: &M, address: u64 ) -> Option< Prologue > {
+    let mut start = address;
+    loop {
+        if address - start >= MAX_PROLOGUE_SCAN_LENGTH {
+            return None;
+        }
+
+        let instruction = memory.get_u32_at_address( A::ENDIANNESS, start )?;
+        if is_sp_adjustment( instruction ) {
+            break;
+        }
+
+        // jr $ra; we've hit the end of the previous function.
+        if instruction == 0x03e00008 && start != address {
+            return None;
+        }
+
+        start = start.checked_sub( 4 )?;
+    }
+
+    let instruction = memory.get_u32_at_address( A::ENDIANNESS, start )?;
+    let mut prologue = Prologue {
+        frame_size: (-((instruction & 0xffff) as i16 as i64)) as u64,
+        ra_offset: None,
+        fp_offset: None,
+        sets_fp: false
+    };
+
+    let mut current = start + 4;
+    while current < address {
+        let instruction = memory.get_u32_at_address( A::ENDIANNESS, current )?;
+        let offset = (instruction & 0xffff) as i16 as u64;
+        match instruction >> 16 {
+            // sd $ra, offset($sp)
+            0xffbf => prologue.ra_offset = Some( offset ),
+            // sd $fp, offset($sp)
+            0xffbe => prologue.fp_offset = Some( offset ),
+            _ => {}
+        }
+
+        // move $fp, $sp
+        if instruction == 0x03a0f025 || instruction == 0x03a0f02d {
+            prologue.sets_fp = true;
+        }
+
+        current += 4;
+    }
+
+    Some( prologue )
+}
+
+pub fn register_name_str( register: u16 ) -> Option< &'static str > {
+    use self::dwarf::*;
+
+    let name = match register {
+        R0 => "R0",
+        R1 => "AT",
+        R2 => "V0",
+        R3 => "V1",
+        R4 => "A0",
+        R5 => "A1",
+        R6 => "A2",
+        R7 => "A3",
+        R8 => "A4",
+        R9 => "A5",
+        R10 => "A6",
+        R11 => "A7",
+        R12 => "T0",
+        R13 => "T1",
+        R14 => "T2",
+        R15 => "T3",
+        R16 => "S0",
+        R17 => "S1",
+        R18 => "S2",
+        R19 => "S3",
+        R20 => "S4",
+        R21 => "S5",
+        R22 => "S6",
+        R23 => "S7",
+        R24 => "T8",
+        R25 => "T9",
+        R26 => "K0",
+        R27 => "K1",
+        R28 => "GP",
+        R29 => "SP",
+        R30 => "FP",
+        R31 => "RA",
+        PC => "PC",
+        _ => return None
+    };
+
+    Some( name )
+}
+
+pub fn unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, state: &mut ContextCache< A::Endianity >, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A >, _panic_on_partial_backtrace: bool ) -> bool {
+    let kind = classify_frame( state, current_frame, || {
+        if is_signal_frame( memory, current_frame ) { FrameKind::Signal } else { FrameKind::Regular }
+    });
+
+    if kind == FrameKind::Signal {
+        return unwind_signal_frame( nth_frame, memory, current_frame, next_frame );
+    }
+
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::PC |
+            dwarf::R31 |
+            dwarf::R29 => continue,
+            _ => next_frame.regs.append( register ,value )
+        }
+    }
+
+    if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+        return false;
+    }
+
+    let sp = current_frame.cfa.unwrap();
+    next_frame.regs.append( dwarf::R29, sp );
+    next_frame.regs.append( dwarf::R30, sp );
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", A::register_name( dwarf::R29 ), nth_frame + 1, sp );
+
+    if let Some( return_address ) = next_frame.regs.get( dwarf::R31 ) {
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    } else if nth_frame == 0 {
+        let return_address = current_frame.regs.get( dwarf::R31 ).unwrap();
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    } else {
+        debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+        current_frame.mark_end_of_stack();
+        false
+    }
+}
+
+pub fn unwind_with_frame_pointer< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
+    let pc = current_frame.regs.get( dwarf::PC ).unwrap();
+    let prologue = match analyze_prologue( memory, pc ) {
+        Some( prologue ) => prologue,
+        None => {
+            debug!( "Previous frame not found: failed to find the prologue for frame #{}", nth_frame );
+            return false;
+        }
+    };
+
+    // With frame pointers the prologue does `move $fp, $sp` right after
+    // allocating the frame, so the FP points to the bottom of the frame.
+    let base = if prologue.sets_fp {
+        current_frame.regs.get( dwarf::R30 )
+    } else {
+        current_frame.regs.get( dwarf::R29 )
+    };
+
+    let base = match base {
+        Some( base ) if memory.is_stack_address( base ) => base,
+        _ => {
+            debug!( "Previous frame not found: frame #{} has no valid frame base", nth_frame );
+            return false;
+        }
+    };
+
+    let return_address = match prologue.ra_offset {
+        Some( offset ) => memory.get_u64_at_address( A::ENDIANNESS, base.wrapping_add( offset ) ),
+        None if nth_frame == 0 => current_frame.regs.get( dwarf::R31 ),
+        None => None
+    };
+
+    let previous_fp = match prologue.fp_offset {
+        Some( offset ) => memory.get_u64_at_address( A::ENDIANNESS, base.wrapping_add( offset ) ),
+        None => current_frame.regs.get( dwarf::R30 )
+    };
+
+    let return_address = match return_address {
+        Some( return_address ) if return_address != 0 => return_address,
+        _ => {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            return false;
+        }
+    };
+
+    for (register, value) in current_frame.regs.iter() {
+        match register {
+            dwarf::PC |
+            dwarf::R31 |
+            dwarf::R30 |
+            dwarf::R29 => continue,
+            _ => next_frame.regs.append( register, value )
+        }
+    }
+
+    let sp = base + prologue.frame_size;
+    current_frame.cfa = Some( sp );
+    next_frame.regs.append( dwarf::R29, sp );
+    if let Some( previous_fp ) = previous_fp {
+        next_frame.regs.append( dwarf::R30, previous_fp );
+    }
+    next_frame.regs.append( dwarf::R31, return_address );
+    next_frame.regs.append( dwarf::PC, return_address );
+    true
+}
+
+pub fn recover_frame_from_stack< A: Architecture, M: MemoryReader< A > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< A > ) -> bool {
+    if !mips::is_after_call_instruction( memory, value ) {
+        return false;
+    }
+
+    next_frame.regs.append( dwarf::R29, stack_slot + 8 );
+    next_frame.regs.append( dwarf::PC, value );
+    true
+}
+
 impl Architecture for Arch {
     const NAME: &'static str = "mips64";
     const ENDIANNESS: Endianness = Endianness::BigEndian;
@@ -55,47 +344,9 @@ impl Architecture for Arch {
     type Endianity = BigEndian;
     type State = ContextCache< BigEndian >;
 
+    #[inline]
     fn register_name_str( register: u16 ) -> Option< &'static str > {
-        use self::dwarf::*;
-
-        let name = match register {
-            R0 => "R0",
-            R1 => "AT",
-            R2 => "V0",
-            R3 => "V1",
-            R4 => "A0",
-            R5 => "A1",
-            R6 => "A2",
-            R7 => "A3",
-            R8 => "A4",
-            R9 => "A5",
-            R10 => "A6",
-            R11 => "A7",
-            R12 => "T0",
-            R13 => "T1",
-            R14 => "T2",
-            R15 => "T3",
-            R16 => "S0",
-            R17 => "S1",
-            R18 => "S2",
-            R19 => "S3",
-            R20 => "S4",
-            R21 => "S5",
-            R22 => "S6",
-            R23 => "S7",
-            R24 => "T8",
-            R25 => "T9",
-            R26 => "K0",
-            R27 => "K1",
-            R28 => "GP",
-            R29 => "SP",
-            R30 => "FP",
-            R31 => "RA",
-            PC => "PC",
-            _ => return None
-        };
-
-        Some( name )
+        register_name_str( register )
     }
 
     #[inline]
@@ -114,35 +365,17 @@ impl Architecture for Arch {
     }
 
     #[inline]
-    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, _panic_on_partial_backtrace: bool ) -> bool {
-        for (register, value) in current_frame.regs.iter() {
-            match register {
-                dwarf::PC |
-                dwarf::R31 |
-                dwarf::R29 => continue,
-                _ => next_frame.regs.append( register ,value )
-            }
-        }
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        unwind( nth_frame, memory, state, current_frame, next_frame, panic_on_partial_backtrace )
+    }
 
-        if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
-            return false;
-        }
+    #[inline]
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        unwind_with_frame_pointer( nth_frame, memory, current_frame, next_frame )
+    }
 
-        let sp = current_frame.cfa.unwrap();
-        next_frame.regs.append( dwarf::R29, sp );
-        next_frame.regs.append( dwarf::R30, sp );
-        debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Self::register_name( dwarf::R29 ), nth_frame + 1, sp );
-
-        if let Some( return_address ) = next_frame.regs.get( dwarf::R31 ) {
-            next_frame.regs.append( dwarf::PC, return_address );
-            true
-        } else if nth_frame == 0 {
-            let return_address = current_frame.regs.get( dwarf::R31 ).unwrap();
-            next_frame.regs.append( dwarf::PC, return_address );
-            true
-        } else {
-            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
-            false
-        }
+    #[inline]
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        recover_frame_from_stack( memory, stack_slot, value, next_frame )
     }
 }
@@ -0,0 +1,57 @@
+use gimli::LittleEndian;
+use dwarf_regs::DwarfRegs;
+use arch::Architecture;
+use arch::mips;
+use address_space::MemoryReader;
+use unwind_context::UnwindFrame;
+use frame_descriptions::ContextCache;
+use archive::{Endianness, Bitness};
+
+pub use arch::mips::dwarf;
+
+#[allow(dead_code)]
+pub struct Arch {}
+
+impl Architecture for Arch {
+    const NAME: &'static str = "mipsel";
+    const ENDIANNESS: Endianness = Endianness::LittleEndian;
+    const BITNESS: Bitness = Bitness::B32;
+
+    type Endianity = LittleEndian;
+    type State = ContextCache< LittleEndian >;
+
+    #[inline]
+    fn register_name_str( register: u16 ) -> Option< &'static str > {
+        mips::register_name_str( register )
+    }
+
+    #[inline]
+    fn get_stack_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::R29 )
+    }
+
+    #[inline]
+    fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::PC )
+    }
+
+    #[inline]
+    fn initial_state() -> Self::State {
+        ContextCache::new()
+    }
+
+    #[inline]
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        mips::unwind( nth_frame, memory, state, current_frame, next_frame, panic_on_partial_backtrace )
+    }
+
+    #[inline]
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        mips::unwind_with_frame_pointer( nth_frame, memory, current_frame, next_frame )
+    }
+
+    #[inline]
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        mips::recover_frame_from_stack( memory, stack_slot, value, next_frame )
+    }
+}
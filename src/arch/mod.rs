@@ -3,21 +3,46 @@ use gimli;
 use dwarf_regs::DwarfRegs;
 use address_space::MemoryReader;
 use unwind_context::UnwindFrame;
+use frame_descriptions::{ContextCache, FrameKind};
 use archive::{Endianness, Bitness};
 
 pub mod amd64;
+pub mod mips;
+pub mod mipsel;
 pub mod mips64;
+pub mod mips64el;
 pub mod arm;
+pub mod aarch64;
+pub mod x86;
+pub mod riscv64;
 
 pub mod native {
     #[cfg(target_arch = "x86_64")]
     pub use arch::amd64::*;
 
-    #[cfg(target_arch = "mips64")]
+    #[cfg(target_arch = "x86")]
+    pub use arch::x86::*;
+
+    #[cfg(all(target_arch = "mips", target_endian = "big"))]
+    pub use arch::mips::*;
+
+    #[cfg(all(target_arch = "mips", target_endian = "little"))]
+    pub use arch::mipsel::*;
+
+    #[cfg(all(target_arch = "mips64", target_endian = "big"))]
     pub use arch::mips64::*;
 
+    #[cfg(all(target_arch = "mips64", target_endian = "little"))]
+    pub use arch::mips64el::*;
+
     #[cfg(target_arch = "arm")]
     pub use arch::arm::*;
+
+    #[cfg(target_arch = "aarch64")]
+    pub use arch::aarch64::*;
+
+    #[cfg(target_arch = "riscv64")]
+    pub use arch::riscv64::*;
 }
 
 pub enum RegName {
@@ -50,13 +75,13 @@ impl Endianity for gimli::BigEndian {
     }
 }
 
-pub trait Architecture: Sized {
+pub trait Architecture: Sized + Send + Sync + 'static {
     const NAME: &'static str;
     const ENDIANNESS: Endianness;
     const BITNESS: Bitness;
 
-    type Endianity: Endianity + 'static;
-    type State;
+    type Endianity: Endianity + Send + Sync + 'static;
+    type State: Send + Sync;
 
     fn register_name( register: u16 ) -> RegName {
         if let Some( name ) = Self::register_name_str( register ) {
@@ -71,4 +96,24 @@ pub trait Architecture: Sized {
     fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 >;
     fn initial_state() -> Self::State;
     fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool;
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool;
+
+    // Used by the stack scanning; if the `value` read from the `stack_slot` looks
+    // like a return address (that is, it points right after a call instruction)
+    // then initializes the `next_frame` as if we've returned through it.
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool;
+}
+
+// Figuring out whether we're in a signal frame might involve a symbol lookup,
+// so just as with the unwind rules we only do it once for every address.
+pub fn classify_frame< A: Architecture, F: FnOnce() -> FrameKind >( state: &mut ContextCache< A::Endianity >, current_frame: &UnwindFrame< A >, classify: F ) -> FrameKind {
+    let address = match A::get_instruction_pointer( &current_frame.regs ) {
+        Some( address ) => address,
+        None => return classify()
+    };
+
+    match current_frame.binary {
+        Some( ref binary ) => state.get_frame_kind( binary.generation(), address, classify ),
+        None => classify()
+    }
 }
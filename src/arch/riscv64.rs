@@ -0,0 +1,301 @@
+use gimli::LittleEndian;
+use dwarf_regs::DwarfRegs;
+use arch::{Architecture, classify_frame};
+use address_space::MemoryReader;
+use unwind_context::UnwindFrame;
+use frame_descriptions::{ContextCache, FrameKind};
+use archive::{Endianness, Bitness};
+use dwarf::dwarf_unwind;
+
+// Source: RISC-V ELF psABI specification
+//         https://github.com/riscv-non-isa/riscv-elf-psabi-doc/blob/master/riscv-dwarf.adoc
+pub mod dwarf {
+    pub const X0: u16 = 0;
+    pub const X1: u16 = 1;
+    pub const X2: u16 = 2;
+    pub const X3: u16 = 3;
+    pub const X4: u16 = 4;
+    pub const X5: u16 = 5;
+    pub const X6: u16 = 6;
+    pub const X7: u16 = 7;
+    pub const X8: u16 = 8;
+    pub const X9: u16 = 9;
+    pub const X10: u16 = 10;
+    pub const X11: u16 = 11;
+    pub const X12: u16 = 12;
+    pub const X13: u16 = 13;
+    pub const X14: u16 = 14;
+    pub const X15: u16 = 15;
+    pub const X16: u16 = 16;
+    pub const X17: u16 = 17;
+    pub const X18: u16 = 18;
+    pub const X19: u16 = 19;
+    pub const X20: u16 = 20;
+    pub const X21: u16 = 21;
+    pub const X22: u16 = 22;
+    pub const X23: u16 = 23;
+    pub const X24: u16 = 24;
+    pub const X25: u16 = 25;
+    pub const X26: u16 = 26;
+    pub const X27: u16 = 27;
+    pub const X28: u16 = 28;
+    pub const X29: u16 = 29;
+    pub const X30: u16 = 30;
+    pub const X31: u16 = 31;
+
+    // The PC doesn't have a DWARF register number of its own, so we use
+    // the one of `ft0` which is caller-saved and never appears in the CFI.
+    pub const PC: u16 = 32;
+}
+
+#[allow(dead_code)]
+pub struct Arch {}
+
+// li a7, __NR_rt_sigreturn; ecall
+const RT_SIGRETURN_CODE: u32 = 0x08b0_0893;
+const ECALL_CODE: u32 = 0x0000_0073;
+
+// The offset of `uc.uc_mcontext.sc_regs` from the stack pointer at the point
+// when the signal handler returns, based on the kernel's `struct rt_sigframe`
+// from `arch/riscv/kernel/signal.c`. The PC is stored in place of X0.
+const RT_SIGFRAME_REGS_OFFSET: u64 = 304;
+
+fn is_rt_sigreturn< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    memory.get_u32_at_address( Arch::ENDIANNESS, address ) == Some( RT_SIGRETURN_CODE ) &&
+    memory.get_u32_at_address( Arch::ENDIANNESS, address + 4 ) == Some( ECALL_CODE )
+}
+
+fn is_signal_frame< M: MemoryReader< Arch > >( memory: &M, current_frame: &UnwindFrame< Arch > ) -> bool {
+    let address = match current_frame.regs.get( dwarf::PC ) {
+        Some( address ) => address,
+        None => return false
+    };
+
+    if let Some( ref binary ) = current_frame.binary {
+        if binary.is_signal_trampoline( address ) {
+            return true;
+        }
+
+        if let Some( (_, "__vdso_rt_sigreturn") ) = binary.lookup_absolute_symbol( address ) {
+            return true;
+        }
+    }
+
+    is_rt_sigreturn( memory, address ) || is_rt_sigreturn( memory, address.wrapping_sub( 4 ) )
+}
+
+fn unwind_signal_frame< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    let sp = match current_frame.regs.get( dwarf::X2 ) {
+        Some( sp ) => sp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let base = sp + RT_SIGFRAME_REGS_OFFSET;
+    for register in dwarf::X1..dwarf::X31 + 1 {
+        if let Some( value ) = memory.get_u64_at_address( Arch::ENDIANNESS, base + register as u64 * 8 ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value );
+        }
+    }
+
+    let pc = match memory.get_u64_at_address( Arch::ENDIANNESS, base ) {
+        Some( pc ) => pc,
+        None => {
+            debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+            return false;
+        }
+    };
+
+    debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Arch::register_name( dwarf::PC ), nth_frame + 1, pc );
+    next_frame.regs.append( dwarf::PC, pc );
+    current_frame.cfa = Some( sp );
+    next_frame.regs.get( dwarf::X2 ).is_some()
+}
+
+fn is_after_call_instruction< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    let code = match memory.get_u32_at_address( Endianness::LittleEndian, address.wrapping_sub( 4 ) ) {
+        Some( code ) => code,
+        None => return false
+    };
+
+    // jal ra, <label> and jalr ra, <offset>(<register>)
+    let opcode = code & 0x7F;
+    if (opcode == 0x6F || opcode == 0x67) && (code >> 7) & 0x1F == dwarf::X1 as u32 {
+        return true;
+    }
+
+    // c.jalr <register>
+    let compressed = code >> 16;
+    compressed & 0xF07F == 0x9002 && (compressed >> 7) & 0x1F != 0
+}
+
+impl Architecture for Arch {
+    const NAME: &'static str = "riscv64";
+    const ENDIANNESS: Endianness = Endianness::LittleEndian;
+    const BITNESS: Bitness = Bitness::B64;
+
+    type Endianity = LittleEndian;
+    type State = ContextCache< LittleEndian >;
+
+    fn register_name_str( register: u16 ) -> Option< &'static str > {
+        use self::dwarf::*;
+
+        let name = match register {
+            X0 => "ZERO",
+            X1 => "RA",
+            X2 => "SP",
+            X3 => "GP",
+            X4 => "TP",
+            X5 => "T0",
+            X6 => "T1",
+            X7 => "T2",
+            X8 => "FP",
+            X9 => "S1",
+            X10 => "A0",
+            X11 => "A1",
+            X12 => "A2",
+            X13 => "A3",
+            X14 => "A4",
+            X15 => "A5",
+            X16 => "A6",
+            X17 => "A7",
+            X18 => "S2",
+            X19 => "S3",
+            X20 => "S4",
+            X21 => "S5",
+            X22 => "S6",
+            X23 => "S7",
+            X24 => "S8",
+            X25 => "S9",
+            X26 => "S10",
+            X27 => "S11",
+            X28 => "T3",
+            X29 => "T4",
+            X30 => "T5",
+            X31 => "T6",
+            PC => "PC",
+            _ => return None
+        };
+
+        Some( name )
+    }
+
+    #[inline]
+    fn get_stack_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::X2 )
+    }
+
+    #[inline]
+    fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::PC )
+    }
+
+    #[inline]
+    fn initial_state() -> Self::State {
+        ContextCache::new()
+    }
+
+    #[inline]
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        let kind = classify_frame( state, current_frame, || {
+            if is_signal_frame( memory, current_frame ) { FrameKind::Signal } else { FrameKind::Regular }
+        });
+
+        if kind == FrameKind::Signal {
+            return unwind_signal_frame( nth_frame, memory, current_frame, next_frame );
+        }
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::PC |
+                dwarf::X1 |
+                dwarf::X2 => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+            if panic_on_partial_backtrace {
+                panic!( "Partial backtrace!" );
+            }
+            return false;
+        }
+
+        let sp = current_frame.cfa.unwrap();
+        next_frame.regs.append( dwarf::X2, sp );
+        debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", Self::register_name( dwarf::X2 ), nth_frame + 1, sp );
+
+        // A leaf function doesn't have to save the RA anywhere
+        // so on the first frame it can still be in the register.
+        if let Some( return_address ) = next_frame.regs.get( dwarf::X1 ) {
+            next_frame.regs.append( dwarf::PC, return_address );
+            true
+        } else if let (0, Some( return_address )) = (nth_frame, current_frame.regs.get( dwarf::X1 )) {
+            next_frame.regs.append( dwarf::PC, return_address );
+            true
+        } else {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            current_frame.mark_end_of_stack();
+            false
+        }
+    }
+
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        // With frame pointers the FP points just past the saved RA and FP, so:
+        //   previous.FP == *(current.FP - 16)
+        //   previous.PC == *(current.FP - 8)
+        //   previous.SP == current.FP
+        let fp = match current_frame.regs.get( dwarf::X8 ) {
+            Some( fp ) => fp,
+            None => {
+                debug!( "Previous frame not found: FP is unknown for frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        let sp = current_frame.regs.get( dwarf::X2 ).unwrap_or( 0 );
+        if fp < sp + 16 || !memory.is_stack_address( fp - 16 ) {
+            debug!( "Previous frame not found: FP=0x{:016X} of frame #{} doesn't point to the stack", fp, nth_frame );
+            return false;
+        }
+
+        let previous_fp = memory.get_u64_at_address( Self::ENDIANNESS, fp - 16 );
+        let return_address = memory.get_u64_at_address( Self::ENDIANNESS, fp - 8 );
+        let (previous_fp, return_address) = match (previous_fp, return_address) {
+            (Some( previous_fp ), Some( return_address )) if return_address != 0 => (previous_fp, return_address),
+            _ => {
+                debug!( "Previous frame not found: failed to read the frame record of frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::PC |
+                dwarf::X1 |
+                dwarf::X2 |
+                dwarf::X8 => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        current_frame.cfa = Some( fp );
+        next_frame.regs.append( dwarf::X1, return_address );
+        next_frame.regs.append( dwarf::X2, fp );
+        next_frame.regs.append( dwarf::X8, previous_fp );
+        next_frame.regs.append( dwarf::PC, return_address );
+        true
+    }
+
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        if !is_after_call_instruction( memory, value ) {
+            return false;
+        }
+
+        next_frame.regs.append( dwarf::X2, stack_slot + 8 );
+        next_frame.regs.append( dwarf::PC, value );
+        true
+    }
+}
@@ -0,0 +1,263 @@
+use gimli::LittleEndian;
+
+use dwarf_regs::DwarfRegs;
+use arch::{Architecture, classify_frame};
+use arch::amd64;
+use address_space::MemoryReader;
+use unwind_context::UnwindFrame;
+use frame_descriptions::{ContextCache, FrameKind};
+use archive::{Endianness, Bitness};
+use dwarf::dwarf_unwind;
+
+// Source: https://github.com/hjl-tools/x86-psABI/wiki/intel386-psABI-1.1.pdf
+pub mod dwarf {
+    pub const EAX: u16 = 0;
+    pub const ECX: u16 = 1;
+    pub const EDX: u16 = 2;
+    pub const EBX: u16 = 3;
+    pub const ESP: u16 = 4;
+    pub const EBP: u16 = 5;
+    pub const ESI: u16 = 6;
+    pub const EDI: u16 = 7;
+    pub const RETURN_ADDRESS: u16 = 8;
+    pub const FLAGS: u16 = 9;
+    pub const ES: u16 = 40;
+    pub const CS: u16 = 41;
+    pub const SS: u16 = 42;
+    pub const DS: u16 = 43;
+    pub const FS: u16 = 44;
+    pub const GS: u16 = 45;
+}
+
+#[allow(dead_code)]
+pub struct Arch {}
+
+// The offsets of the registers within `struct sigcontext_32` from the kernel's `asm/sigcontext.h`.
+const SIGCONTEXT_REGS: &[(u16, u64)] = &[
+    (dwarf::GS, 0),
+    (dwarf::FS, 4),
+    (dwarf::ES, 8),
+    (dwarf::DS, 12),
+    (dwarf::EDI, 16),
+    (dwarf::ESI, 20),
+    (dwarf::EBP, 24),
+    (dwarf::ESP, 28),
+    (dwarf::EBX, 32),
+    (dwarf::EDX, 36),
+    (dwarf::ECX, 40),
+    (dwarf::EAX, 44),
+    (dwarf::RETURN_ADDRESS, 56),
+    (dwarf::CS, 60),
+    (dwarf::FLAGS, 64),
+    (dwarf::SS, 72)
+];
+
+// The offset of `uc.uc_mcontext` within `struct rt_sigframe` (without the
+// already popped `pretcode`) from the kernel's `arch/x86/include/asm/sigframe.h`.
+const RT_SIGFRAME_MCONTEXT_OFFSET: u64 = 160;
+
+// mov $0xad, %eax; int $0x80
+const RT_SIGRETURN_CODE_HEAD: u32 = 0x0000_adb8;
+const RT_SIGRETURN_CODE_TAIL: u32 = 0x80cd_0000;
+const RT_SIGRETURN_SYSCALL_OFFSET: u64 = 5;
+
+fn is_rt_sigreturn< M: MemoryReader< Arch > >( memory: &M, address: u64 ) -> bool {
+    memory.get_u32_at_address( Endianness::LittleEndian, address ) == Some( RT_SIGRETURN_CODE_HEAD ) &&
+    memory.get_u32_at_address( Endianness::LittleEndian, address + 3 ) == Some( RT_SIGRETURN_CODE_TAIL )
+}
+
+fn is_signal_frame< M: MemoryReader< Arch > >( memory: &M, current_frame: &UnwindFrame< Arch > ) -> bool {
+    let address = match current_frame.regs.get( dwarf::RETURN_ADDRESS ) {
+        Some( address ) => address,
+        None => return false
+    };
+
+    if let Some( ref binary ) = current_frame.binary {
+        if binary.is_signal_trampoline( address ) {
+            return true;
+        }
+
+        match binary.lookup_absolute_symbol( address ) {
+            Some( (_, "__restore_rt") ) |
+            Some( (_, "__kernel_rt_sigreturn") ) => return true,
+            _ => {}
+        }
+    }
+
+    is_rt_sigreturn( memory, address ) ||
+    is_rt_sigreturn( memory, address.wrapping_sub( RT_SIGRETURN_SYSCALL_OFFSET ) )
+}
+
+fn unwind_signal_frame< M: MemoryReader< Arch > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Arch >, next_frame: &mut UnwindFrame< Arch > ) -> bool {
+    let esp = match current_frame.regs.get( dwarf::ESP ) {
+        Some( esp ) => esp,
+        None => return false
+    };
+
+    debug!( "Frame #{} is a signal frame", nth_frame );
+
+    let mcontext = esp + RT_SIGFRAME_MCONTEXT_OFFSET;
+    for &(register, offset) in SIGCONTEXT_REGS {
+        if let Some( value ) = memory.get_u32_at_address( Endianness::LittleEndian, mcontext + offset ) {
+            debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", Arch::register_name( register ), nth_frame + 1, value );
+            next_frame.regs.append( register, value as u64 );
+        }
+    }
+
+    current_frame.cfa = Some( mcontext );
+    if next_frame.regs.get( dwarf::ESP ).is_none() || next_frame.regs.get( dwarf::RETURN_ADDRESS ).is_none() {
+        debug!( "Previous frame not found: failed to read the signal frame of frame #{}", nth_frame );
+        return false;
+    }
+
+    true
+}
+
+impl Architecture for Arch {
+    const NAME: &'static str = "x86";
+    const ENDIANNESS: Endianness = Endianness::LittleEndian;
+    const BITNESS: Bitness = Bitness::B32;
+
+    type Endianity = LittleEndian;
+    type State = ContextCache< LittleEndian >;
+
+    fn register_name_str( register: u16 ) -> Option< &'static str > {
+        use self::dwarf::*;
+
+        let name = match register {
+            EAX => "EAX",
+            ECX => "ECX",
+            EDX => "EDX",
+            EBX => "EBX",
+            ESP => "ESP",
+            EBP => "EBP",
+            ESI => "ESI",
+            EDI => "EDI",
+            RETURN_ADDRESS => "RA",
+            FLAGS => "EFLAGS",
+            ES => "ES",
+            CS => "CS",
+            SS => "SS",
+            DS => "DS",
+            FS => "FS",
+            GS => "GS",
+            _ => return None
+        };
+
+        Some( name )
+    }
+
+    #[inline]
+    fn get_stack_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::ESP )
+    }
+
+    #[inline]
+    fn get_instruction_pointer( regs: &DwarfRegs ) -> Option< u64 > {
+        regs.get( dwarf::RETURN_ADDRESS )
+    }
+
+    #[inline]
+    fn initial_state() -> Self::State {
+        ContextCache::new()
+    }
+
+    #[inline]
+    fn unwind< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, state: &mut Self::State, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self >, panic_on_partial_backtrace: bool ) -> bool {
+        let kind = classify_frame( state, current_frame, || {
+            if is_signal_frame( memory, current_frame ) { FrameKind::Signal } else { FrameKind::Regular }
+        });
+
+        if kind == FrameKind::Signal {
+            return unwind_signal_frame( nth_frame, memory, current_frame, next_frame );
+        }
+
+        if current_frame.regs.get( dwarf::EBP ).is_none() {
+            if let Some( ebp ) = amd64::guess_ebp( nth_frame, memory, state, current_frame, dwarf::EBP, dwarf::ESP, dwarf::RETURN_ADDRESS ) {
+                current_frame.regs.append( dwarf::EBP, ebp );
+            }
+        }
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::ESP |
+                dwarf::RETURN_ADDRESS => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        if !dwarf_unwind( nth_frame, memory, state, current_frame, next_frame ) {
+            if panic_on_partial_backtrace {
+                panic!( "Partial backtrace!" );
+            }
+            return false;
+        }
+
+        let esp = current_frame.cfa.unwrap();
+        next_frame.regs.append( dwarf::ESP, esp );
+        debug!( "Register {:?} at frame #{} is equal to 0x{:08X}", Self::register_name( dwarf::ESP ), nth_frame + 1, esp );
+
+        if next_frame.regs.get( dwarf::RETURN_ADDRESS ).is_some() {
+            true
+        } else {
+            debug!( "Previous frame not found: failed to determine the return address of frame #{}", nth_frame + 1 );
+            current_frame.mark_end_of_stack();
+            false
+        }
+    }
+
+    fn unwind_with_frame_pointer< M: MemoryReader< Self > >( nth_frame: usize, memory: &M, current_frame: &mut UnwindFrame< Self >, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        // With frame pointers every function starts with `push ebp; mov ebp, esp`, so:
+        //   previous.EBP == *(current.EBP)
+        //   previous.EIP == *(current.EBP + 4)
+        //   previous.ESP == current.EBP + 8
+        let ebp = match current_frame.regs.get( dwarf::EBP ) {
+            Some( ebp ) => ebp,
+            None => {
+                debug!( "Previous frame not found: EBP is unknown for frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        let esp = current_frame.regs.get( dwarf::ESP ).unwrap_or( 0 );
+        if ebp < esp || !memory.is_stack_address( ebp ) {
+            debug!( "Previous frame not found: EBP=0x{:08X} of frame #{} doesn't point to the stack", ebp, nth_frame );
+            return false;
+        }
+
+        let previous_ebp = memory.get_u32_at_address( Self::ENDIANNESS, ebp );
+        let return_address = memory.get_u32_at_address( Self::ENDIANNESS, ebp + 4 );
+        let (previous_ebp, return_address) = match (previous_ebp, return_address) {
+            (Some( previous_ebp ), Some( return_address )) if return_address != 0 => (previous_ebp as u64, return_address as u64),
+            _ => {
+                debug!( "Previous frame not found: failed to read the frame record of frame #{}", nth_frame );
+                return false;
+            }
+        };
+
+        for (register, value) in current_frame.regs.iter() {
+            match register {
+                dwarf::EBP |
+                dwarf::ESP |
+                dwarf::RETURN_ADDRESS => continue,
+                _ => next_frame.regs.append( register, value )
+            }
+        }
+
+        current_frame.cfa = Some( ebp + 8 );
+        next_frame.regs.append( dwarf::EBP, previous_ebp );
+        next_frame.regs.append( dwarf::ESP, ebp + 8 );
+        next_frame.regs.append( dwarf::RETURN_ADDRESS, return_address );
+        true
+    }
+
+    fn recover_frame_from_stack< M: MemoryReader< Self > >( memory: &M, stack_slot: u64, value: u64, next_frame: &mut UnwindFrame< Self > ) -> bool {
+        if !amd64::is_after_call_instruction( memory, value ) {
+            return false;
+        }
+
+        next_frame.regs.append( dwarf::ESP, stack_slot + 4 );
+        next_frame.regs.append( dwarf::RETURN_ADDRESS, value );
+        true
+    }
+}
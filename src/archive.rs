@@ -32,7 +32,10 @@ impl Bitness {
 #[derive(Clone, Debug)]
 pub struct UserFrame {
     pub address: u64,
-    pub initial_address: Option< u64 >
+    pub initial_address: Option< u64 >,
+    // This isn't serialized as part of the frame; it's stored
+    // in a separate `GuessedFrames` packet instead.
+    pub is_guessed: bool
 }
 
 impl< 'a, C: Context > Readable< 'a, C > for UserFrame {
@@ -44,7 +47,7 @@ impl< 'a, C: Context > Readable< 'a, C > for UserFrame {
             value => Some( value )
         };
 
-        Ok( UserFrame { address, initial_address } )
+        Ok( UserFrame { address, initial_address, is_guessed: false } )
     }
 }
 
@@ -186,6 +189,20 @@ pub enum Packet< 'a > {
     },
     Lost {
         count: u64
+    },
+    // Lists the indexes of the user frames of the `Sample` which immediately
+    // follows it which were guessed by scanning the stack.
+    GuessedFrames {
+        indexes: Cow< 'a, [u32] >
+    },
+    // Describes the `Sample` which immediately follows it; this is a separate
+    // packet so that the samples can still be read by older versions.
+    SampleInfo {
+        used_live_stack_reads: bool,
+        // How many bytes of the user stack the kernel has actually dumped.
+        dynamic_stack_size: u64,
+        // Whether the stack most likely didn't fit into the dump.
+        is_stack_truncated: bool
     }
 }
 
@@ -649,7 +649,8 @@ pub enum Error {
     UnsupportedPersonality( u8 ),
     MissingRegisterValue( Reg ),
     UnwindingFailed,
-    UnwindInfoMissing
+    UnwindInfoMissing,
+    CantUnwind
 }
 
 impl From< DecodeError > for Error {
@@ -895,7 +896,7 @@ impl VirtualMachine {
 
         if entry.value() == EXIDX_CANTUNWIND {
             debug!( "Entry for 0x{:08X} (index: {}) doesn't support unwinding", address, index );
-            return Err( Error::EndOfStack );
+            return Err( Error::CantUnwind );
         }
 
         self.vsp = match previous_regs.get( dwarf::R13 ) { // R13 is the stack pointer.
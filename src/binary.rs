@@ -1,13 +1,17 @@
 use std::str;
-use std::io;
+use std::io::{self, Read};
 use std::fs::File;
 use std::ops::{Range, Deref, Index};
+use std::cmp::min;
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 
 use memmap::Mmap;
+use lzma_rs;
+use flate2::read::ZlibDecoder;
+use byteorder::{ByteOrder, LittleEndian, BigEndian};
 use goblin::elf::header as elf_header;
-use goblin::elf::section_header::{SHT_SYMTAB, SHT_DYNSYM, SHT_STRTAB};
+use goblin::elf::section_header::{SHT_SYMTAB, SHT_DYNSYM, SHT_STRTAB, SHF_COMPRESSED};
 use goblin::elf::program_header::PT_LOAD;
 
 use elf::{self, Endian};
@@ -33,6 +37,8 @@ impl Deref for Blob {
     }
 }
 
+const ELFCOMPRESS_ZLIB: u32 = 1;
+
 #[derive(Debug)]
 pub struct SymbolTable {
     pub range: Range< u64 >,
@@ -59,10 +65,17 @@ pub struct BinaryData {
     data_range: Option< Range< usize > >,
     text_range: Option< Range< usize > >,
     eh_frame_range: Option< Range< usize > >,
+    eh_frame_hdr_range: Option< Range< usize > >,
     debug_frame_range: Option< Range< usize > >,
     gnu_debuglink_range: Option< Range< usize > >,
     arm_extab_range: Option< Range< usize > >,
     arm_exidx_range: Option< Range< usize > >,
+    debug_line_range: Option< Range< usize > >,
+    debug_info_range: Option< Range< usize > >,
+    debug_abbrev_range: Option< Range< usize > >,
+    debug_str_range: Option< Range< usize > >,
+    debug_ranges_range: Option< Range< usize > >,
+    decompressed_data: Vec< u8 >,
     is_shared_object: bool,
     symbol_tables: Vec< SymbolTable >,
     load_headers: Vec< LoadHeader >,
@@ -114,16 +127,24 @@ impl BinaryData {
         let mut data_range = None;
         let mut text_range = None;
         let mut eh_frame_range = None;
+        let mut eh_frame_hdr_range = None;
         let mut debug_frame_range = None;
         let mut gnu_debuglink_range = None;
         let mut arm_extab_range = None;
         let mut arm_exidx_range = None;
+        let mut debug_line_range = None;
+        let mut debug_info_range = None;
+        let mut debug_abbrev_range = None;
+        let mut debug_str_range = None;
+        let mut debug_ranges_range = None;
+        let mut gnu_debugdata_range = None;
         let mut is_shared_object = false;
         let mut symbol_tables = Vec::new();
         let mut load_headers = Vec::new();
         let mut endianness = Endianness::LittleEndian;
         let mut bitness = Bitness::B32;
         let mut architecture = "";
+        let mut decompressed_data = Vec::new();
 
         {
             let elf = elf::parse( &blob ).map_err( |err| io::Error::new( io::ErrorKind::Other, err ) )?;
@@ -151,9 +172,21 @@ impl BinaryData {
                     elf_header::EM_X86_64 => "amd64",
                     elf_header::EM_386 => "x86",
                     elf_header::EM_ARM => "arm",
-                    elf_header::EM_MIPS => {
+                    elf_header::EM_AARCH64 => "aarch64",
+                    elf_header::EM_RISCV => {
                         if elf.is_64_bit() {
+                            "riscv64"
+                        } else {
+                            "riscv32"
+                        }
+                    },
+                    elf_header::EM_MIPS => {
+                        if elf.is_64_bit() && endianness == Endianness::LittleEndian {
+                            "mips64el"
+                        } else if elf.is_64_bit() {
                             "mips64"
+                        } else if endianness == Endianness::LittleEndian {
+                            "mipsel"
                         } else {
                             "mips"
                         }
@@ -169,40 +202,55 @@ impl BinaryData {
                 let name_strtab = elf.get_strtab( &name_strtab_header )
                     .ok_or_else( || io::Error::new( io::ErrorKind::Other, format!( "missing strtab for section names strtab for {:?}", path ) ) )?;
 
+                find_symbol_tables( &elf, 0, &mut symbol_tables );
+
                 for header in elf.section_headers() {
-                    let ty = header.sh_type as u32;
-                    if ty == SHT_SYMTAB || ty == SHT_DYNSYM {
-                        let is_dynamic = ty == SHT_DYNSYM;
-                        let strtab_key = header.sh_link as usize;
-                        if let Some( strtab_header ) = elf.get_section_header( strtab_key ) {
-                            if strtab_header.sh_type as u32 == SHT_STRTAB {
-                                let strtab_range = elf.get_section_body_range( &strtab_header );
-                                let symtab_range = elf.get_section_body_range( &header );
-                                symbol_tables.push( SymbolTable {
-                                    range: symtab_range,
-                                    strtab_range,
-                                    is_dynamic
-                                });
-                            }
-                        }
-                    }
+                    let name = match name_strtab.get( header.sh_name ) {
+                        Some( Ok( name ) ) => name,
+                        _ => continue
+                    };
 
-                    let out_range = match name_strtab.get( header.sh_name ) {
-                        Some( Ok( ".data" ) ) => &mut data_range,
-                        Some( Ok( ".text" ) ) => &mut text_range,
-                        Some( Ok( ".eh_frame" ) ) => &mut eh_frame_range,
-                        Some( Ok( ".debug_frame" ) ) => &mut debug_frame_range,
-                        Some( Ok( ".gnu_debuglink" ) ) => &mut gnu_debuglink_range,
-                        Some( Ok( ".ARM.extab" ) ) => &mut arm_extab_range,
-                        Some( Ok( ".ARM.exidx" ) ) => &mut arm_exidx_range,
+                    let out_range = match name {
+                        ".data" => &mut data_range,
+                        ".text" => &mut text_range,
+                        ".eh_frame" => &mut eh_frame_range,
+                        ".eh_frame_hdr" => &mut eh_frame_hdr_range,
+                        ".debug_frame" | ".zdebug_frame" => &mut debug_frame_range,
+                        ".gnu_debuglink" => &mut gnu_debuglink_range,
+                        ".ARM.extab" => &mut arm_extab_range,
+                        ".ARM.exidx" => &mut arm_exidx_range,
+                        ".debug_line" | ".zdebug_line" => &mut debug_line_range,
+                        ".debug_info" | ".zdebug_info" => &mut debug_info_range,
+                        ".debug_abbrev" | ".zdebug_abbrev" => &mut debug_abbrev_range,
+                        ".debug_str" | ".zdebug_str" => &mut debug_str_range,
+                        ".debug_ranges" | ".zdebug_ranges" => &mut debug_ranges_range,
+                        ".gnu_debugdata" => &mut gnu_debugdata_range,
                         _ => continue
                     };
 
                     let offset = header.sh_offset as usize;
                     let length = header.sh_size as usize;
                     let range = offset..offset + length;
-                    if let Some( _ ) = blob.get( range.clone() ) {
+                    let bytes = match blob.get( range.clone() ) {
+                        Some( bytes ) => bytes,
+                        None => continue
+                    };
+
+                    let has_compression_header = header.sh_flags & SHF_COMPRESSED as u64 != 0;
+                    if !has_compression_header && !name.starts_with( ".zdebug_" ) {
                         *out_range = Some( range );
+                        continue;
+                    }
+
+                    match decompress_section( elf.is_64_bit(), elf.endianness(), has_compression_header, bytes ) {
+                        Ok( section ) => {
+                            let offset = blob.len() + decompressed_data.len();
+                            *out_range = Some( offset..offset + section.len() );
+                            decompressed_data.extend_from_slice( &section );
+                        },
+                        Err( error ) => {
+                            warn!( "Failed to decompress section '{}' from {:?}: {}", name, path, error );
+                        }
                     }
                 }
 
@@ -229,6 +277,16 @@ impl BinaryData {
             })?;
         }
 
+        let has_full_symbol_table = symbol_tables.iter().any( |symbol_table| !symbol_table.is_dynamic );
+        if let Some( range ) = gnu_debugdata_range {
+            if !has_full_symbol_table {
+                let base_offset = blob.len() as u64;
+                if let Err( error ) = load_mini_debug_info( &blob[ range ], base_offset, &mut decompressed_data, &mut symbol_tables ) {
+                    warn!( "Failed to load MiniDebugInfo from {:?}: {}", path, error );
+                }
+            }
+        }
+
         let binary = BinaryData {
             id,
             name: path.to_string(),
@@ -236,10 +294,17 @@ impl BinaryData {
             data_range,
             text_range,
             eh_frame_range,
+            eh_frame_hdr_range,
             debug_frame_range,
             gnu_debuglink_range,
             arm_extab_range,
             arm_exidx_range,
+            debug_line_range,
+            debug_info_range,
+            debug_abbrev_range,
+            debug_str_range,
+            debug_ranges_range,
+            decompressed_data,
             is_shared_object,
             symbol_tables,
             load_headers,
@@ -286,6 +351,19 @@ impl BinaryData {
         &self.blob
     }
 
+    // Sections which were decompressed at load time have ranges
+    // which point past the end of the file, so those should be
+    // accessed through this method instead of `as_bytes`.
+    #[inline]
+    pub fn get_section_data( &self, range: Range< usize > ) -> &[u8] {
+        let blob_length = self.blob.len();
+        if range.start >= blob_length && !self.decompressed_data.is_empty() {
+            return &self.decompressed_data[ range.start - blob_length..range.end - blob_length ];
+        }
+
+        &self.blob[ range ]
+    }
+
     #[inline]
     pub fn is_shared_object( &self ) -> bool {
         self.is_shared_object
@@ -306,6 +384,11 @@ impl BinaryData {
         self.eh_frame_range.clone()
     }
 
+    #[inline]
+    pub fn eh_frame_hdr_range( &self ) -> Option< Range< usize > > {
+        self.eh_frame_hdr_range.clone()
+    }
+
     #[inline]
     pub fn debug_frame_range( &self ) -> Option< Range< usize > > {
         self.debug_frame_range.clone()
@@ -326,6 +409,31 @@ impl BinaryData {
         self.arm_exidx_range.clone()
     }
 
+    #[inline]
+    pub fn debug_line_range( &self ) -> Option< Range< usize > > {
+        self.debug_line_range.clone()
+    }
+
+    #[inline]
+    pub fn debug_info_range( &self ) -> Option< Range< usize > > {
+        self.debug_info_range.clone()
+    }
+
+    #[inline]
+    pub fn debug_abbrev_range( &self ) -> Option< Range< usize > > {
+        self.debug_abbrev_range.clone()
+    }
+
+    #[inline]
+    pub fn debug_str_range( &self ) -> Option< Range< usize > > {
+        self.debug_str_range.clone()
+    }
+
+    #[inline]
+    pub fn debug_ranges_range( &self ) -> Option< Range< usize > > {
+        self.debug_ranges_range.clone()
+    }
+
     #[inline]
     pub fn load_headers( &self ) -> &[LoadHeader] {
         &self.load_headers
@@ -348,6 +456,143 @@ impl Index< Range< u64 > > for BinaryData {
 
     #[inline]
     fn index( &self, index: Range< u64 > ) -> &Self::Output {
-        &self.as_bytes()[ index.start as usize..index.end as usize ]
+        self.get_section_data( index.start as usize..index.end as usize )
+    }
+}
+
+fn find_symbol_tables< 'a, E: elf::Elf< 'a > >( elf: &E, base_offset: u64, output: &mut Vec< SymbolTable > ) {
+    for header in elf.section_headers() {
+        let ty = header.sh_type;
+        if ty != SHT_SYMTAB && ty != SHT_DYNSYM {
+            continue;
+        }
+
+        let is_dynamic = ty == SHT_DYNSYM;
+        let strtab_key = header.sh_link as usize;
+        if let Some( strtab_header ) = elf.get_section_header( strtab_key ) {
+            if strtab_header.sh_type == SHT_STRTAB {
+                let strtab_range = elf.get_section_body_range( &strtab_header );
+                let symtab_range = elf.get_section_body_range( &header );
+                output.push( SymbolTable {
+                    range: base_offset + symtab_range.start..base_offset + symtab_range.end,
+                    strtab_range: base_offset + strtab_range.start..base_offset + strtab_range.end,
+                    is_dynamic
+                });
+            }
+        }
+    }
+}
+
+// Compressed sections either have the `SHF_COMPRESSED` flag set and start
+// with an ELF compression header, or (in the older GNU format used by
+// the `.zdebug_*` sections) start with a "ZLIB" magic followed by
+// the big-endian 64-bit uncompressed size.
+// How much bigger than the compressed data we expect a decompressed section to usually be.
+const MAX_COMPRESSION_RATIO: u64 = 16;
+
+fn decompress_section( is_64_bit: bool, endianness: Endian, has_compression_header: bool, bytes: &[u8] ) -> io::Result< Vec< u8 > > {
+    let read_u32 = |bytes: &[u8]| match endianness {
+        Endian::Little => LittleEndian::read_u32( bytes ),
+        Endian::Big => BigEndian::read_u32( bytes )
+    };
+
+    let read_u64 = |bytes: &[u8]| match endianness {
+        Endian::Little => LittleEndian::read_u64( bytes ),
+        Endian::Big => BigEndian::read_u64( bytes )
+    };
+
+    let (kind, size, header_size) = if has_compression_header {
+        if is_64_bit {
+            if bytes.len() < 24 {
+                return Err( io::Error::new( io::ErrorKind::Other, "truncated compression header" ) );
+            }
+
+            (read_u32( &bytes[ 0..4 ] ), read_u64( &bytes[ 8..16 ] ), 24)
+        } else {
+            if bytes.len() < 12 {
+                return Err( io::Error::new( io::ErrorKind::Other, "truncated compression header" ) );
+            }
+
+            (read_u32( &bytes[ 0..4 ] ), read_u32( &bytes[ 4..8 ] ) as u64, 12)
+        }
+    } else {
+        if bytes.len() < 12 || &bytes[ 0..4 ] != b"ZLIB" {
+            return Err( io::Error::new( io::ErrorKind::Other, "missing ZLIB header" ) );
+        }
+
+        (ELFCOMPRESS_ZLIB, BigEndian::read_u64( &bytes[ 4..12 ] ), 12)
+    };
+
+    if kind != ELFCOMPRESS_ZLIB {
+        return Err( io::Error::new( io::ErrorKind::Other, format!( "unsupported compression type {}", kind ) ) );
+    }
+
+    // The size comes straight from the file, so we can't trust it to preallocate
+    // the whole output, nor to decompress an unbounded amount of data.
+    let compressed = &bytes[ header_size.. ];
+    let capacity = min( size, compressed.len() as u64 * MAX_COMPRESSION_RATIO );
+    let mut output = Vec::with_capacity( capacity as usize );
+    ZlibDecoder::new( compressed ).take( size.saturating_add( 1 ) ).read_to_end( &mut output )?;
+    if output.len() as u64 != size {
+        return Err( io::Error::new( io::ErrorKind::Other, format!( "decompressed size mismatch: expected {}, got {}", size, output.len() ) ) );
     }
+
+    Ok( output )
+}
+
+// MiniDebugInfo is an xz-compressed ELF file embedded in the `.gnu_debugdata`
+// section which contains the symbols that were stripped from the main symbol table.
+//
+// The decompressed ELF file is appended to `output`, and the ranges of its symbol tables
+// are offset by `base_offset` so that they point past the end of the original file.
+fn load_mini_debug_info( compressed: &[u8], base_offset: u64, output: &mut Vec< u8 >, symbol_tables: &mut Vec< SymbolTable > ) -> io::Result< () > {
+    let mut input = compressed;
+    let mut decompressed = Vec::new();
+    lzma_rs::xz_decompress( &mut input, &mut decompressed ).map_err( |err| io::Error::new( io::ErrorKind::Other, err.to_string() ) )?;
+
+    let base_offset = base_offset + output.len() as u64;
+    let mut embedded_symbol_tables = Vec::new();
+    {
+        let elf = elf::parse( &decompressed ).map_err( |err| io::Error::new( io::ErrorKind::Other, err ) )?;
+        parse_elf!( elf, |elf| {
+            find_symbol_tables( &elf, base_offset, &mut embedded_symbol_tables );
+        });
+    }
+
+    let length = decompressed.len() as u64;
+    embedded_symbol_tables.retain( |symbol_table| {
+        !symbol_table.is_dynamic &&
+        symbol_table.range.end <= base_offset + length &&
+        symbol_table.strtab_range.end <= base_offset + length
+    });
+
+    debug!( "Loaded {} symbol table(s) from MiniDebugInfo", embedded_symbol_tables.len() );
+
+    output.extend_from_slice( &decompressed );
+    symbol_tables.extend( embedded_symbol_tables );
+    Ok(())
+}
+
+#[test]
+fn test_decompress_section_with_bogus_size() {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    let data = vec![ 0xAA; 4096 ];
+    let mut encoder = ZlibEncoder::new( Vec::new(), Compression::default() );
+    encoder.write_all( &data ).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let section = |size: u64| {
+        let mut section = b"ZLIB".to_vec();
+        section.extend_from_slice( &[ 0; 8 ] );
+        BigEndian::write_u64( &mut section[ 4..12 ], size );
+        section.extend_from_slice( &compressed );
+        section
+    };
+
+    assert_eq!( decompress_section( true, Endian::Little, false, &section( data.len() as u64 ) ).unwrap(), data );
+    assert!( decompress_section( true, Endian::Little, false, &section( !0 ) ).is_err() );
+    assert!( decompress_section( true, Endian::Little, false, &section( 100 ) ).is_err() );
 }
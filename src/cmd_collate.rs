@@ -8,39 +8,49 @@ use std::ops::{Range, Index};
 use std::path::Path;
 use std::cmp::min;
 use std::fmt;
+use std::mem;
+use std::thread;
+use std::borrow::Cow;
 use std::error::Error;
 
 use speedy::Endianness;
 use cpp_demangle;
+use rustc_demangle;
 use regex::Regex;
 
-use archive::{Packet, BinaryId, Bitness, UserFrame, ArchiveReader};
+use archive::{Packet, BinaryId, Bitness, UserFrame, DwarfReg, ArchiveReader};
 use binary::{BinaryData, SymbolTable};
 use symbols::Symbols;
+use debug_info::{DebugInfo, SourceLocation};
 use utils::StableIndex;
 use range_map::RangeMap;
 use maps::Region;
 use kallsyms::{self, KernelSymbol};
-use address_space::{IAddressSpace, AddressSpace, BinarySource};
+use address_space::{IAddressSpace, IUnwinder, AddressSpace, BinarySource};
+use raw_data::CowRawData;
 use arch::{self, Architecture};
 use dwarf_regs::DwarfRegs;
+use unwind_context::{UnwindMode, StopReason};
 use stack_reader::StackReader;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
-enum Table {
+pub enum Table {
     Debug,
     Original,
     AddressSpace
 }
 
 #[derive(PartialEq, Eq, Debug, Hash)]
-enum Frame {
+pub enum Frame {
     Process( u32 ),
     Thread( u32 ),
     MainThread,
+    Guessed,
+    Truncated,
     User( u64 ),
     UserBinary( BinaryId, u64 ),
-    UserSymbol( BinaryId, usize, Table ),
+    UserSymbol( BinaryId, usize, Table, Option< SourceLocation > ),
+    UserInlined( BinaryId, usize, Option< SourceLocation > ),
     Kernel( u64 ),
     KernelSymbol( usize )
 }
@@ -61,7 +71,8 @@ struct Binary {
     symbol_tables_chunks: BinaryChunks,
     symbol_tables: Vec< SymbolTable >,
     symbols: Option< Symbols< BinaryChunks > >,
-    debug_symbols: Option< Symbols< BinaryData > >
+    debug_symbols: Option< Symbols< BinaryData > >,
+    debug_info: Option< DebugInfo >
 }
 
 struct BinaryChunks {
@@ -113,10 +124,14 @@ unsafe impl StableIndex for BinaryChunks {}
 
 fn decode_user_frame(
     omit_regex: &Option< Regex >,
-    address_space: Option< &Box< IAddressSpace > >,
+    line_numbers: bool,
+    expand_inlined_frames: bool,
+    address_space: Option< &dyn IAddressSpace >,
     process: &Process,
     binary_by_id: &HashMap< BinaryId, Binary >,
-    user_frame: &UserFrame
+    user_frame: &UserFrame,
+    is_topmost: bool,
+    output: &mut Vec< Frame >
 ) -> Option< Frame > {
     let address = user_frame.initial_address.unwrap_or( user_frame.address );
     if let Some( region ) = process.memory_regions.get_value( address ) {
@@ -127,6 +142,39 @@ fn decode_user_frame(
         };
 
         if let Some( binary ) = binary_by_id.get( &binary_id ) {
+            let mut location = None;
+            if let Some( debug_info ) = binary.debug_info.as_ref() {
+                if let Some( base_address ) = process.base_address_for_binary.get( &binary_id ) {
+                    // Every frame except the topmost one points to a return address,
+                    // which can already belong to the next line, so we look up
+                    // the address of the call instruction instead.
+                    let address = if is_topmost {
+                        user_frame.address
+                    } else {
+                        user_frame.address.wrapping_sub( 1 )
+                    };
+
+                    let address = address.wrapping_sub( *base_address );
+                    if line_numbers {
+                        location = debug_info.lookup_source_location( address );
+                    }
+
+                    if expand_inlined_frames {
+                        let mut inlined_frames = Vec::new();
+                        debug_info.lookup_inlined_frames( address, &mut inlined_frames );
+
+                        // The innermost inlined frame is at the current line, and every
+                        // other frame is at the line from which the next one was inlined.
+                        for &frame_index in inlined_frames.iter().rev() {
+                            output.push( Frame::UserInlined( binary_id.clone(), frame_index, location ) );
+                            if line_numbers {
+                                location = debug_info.get_inlined_frame( frame_index ).call_location;
+                            }
+                        }
+                    }
+                }
+            }
+
             if let Some( debug_symbols ) = binary.debug_symbols.as_ref() {
                 let base_address = process.base_address_for_binary.get( &binary_id ).expect( "no base address for binary" );
                 if let Some( index ) = debug_symbols.get_symbol_index( address - base_address ) {
@@ -137,7 +185,7 @@ fn decode_user_frame(
                         }
                     }
 
-                    return Some( Frame::UserSymbol( binary_id, index, Table::Debug ) );
+                    return Some( Frame::UserSymbol( binary_id, index, Table::Debug, location ) );
                 }
             }
 
@@ -151,7 +199,7 @@ fn decode_user_frame(
                         }
                     }
 
-                    return Some( Frame::UserSymbol( binary_id, index, Table::Original ) );
+                    return Some( Frame::UserSymbol( binary_id, index, Table::Original, location ) );
                 }
             }
 
@@ -164,7 +212,7 @@ fn decode_user_frame(
                         }
                     }
 
-                    return Some( Frame::UserSymbol( binary_id, index, Table::AddressSpace ) );
+                    return Some( Frame::UserSymbol( binary_id, index, Table::AddressSpace, location ) );
                 }
             }
 
@@ -179,18 +227,48 @@ fn get_basename( path: &str ) -> String {
     path[ path.rfind( "/" ).map( |index| index + 1 ).unwrap_or( 0 ).. ].to_owned()
 }
 
+fn is_mangled( symbol: &str ) -> bool {
+    symbol.starts_with( "_Z" ) || symbol.starts_with( "_R" )
+}
+
 struct DemangleCache {
+    strip_rust_hashes: bool,
     cache: HashMap< String, Option< String > >
 }
 
 impl DemangleCache {
-    fn new() -> Self {
+    fn new( strip_rust_hashes: bool ) -> Self {
         DemangleCache {
+            strip_rust_hashes,
             cache: HashMap::new()
         }
     }
 
-    fn demangle_uncached( symbol: &str ) -> Option< String > {
+    fn demangle_rust( symbol: &str, strip_rust_hashes: bool ) -> Option< String > {
+        let demangled = rustc_demangle::try_demangle( symbol ).ok()?;
+        let with_hash = format!( "{}", demangled );
+        let without_hash = format!( "{:#}", demangled );
+
+        // Legacy Rust symbols use the same mangling scheme as C++,
+        // so the only way to tell them apart is the trailing hash.
+        if !symbol.starts_with( "_R" ) && with_hash == without_hash {
+            return None;
+        }
+
+        if strip_rust_hashes {
+            Some( without_hash )
+        } else {
+            Some( with_hash )
+        }
+    }
+
+    fn demangle_uncached( symbol: &str, strip_rust_hashes: bool ) -> Option< String > {
+        if symbol.starts_with( "_R" ) || symbol.starts_with( "_ZN" ) {
+            if let Some( demangled ) = Self::demangle_rust( symbol, strip_rust_hashes ) {
+                return Some( demangled );
+            }
+        }
+
         if !symbol.starts_with( "_Z" ) {
             return None;
         }
@@ -201,7 +279,7 @@ impl DemangleCache {
     }
 
     fn demangle< 'a, 'b >( &'a mut self, symbol: &'b str ) -> Option< &'a str > {
-        if !symbol.starts_with( "_Z" ) {
+        if !is_mangled( symbol ) {
             return None;
         }
 
@@ -209,20 +287,19 @@ impl DemangleCache {
             return self.cache.get( symbol ).unwrap().as_ref().map( String::as_str );
         }
 
-        self.cache.insert( symbol.to_owned(), Self::demangle_uncached( symbol ) );
+        let demangled = Self::demangle_uncached( symbol, self.strip_rust_hashes );
+        self.cache.insert( symbol.to_owned(), demangled );
         self.cache.get( symbol ).unwrap().as_ref().map( String::as_str )
     }
 }
 
-fn look_through_debug_symbols( debug_symbols: &[&OsStr] ) -> HashMap< String, Symbols< BinaryData > > {
-    fn check( path: &Path, results: &mut HashMap< String, Symbols< BinaryData > > ) {
+fn look_through_debug_symbols( debug_symbols: &[&OsStr] ) -> HashMap< String, Arc< BinaryData > > {
+    fn check( path: &Path, results: &mut HashMap< String, Arc< BinaryData > > ) {
         match BinaryData::load_from_fs( None, path ) {
             Ok( binary ) => {
                 let filename = path.file_name().unwrap();
                 let filename = filename.to_string_lossy().into_owned();
-                let binary = Arc::new( binary );
-                let symbols = Symbols::load_from_binary_data( &binary );
-                results.insert( filename, symbols );
+                results.insert( filename, Arc::new( binary ) );
             },
             Err( error ) => {
                 warn!( "Cannot read debug symbols from {:?}: {}", path, error );
@@ -262,14 +339,17 @@ fn look_through_debug_symbols( debug_symbols: &[&OsStr] ) -> HashMap< String, Sy
 
 fn emit_frames(
     omit_regex: &Option< Regex >,
+    line_numbers: bool,
+    expand_inlined_frames: bool,
     kallsyms: &RangeMap< KernelSymbol >,
-    address_space: Option< &Box< IAddressSpace > >,
+    address_space: Option< &dyn IAddressSpace >,
     binary_by_id: &HashMap< BinaryId, Binary >,
     process: &Process,
     pid: u32,
     tid: u32,
     user_backtrace: &[UserFrame],
     kernel_backtrace: &[u64],
+    is_stack_truncated: bool,
     stacks: &mut HashMap< Vec< Frame >, u64 >
 ) {
     let mut frames = Vec::with_capacity( user_backtrace.len() + kernel_backtrace.len() + 1 );
@@ -281,8 +361,13 @@ fn emit_frames(
         }
     }
 
-    for user_frame in user_backtrace.iter() {
-        let frame = match decode_user_frame( omit_regex, address_space, process, &binary_by_id, user_frame ) {
+    for (nth_frame, user_frame) in user_backtrace.iter().enumerate() {
+        // Mark the point after which the frames were recovered by stack scanning.
+        if user_frame.is_guessed && (nth_frame == 0 || !user_backtrace[ nth_frame - 1 ].is_guessed) {
+            frames.push( Frame::Guessed );
+        }
+
+        let frame = match decode_user_frame( omit_regex, line_numbers, expand_inlined_frames, address_space, process, binary_by_id, user_frame, nth_frame == 0, &mut frames ) {
             Some( frame ) => frame,
             None => return // Was filtered out.
         };
@@ -290,6 +375,11 @@ fn emit_frames(
         frames.push( frame );
     }
 
+    // The outermost frames didn't fit into the stack dump, so mark where they'd be.
+    if is_stack_truncated {
+        frames.push( Frame::Truncated );
+    }
+
     if pid == tid {
         frames.push( Frame::MainThread );
     } else {
@@ -301,18 +391,173 @@ fn emit_frames(
     *stacks.entry( frames ).or_insert( 0 ) += 1;
 }
 
+// How many samples each thread gets to process at a time; the samples
+// are buffered until then, so this also bounds how much memory we use.
+const SAMPLES_PER_THREAD_IN_BATCH: usize = 256;
+
+enum PendingSample {
+    Unwound {
+        pid: u32,
+        tid: u32,
+        user_backtrace: Cow< 'static, [UserFrame] >,
+        kernel_backtrace: Cow< 'static, [u64] >,
+        is_stack_truncated: bool
+    },
+    Raw {
+        pid: u32,
+        tid: u32,
+        stack: CowRawData< 'static >,
+        regs: Cow< 'static, [DwarfReg] >,
+        kernel_backtrace: Cow< 'static, [u64] >,
+        is_stack_truncated: bool
+    }
+}
+
+// Everything needed to process the samples; none of it
+// changes while a batch of samples is being processed.
+struct SampleContext< 'a > {
+    omit_regex: &'a Option< Regex >,
+    line_numbers: bool,
+    expand_inlined_frames: bool,
+    force_stack_size: Option< u32 >,
+    kallsyms: &'a RangeMap< KernelSymbol >,
+    address_space: Option< &'a dyn IAddressSpace >,
+    binary_by_id: &'a HashMap< BinaryId, Binary >,
+    process: &'a Process
+}
+
+#[derive(Default)]
+struct PartialCollation {
+    stacks: HashMap< Vec< Frame >, u64 >,
+    stop_reasons: HashMap< (StopReason, Option< Frame >), u64 >
+}
+
+fn process_sample( ctx: &SampleContext, unwinder: Option< &mut Box< dyn IUnwinder > >, sample: &PendingSample, output: &mut PartialCollation ) {
+    match *sample {
+        PendingSample::Unwound { pid, tid, ref user_backtrace, ref kernel_backtrace, is_stack_truncated } => {
+            emit_frames(
+                ctx.omit_regex,
+                ctx.line_numbers,
+                ctx.expand_inlined_frames,
+                ctx.kallsyms,
+                None,
+                ctx.binary_by_id,
+                ctx.process,
+                pid,
+                tid,
+                user_backtrace,
+                kernel_backtrace,
+                is_stack_truncated,
+                &mut output.stacks
+            );
+        },
+        PendingSample::Raw { pid, tid, ref stack, ref regs, ref kernel_backtrace, is_stack_truncated } => {
+            let (address_space, unwinder) = match (ctx.address_space, unwinder) {
+                (Some( address_space ), Some( unwinder )) => (address_space, unwinder),
+                _ => return
+            };
+
+            let mut dwarf_regs = DwarfRegs::new();
+            for reg in regs.iter() {
+                dwarf_regs.append( reg.register, reg.value );
+            }
+
+            let mut stack = &stack.as_slice()[..];
+            if let Some( force_stack_size ) = ctx.force_stack_size {
+                stack = &stack[ 0..min( force_stack_size as usize, stack.len() ) ];
+            }
+
+            let reader = StackReader { stack: stack.into() };
+            let mut user_backtrace = Vec::new();
+            let stop_reason = unwinder.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
+
+            let last_frame = user_backtrace.last().and_then( |user_frame| {
+                let mut inlined_frames = Vec::new();
+                decode_user_frame( &None, false, false, Some( address_space ), ctx.process, ctx.binary_by_id, user_frame, user_backtrace.len() == 1, &mut inlined_frames )
+            });
+            *output.stop_reasons.entry( (stop_reason, last_frame) ).or_insert( 0 ) += 1;
+
+            emit_frames(
+                ctx.omit_regex,
+                ctx.line_numbers,
+                ctx.expand_inlined_frames,
+                ctx.kallsyms,
+                Some( address_space ),
+                ctx.binary_by_id,
+                ctx.process,
+                pid,
+                tid,
+                &user_backtrace,
+                kernel_backtrace,
+                is_stack_truncated,
+                &mut output.stacks
+            );
+        }
+    }
+}
+
+// The samples are independent of each other, so we split them evenly
+// between the threads, each of which unwinds with its own unwinder.
+fn process_samples(
+    ctx: &SampleContext,
+    unwinders: &mut [Box< dyn IUnwinder >],
+    thread_count: usize,
+    samples: &[PendingSample],
+    stacks: &mut HashMap< Vec< Frame >, u64 >,
+    stop_reasons: &mut HashMap< (StopReason, Option< Frame >), u64 >
+) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let chunk_size = samples.len().div_ceil( thread_count );
+    let mut unwinders = unwinders.iter_mut();
+    let partials: Vec< PartialCollation > = thread::scope( |scope| {
+        let handles: Vec< _ > = samples.chunks( chunk_size ).map( |chunk| {
+            let mut unwinder = unwinders.next();
+            scope.spawn( move || {
+                let mut partial = PartialCollation::default();
+                for sample in chunk {
+                    process_sample( ctx, unwinder.as_deref_mut(), sample, &mut partial );
+                }
+                partial
+            })
+        }).collect();
+
+        handles.into_iter().map( |handle| handle.join().unwrap() ).collect()
+    });
+
+    for partial in partials {
+        for (frames, count) in partial.stacks {
+            *stacks.entry( frames ).or_insert( 0 ) += count;
+        }
+
+        for (key, count) in partial.stop_reasons {
+            *stop_reasons.entry( key ).or_insert( 0 ) += count;
+        }
+    }
+}
+
 pub struct Args< 'a > {
     pub input_path: &'a OsStr,
     pub debug_symbols: Vec< &'a OsStr >,
     pub force_stack_size: Option< u32 >,
     pub omit_symbols: Vec< &'a str >,
     pub only_sample: Option< u64 >,
-    pub without_kernel_callstacks: bool
+    pub without_kernel_callstacks: bool,
+    pub line_numbers: bool,
+    pub expand_inlined_frames: bool,
+    pub strip_rust_hashes: bool,
+    pub unwind_mode: UnwindMode,
+    pub stack_scanning: bool,
+    pub thread_count: usize
 }
 
-struct Collation {
+pub struct Collation {
     kallsyms: RangeMap< KernelSymbol >,
     stacks: HashMap< Vec< Frame >, u64 >,
+    // Why the offline unwinding has stopped, keyed by the outermost frame we've reached.
+    pub stop_reasons: HashMap< (StopReason, Option< Frame >), u64 >,
     process_index_by_pid: HashMap< u32, usize >,
     processes: Vec< Process >,
     thread_names: HashMap< u32, String >,
@@ -320,11 +565,12 @@ struct Collation {
     address_space: Option< Box< IAddressSpace > >
 }
 
-fn collate( args: Args ) -> Result< Collation, Box< Error > > {
+pub fn collate( args: Args ) -> Result< Collation, Box< dyn Error > > {
     let fp = fs::File::open( args.input_path ).map_err( |err| format!( "cannot open {:?}: {}", args.input_path, err ) )?;
     let mut reader = ArchiveReader::new( fp ).validate_header().unwrap().skip_unknown();
 
     let mut stacks = HashMap::new();
+    let mut stop_reasons = HashMap::new();
     let mut processes: Vec< Process > = Vec::new();
     let mut process_index_by_pid: HashMap< u32, usize > = HashMap::new();
     let mut binary_by_id = HashMap::new();
@@ -334,10 +580,16 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
     let mut kallsyms = RangeMap::new();
     let mut address_space: Option< Box< IAddressSpace > > = None;
     let mut sample_counter = 0;
+    let mut is_next_sample_truncated = false;
+    let mut next_sample_guessed_frames: Option< Vec< u32 > > = None;
     let mut thread_names = HashMap::new();
     let mut binary_source_map = HashMap::new();
+    let mut pending_samples = Vec::new();
+    let mut unwinders = Vec::new();
+    let thread_count = args.thread_count;
+    assert!( thread_count > 0 );
 
-    let mut debug_symbols = look_through_debug_symbols( &args.debug_symbols );
+    let mut debug_binaries = look_through_debug_symbols( &args.debug_symbols );
 
     let omit_regex = if args.omit_symbols.is_empty() {
         None
@@ -347,17 +599,81 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
         Some( regex )
     };
 
+    // The samples are processed in batches, and since they depend on the state
+    // of the address space at the time they were taken every packet which can
+    // change it needs to wait for all of the samples before it to be processed.
+    macro_rules! flush_pending_samples {
+        () => {
+            if !pending_samples.is_empty() {
+                let has_raw_samples = pending_samples.iter().any( |sample| match *sample {
+                    PendingSample::Raw { .. } => true,
+                    _ => false
+                });
+
+                if has_raw_samples {
+                    if let Some( ref mut address_space ) = address_space {
+                        let process = &mut processes[0];
+                        if process.address_space_needs_reload {
+                            process.address_space_needs_reload = false;
+                            let binaries = binary_source_map.clone();
+                            let regions = process.memory_regions.values().cloned().collect();
+                            address_space.reload( binaries, regions, true );
+                            unwinders.clear();
+                        }
+
+                        while unwinders.len() < thread_count {
+                            unwinders.push( address_space.create_unwinder() );
+                        }
+                    }
+                }
+
+                let ctx = SampleContext {
+                    omit_regex: &omit_regex,
+                    line_numbers: args.line_numbers,
+                    expand_inlined_frames: args.expand_inlined_frames,
+                    force_stack_size: args.force_stack_size,
+                    kallsyms: &kallsyms,
+                    address_space: address_space.as_ref().map( |address_space| &**address_space ),
+                    binary_by_id: &binary_by_id,
+                    process: &processes[0]
+                };
+
+                process_samples( &ctx, &mut unwinders, thread_count, &pending_samples, &mut stacks, &mut stop_reasons );
+                pending_samples.clear();
+            }
+        }
+    }
+
     while let Some( packet ) = reader.next() {
         let packet = packet.unwrap();
+        match packet {
+            Packet::Sample { .. } | Packet::RawSample { .. } | Packet::SampleInfo { .. } | Packet::GuessedFrames { .. } | Packet::ThreadName { .. } => {},
+            _ => flush_pending_samples!()
+        }
+
         match packet {
             Packet::MachineInfo { architecture, bitness, endianness, .. } => {
                 address_space = match &*architecture {
                     arch::arm::Arch::NAME => Some( Box::new( AddressSpace::< arch::arm::Arch >::new() ) ),
                     arch::amd64::Arch::NAME => Some( Box::new( AddressSpace::< arch::amd64::Arch >::new() ) ),
+                    arch::mips::Arch::NAME => Some( Box::new( AddressSpace::< arch::mips::Arch >::new() ) ),
+                    arch::mipsel::Arch::NAME => Some( Box::new( AddressSpace::< arch::mipsel::Arch >::new() ) ),
+                    // Little endian MIPS64 used to be recorded under the big endian name.
+                    arch::mips64::Arch::NAME if endianness == Endianness::LittleEndian => Some( Box::new( AddressSpace::< arch::mips64el::Arch >::new() ) ),
                     arch::mips64::Arch::NAME => Some( Box::new( AddressSpace::< arch::mips64::Arch >::new() ) ),
+                    arch::mips64el::Arch::NAME => Some( Box::new( AddressSpace::< arch::mips64el::Arch >::new() ) ),
+                    arch::aarch64::Arch::NAME => Some( Box::new( AddressSpace::< arch::aarch64::Arch >::new() ) ),
+                    arch::x86::Arch::NAME => Some( Box::new( AddressSpace::< arch::x86::Arch >::new() ) ),
+                    arch::riscv64::Arch::NAME => Some( Box::new( AddressSpace::< arch::riscv64::Arch >::new() ) ),
                     _ => None
                 };
 
+                if let Some( ref mut address_space ) = address_space {
+                    address_space.set_unwind_mode( args.unwind_mode );
+                    address_space.set_stack_scanning( args.stack_scanning );
+                }
+                unwinders.clear();
+
                 machine_architecture = architecture.into_owned();
                 machine_bitness = bitness;
                 machine_endianness = endianness;
@@ -392,14 +708,23 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
                     symbol_tables_chunks: BinaryChunks::new(),
                     symbol_tables: Vec::new(),
                     symbols: None,
-                    debug_symbols: None
+                    debug_symbols: None,
+                    debug_info: None
                 };
 
                 debug!( "New binary: {:?}", binary.path );
                 if !debuglink.is_empty() {
                     let debuglink = String::from_utf8_lossy( &debuglink );
-                    if let Some( debug_symbols ) = debug_symbols.remove( &*debuglink ) {
-                        binary.debug_symbols = Some( debug_symbols );
+                    if let Some( debug_data ) = debug_binaries.remove( &*debuglink ) {
+                        binary.debug_symbols = Some( Symbols::load_from_binary_data( &debug_data ) );
+                        if args.line_numbers || args.expand_inlined_frames {
+                            binary.debug_info = DebugInfo::load( &debug_data );
+                        }
+
+                        if let Some( ref mut address_space ) = address_space {
+                            address_space.set_debug_binary( id.clone(), debug_data );
+                        }
+
                         debug!( "Found debug symbols for '{}': '{}'", binary.path, debuglink );
                     } else {
                         warn!( "Missing external debug symbols for '{}': '{}'", binary.path, debuglink );
@@ -518,7 +843,15 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
                     binary.symbol_tables_chunks.clear();
                 }
             },
-            Packet::Sample { user_backtrace, mut kernel_backtrace, pid, tid, .. } => {
+            Packet::SampleInfo { is_stack_truncated, .. } => {
+                is_next_sample_truncated = is_stack_truncated;
+            },
+            Packet::GuessedFrames { indexes } => {
+                next_sample_guessed_frames = Some( indexes.into_owned() );
+            },
+            Packet::Sample { mut user_backtrace, mut kernel_backtrace, pid, tid, .. } => {
+                let is_stack_truncated = mem::replace( &mut is_next_sample_truncated, false );
+                let guessed_frames = next_sample_guessed_frames.take();
                 if let Some( only_sample ) = args.only_sample {
                     if only_sample != sample_counter {
                         sample_counter += 1;
@@ -538,22 +871,28 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
                     kernel_backtrace = Vec::new().into();
                 }
 
-                emit_frames(
-                    &omit_regex,
-                    &kallsyms,
-                    None,
-                    &binary_by_id,
-                    process,
+                if let Some( guessed_frames ) = guessed_frames {
+                    let frames = user_backtrace.to_mut();
+                    for index in guessed_frames {
+                        if let Some( frame ) = frames.get_mut( index as usize ) {
+                            frame.is_guessed = true;
+                        }
+                    }
+                }
+
+                pending_samples.push( PendingSample::Unwound {
                     pid,
                     tid,
-                    &user_backtrace,
-                    &kernel_backtrace,
-                    &mut stacks
-                );
+                    user_backtrace,
+                    kernel_backtrace,
+                    is_stack_truncated
+                });
 
                 sample_counter += 1;
             },
             Packet::RawSample { mut kernel_backtrace, pid, tid, stack, regs, .. } => {
+                let is_stack_truncated = mem::replace( &mut is_next_sample_truncated, false );
+                next_sample_guessed_frames = None;
                 if let Some( only_sample ) = args.only_sample {
                     if only_sample != sample_counter {
                         sample_counter += 1;
@@ -563,7 +902,7 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
 
                 debug!( "Sample #{}", sample_counter );
 
-                let process = &mut processes[0];
+                let process = &processes[0];
                 if process.pid != pid {
                     debug!( "Sample #{} is from different process with PID {}, skipping!", sample_counter, pid );
                     continue;
@@ -573,46 +912,29 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
                     kernel_backtrace = Vec::new().into();
                 }
 
-                if let Some( ref mut address_space ) = address_space {
-                    if process.address_space_needs_reload {
-                        process.address_space_needs_reload = false;
-                        let binaries = binary_source_map.clone();
-                        let regions = process.memory_regions.values().cloned().collect();
-                        address_space.reload( binaries, regions, true );
-                    }
-
-                    let mut dwarf_regs = DwarfRegs::new();
-                    for reg in regs.iter() {
-                        dwarf_regs.append( reg.register, reg.value );
-                    }
-
-                    let mut stack = &stack.as_slice()[..];
-                    if let Some( force_stack_size ) = args.force_stack_size {
-                        stack = &stack[ 0..min( force_stack_size as usize, stack.len() ) ];
-                    }
-
-                    let reader = StackReader { stack: stack.into() };
-                    let mut user_backtrace = Vec::new();
-                    address_space.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
-
-                    emit_frames(
-                        &omit_regex,
-                        &kallsyms,
-                        Some( address_space ),
-                        &binary_by_id,
-                        process,
+                if address_space.is_some() {
+                    pending_samples.push( PendingSample::Raw {
                         pid,
                         tid,
-                        &user_backtrace,
-                        &kernel_backtrace,
-                        &mut stacks
-                    );
+                        stack,
+                        regs,
+                        kernel_backtrace,
+                        is_stack_truncated
+                    });
                 }
 
                 sample_counter += 1;
             },
             Packet::BinaryBlob { id, path, data } => {
                 let data = BinaryData::load_from_owned_bytes( &String::from_utf8_lossy( &path ), id.clone(), data.into_owned() ).unwrap();
+                if args.line_numbers || args.expand_inlined_frames {
+                    if let Some( binary ) = binary_by_id.get_mut( &id ) {
+                        if binary.debug_info.is_none() {
+                            binary.debug_info = DebugInfo::load( &data );
+                        }
+                    }
+                }
+
                 let source = BinarySource::Preloaded( Arc::new( data ) );
                 binary_source_map.insert( id, source );
             },
@@ -630,12 +952,18 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
             },
             _ => {}
         }
+
+        if pending_samples.len() >= thread_count * SAMPLES_PER_THREAD_IN_BATCH {
+            flush_pending_samples!();
+        }
     }
 
+    flush_pending_samples!();
 
     Ok( Collation {
         kallsyms,
         stacks,
+        stop_reasons,
         process_index_by_pid,
         processes,
         thread_names,
@@ -644,16 +972,16 @@ fn collate( args: Args ) -> Result< Collation, Box< Error > > {
     })
 }
 
-struct Decoder< 'a > {
+pub struct Decoder< 'a > {
     collation: &'a Collation,
     demangle_cache: DemangleCache
 }
 
 impl< 'a > Decoder< 'a > {
-    fn new( collation: &'a Collation ) -> Self {
+    pub fn new( collation: &'a Collation, strip_rust_hashes: bool ) -> Self {
         Decoder {
             collation,
-            demangle_cache: DemangleCache::new()
+            demangle_cache: DemangleCache::new( strip_rust_hashes )
         }
     }
 
@@ -668,6 +996,14 @@ impl< 'a > Decoder< 'a > {
         (self.demangle_cache.demangle( symbol ).unwrap_or( symbol ), binary)
     }
 
+    fn get_inlined_function( &mut self, binary_id: &BinaryId, frame_index: usize ) -> (&str, &Binary) {
+        let binary = self.collation.binary_by_id.get( binary_id ).unwrap();
+        let debug_info = binary.debug_info.as_ref().unwrap();
+        let name = debug_info.get_name( debug_info.get_inlined_frame( frame_index ).name_index );
+
+        (self.demangle_cache.demangle( name ).unwrap_or( name ), binary)
+    }
+
     fn get_kernel_symbol( &self, symbol_index: usize ) -> &KernelSymbol {
         self.collation.kallsyms.get_value_by_index( symbol_index ).unwrap()
     }
@@ -676,6 +1012,15 @@ impl< 'a > Decoder< 'a > {
         self.collation.binary_by_id.get( binary_id ).unwrap()
     }
 
+    pub fn get_binary_basename( &self, frame: &Frame ) -> Option< &str > {
+        match *frame {
+            Frame::UserSymbol( ref binary_id, .. ) |
+            Frame::UserInlined( ref binary_id, .. ) |
+            Frame::UserBinary( ref binary_id, .. ) => Some( &self.get_binary( binary_id ).basename ),
+            _ => None
+        }
+    }
+
     fn get_thread_name( &self, tid: u32 ) -> Option< &str > {
         self.collation.thread_names.get( &tid ).map( |str| str.as_str() )
     }
@@ -684,7 +1029,7 @@ impl< 'a > Decoder< 'a > {
         self.collation.process_index_by_pid.get( &pid ).map( |&index| &self.collation.processes[ index ] )
     }
 
-    fn write_frame< T: fmt::Write >( &mut self, output: &mut T, frame: &Frame ) {
+    pub fn write_frame< T: fmt::Write >( &mut self, output: &mut T, frame: &Frame ) {
         match *frame {
             Frame::Process( pid ) => {
                 if let Some( process ) = self.get_process( pid ) {
@@ -696,6 +1041,12 @@ impl< 'a > Decoder< 'a > {
             Frame::MainThread => {
                 write!( output, "[MAIN_THREAD]" ).unwrap()
             },
+            Frame::Guessed => {
+                write!( output, "[GUESSED]" ).unwrap()
+            },
+            Frame::Truncated => {
+                write!( output, "[TRUNCATED]" ).unwrap()
+            },
             Frame::Thread( tid ) => {
                 if let Some( name ) = self.get_thread_name( tid ) {
                     write!( output, "{} [THREAD={}]", name, tid ).unwrap()
@@ -703,9 +1054,21 @@ impl< 'a > Decoder< 'a > {
                     write!( output, "[THREAD={}]", tid ).unwrap()
                 }
             },
-            Frame::UserSymbol( ref binary_id, symbol_index, table ) => {
+            Frame::UserSymbol( ref binary_id, symbol_index, table, location ) => {
                 let (symbol, binary) = self.get_user_symbol( binary_id, symbol_index, table );
-                write!( output, "{} [{}]", symbol, binary.basename ).unwrap()
+                write!( output, "{} [{}]", symbol, binary.basename ).unwrap();
+                if let Some( location ) = location {
+                    let file_name = binary.debug_info.as_ref().unwrap().get_file_name( location.file_index );
+                    write!( output, " ({}:{})", file_name, location.line ).unwrap();
+                }
+            },
+            Frame::UserInlined( ref binary_id, frame_index, location ) => {
+                let (name, binary) = self.get_inlined_function( binary_id, frame_index );
+                write!( output, "{} [{}] [inlined]", name, binary.basename ).unwrap();
+                if let Some( location ) = location {
+                    let file_name = binary.debug_info.as_ref().unwrap().get_file_name( location.file_index );
+                    write!( output, " ({}:{})", file_name, location.line ).unwrap();
+                }
             },
             Frame::UserBinary( ref binary_id, addr ) => {
                 let binary = self.get_binary( binary_id );
@@ -730,9 +1093,10 @@ impl< 'a > Decoder< 'a > {
 }
 
 pub fn main( args: Args ) -> Result< (), Box< Error > > {
+    let strip_rust_hashes = args.strip_rust_hashes;
     let collation = collate( args )?;
 
-    let mut decoder = Decoder::new( &collation );
+    let mut decoder = Decoder::new( &collation, strip_rust_hashes );
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
 
@@ -760,20 +1124,32 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
 
 #[cfg(test)]
 mod test {
-    use super::{Args, Frame, Decoder, Collation, collate};
+    use super::{Args, Frame, Decoder, Collation, DemangleCache, collate};
+    use archive::Packet;
+    use unwind_context::{UnwindMode, StopReason};
     use std::path::Path;
     use env_logger;
 
     fn load( filename: &str ) -> Collation {
-        let _ = env_logger::try_init();
         let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "artifacts" ).join( filename );
+        collate_with_threads( &path, 4 )
+    }
+
+    fn collate_with_threads( path: &Path, thread_count: usize ) -> Collation {
+        let _ = env_logger::try_init();
         let collation = collate( Args {
             input_path: path.as_os_str(),
             debug_symbols: vec![],
             force_stack_size: None,
             omit_symbols: vec![],
             only_sample: None,
-            without_kernel_callstacks: false
+            without_kernel_callstacks: false,
+            line_numbers: false,
+            expand_inlined_frames: false,
+            strip_rust_hashes: false,
+            unwind_mode: UnwindMode::Dwarf,
+            stack_scanning: false,
+            thread_count
         }).unwrap();
 
         collation
@@ -796,6 +1172,12 @@ mod test {
             Frame::MainThread => {
                 format!( "[main_thread]" )
             },
+            Frame::Guessed => {
+                format!( "[guessed]" )
+            },
+            Frame::Truncated => {
+                format!( "[truncated]" )
+            },
             Frame::Thread( tid ) => {
                 if let Some( name ) = decoder.get_thread_name( tid ) {
                     format!( "[thread:{}]", name )
@@ -803,10 +1185,14 @@ mod test {
                     format!( "[thread]" )
                 }
             },
-            Frame::UserSymbol( ref binary_id, symbol_index, table ) => {
+            Frame::UserSymbol( ref binary_id, symbol_index, table, _ ) => {
                 let (symbol, binary) = decoder.get_user_symbol( binary_id, symbol_index, table );
                 format!( "{}:{}", symbol, binary.basename )
             },
+            Frame::UserInlined( ref binary_id, frame_index, _ ) => {
+                let (name, binary) = decoder.get_inlined_function( binary_id, frame_index );
+                format!( "{}:{}:inlined", name, binary.basename )
+            },
             Frame::UserBinary( ref binary_id, _ ) => {
                 let binary = decoder.get_binary( binary_id );
                 format!( "?:{}", binary.basename )
@@ -905,7 +1291,7 @@ mod test {
     #[test]
     fn collate_arm_hot_spot_usleep_in_a_loop_no_fp() {
         let collation = load( "arm-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -927,7 +1313,7 @@ mod test {
     #[test]
     fn collate_arm_perfect_unwinding_usleep_in_a_loop_no_fp() {
         let collation = load( "arm-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -944,7 +1330,7 @@ mod test {
     #[test]
     fn collate_arm_hot_spot_usleep_in_a_loop_fp() {
         let collation = load( "arm-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -966,7 +1352,7 @@ mod test {
     #[test]
     fn collate_arm_perfect_unwinding_usleep_in_a_loop_fp() {
         let collation = load( "arm-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -983,7 +1369,7 @@ mod test {
     #[test]
     fn collate_amd64_hot_spot_usleep_in_a_loop_no_fp() {
         let collation = load( "amd64-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -1005,7 +1391,7 @@ mod test {
     #[test]
     fn collate_amd64_perfect_unwinding_usleep_in_a_loop_no_fp() {
         let collation = load( "amd64-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -1022,7 +1408,7 @@ mod test {
     #[test]
     fn collate_amd64_hot_spot_usleep_in_a_loop_no_fp_online() {
         let collation = load( "amd64-usleep_in_a_loop_no_fp_online.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -1044,7 +1430,7 @@ mod test {
     #[test]
     fn collate_amd64_hot_spot_usleep_in_a_loop_fp() {
         let collation = load( "amd64-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -1066,7 +1452,7 @@ mod test {
     #[test]
     fn collate_amd64_perfect_unwinding_usleep_in_a_loop_fp() {
         let collation = load( "amd64-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -1083,7 +1469,7 @@ mod test {
     #[test]
     fn collate_amd64_pthread_cond_wait() {
         let collation = load( "amd64-pthread_cond_wait.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref foo, _) in collation.stacks.iter() {
             println!( "{:?}", frame_to_str( &mut decoder, &foo[ foo.len() - 2 ] ) );
@@ -1123,7 +1509,7 @@ mod test {
     #[test]
     fn collate_mips64_hot_spot_usleep_in_a_loop_no_fp() {
         let collation = load( "mips64-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 50 );
@@ -1145,7 +1531,7 @@ mod test {
     #[test]
     fn collate_mips64_perfect_unwinding_usleep_in_a_loop_no_fp() {
         let collation = load( "mips64-usleep_in_a_loop_no_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -1162,7 +1548,7 @@ mod test {
     #[test]
     fn collate_mips64_hot_spot_usleep_in_a_loop_fp() {
         let collation = load( "mips64-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         let (frames, count) = most_frequent_trace( &decoder );
         assert!( count >= 100 );
@@ -1184,7 +1570,7 @@ mod test {
     #[test]
     fn collate_mips64_perfect_unwinding_usleep_in_a_loop_fp() {
         let collation = load( "mips64-usleep_in_a_loop_fp.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref frames, _) in &decoder.collation.stacks {
             assert_backtrace( &mut decoder, &frames, &[
@@ -1201,7 +1587,7 @@ mod test {
     #[test]
     fn collate_mips64_pthread_cond_wait() {
         let collation = load( "mips64-pthread_cond_wait.nperf" );
-        let mut decoder = Decoder::new( &collation );
+        let mut decoder = Decoder::new( &collation, false );
 
         for (ref foo, _) in collation.stacks.iter() {
             println!( "{:?}", frame_to_str( &mut decoder, &foo[ foo.len() - 2 ] ) );
@@ -1235,6 +1621,294 @@ mod test {
         ]);
     }
 
+    const INLINED_FUNCTIONS_PID: u32 = 1000;
+
+    fn inlined_functions_sample( timestamp: u64, addresses: &[u64] ) -> Packet< 'static > {
+        use std::borrow::Cow;
+        use archive::UserFrame;
+
+        Packet::Sample {
+            timestamp,
+            pid: INLINED_FUNCTIONS_PID,
+            tid: INLINED_FUNCTIONS_PID,
+            cpu: 0,
+            kernel_backtrace: Cow::Owned( Vec::new() ),
+            user_backtrace: Cow::Owned( addresses.iter().map( |&address| UserFrame { address, initial_address: None, is_guessed: false } ).collect() )
+        }
+    }
+
+    // Collates the given samples of the `amd64-inlined_functions` binary
+    // and renders each stack starting from the outermost frame.
+    fn collate_amd64_inlined_functions_samples( name: &str, samples: Vec< Packet< 'static > > ) -> Vec< Vec< String > > {
+        use std::borrow::Cow;
+        use std::env;
+        use std::fs;
+        use std::process;
+        use speedy::{Writable, Endianness};
+        use archive::{FramedPacket, BinaryId, Bitness, ARCHIVE_MAGIC, ARCHIVE_VERSION};
+        use arch::{self, Architecture};
+
+        let debug_symbols = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "bin" ).join( "amd64-inlined_functions" );
+        let binary = include_bytes!( "../test-data/bin/amd64-inlined_functions" );
+        let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+        let pid = INLINED_FUNCTIONS_PID;
+        let range = 0x400000..0x404000;
+
+        let mut packets = vec![
+            Packet::Header { magic: ARCHIVE_MAGIC, version: ARCHIVE_VERSION },
+            Packet::MachineInfo {
+                cpu_count: 1,
+                bitness: Bitness::B64,
+                endianness: Endianness::LittleEndian,
+                architecture: arch::amd64::Arch::NAME.into()
+            },
+            Packet::ProcessInfo { pid, executable: Cow::Borrowed( b"amd64-inlined_functions" ), binary_id: id.clone() },
+            Packet::BinaryInfo {
+                id: id.clone(),
+                is_shared_object: false,
+                symbol_table_count: 0,
+                path: Cow::Borrowed( b"amd64-inlined_functions" ),
+                debuglink: Cow::Borrowed( b"amd64-inlined_functions" )
+            },
+            Packet::BinaryBlob { id: id.clone(), path: Cow::Borrowed( b"amd64-inlined_functions" ), data: Cow::Borrowed( binary ) },
+            Packet::MemoryRegionMap {
+                pid,
+                range: range.clone(),
+                is_read: true,
+                is_write: false,
+                is_executable: true,
+                is_shared: false,
+                file_offset: 0,
+                inode: 1,
+                major: 0,
+                minor: 0,
+                name: Cow::Borrowed( b"amd64-inlined_functions" )
+            },
+            Packet::BinaryMap { pid, id: id.clone(), base_address: 0 }
+        ];
+        packets.extend( samples );
+
+        let mut bytes = Vec::new();
+        for packet in packets {
+            bytes.extend( FramedPacket::Known( packet ).write_to_vec( Endianness::LittleEndian ).unwrap() );
+        }
+
+        let path = env::temp_dir().join( format!( "nperf-collate-{}-{}.nperf", name, process::id() ) );
+        fs::write( &path, &bytes ).unwrap();
+        let _ = env_logger::try_init();
+        let collation = collate( Args {
+            input_path: path.as_os_str(),
+            debug_symbols: vec![ debug_symbols.as_os_str() ],
+            force_stack_size: None,
+            omit_symbols: vec![],
+            only_sample: None,
+            without_kernel_callstacks: false,
+            line_numbers: true,
+            expand_inlined_frames: true,
+            strip_rust_hashes: false,
+            unwind_mode: UnwindMode::Dwarf,
+            stack_scanning: false,
+            thread_count: 1
+        }).unwrap();
+        fs::remove_file( &path ).unwrap();
+
+        let mut decoder = Decoder::new( &collation, false );
+        let mut stacks: Vec< Vec< String > > = collation.stacks.keys().map( |frames| {
+            frames.iter().rev().map( |frame| {
+                let mut line = String::new();
+                decoder.write_frame( &mut line, frame );
+                line
+            }).collect()
+        }).collect();
+        stacks.sort();
+        stacks
+    }
+
+    #[test]
+    fn collate_amd64_inlined_functions() {
+        // The call to `main` is the last instruction of `_start`, so its return
+        // address would point past its end.
+        let stacks = collate_amd64_inlined_functions_samples( "inlined-functions", vec![
+            // inner() -> outer() -> main() -> _start()
+            inlined_functions_sample( 1, &[ 0x401026, 0x401078 ] ),
+            // inner() -> outer() -> nested() -> main() -> _start()
+            inlined_functions_sample( 2, &[ 0x401056, 0x40103d, 0x401078 ] )
+        ]);
+
+        assert_eq!( stacks, vec![
+            vec![
+                "amd64-inlined_functions [PID=1000]",
+                "[MAIN_THREAD]",
+                "_start [amd64-inlined_functions] (amd64-inlined_functions.c:35)",
+                "main [amd64-inlined_functions] (amd64-inlined_functions.c:29)",
+                "outer [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:16)",
+                "inner [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:9)"
+            ],
+            vec![
+                "amd64-inlined_functions [PID=1000]",
+                "[MAIN_THREAD]",
+                "_start [amd64-inlined_functions] (amd64-inlined_functions.c:35)",
+                "main [amd64-inlined_functions] (amd64-inlined_functions.c:30)",
+                "nested.0.constprop.0 [amd64-inlined_functions] (amd64-inlined_functions.c:25)",
+                "outer [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:16)",
+                "inner [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:9)"
+            ]
+        ]);
+    }
+
+    #[test]
+    fn collate_guessed_frames() {
+        use std::borrow::Cow;
+
+        let stacks = collate_amd64_inlined_functions_samples( "guessed-frames", vec![
+            Packet::GuessedFrames { indexes: Cow::Owned( vec![ 1, 2 ] ) },
+            inlined_functions_sample( 1, &[ 0x401056, 0x40103d, 0x401078 ] )
+        ]);
+
+        assert_eq!( stacks, vec![
+            vec![
+                "amd64-inlined_functions [PID=1000]",
+                "[MAIN_THREAD]",
+                "_start [amd64-inlined_functions] (amd64-inlined_functions.c:35)",
+                "main [amd64-inlined_functions] (amd64-inlined_functions.c:30)",
+                "[GUESSED]",
+                "nested.0.constprop.0 [amd64-inlined_functions] (amd64-inlined_functions.c:25)",
+                "outer [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:16)",
+                "inner [amd64-inlined_functions] [inlined] (amd64-inlined_functions.c:9)"
+            ]
+        ]);
+    }
+
+    #[test]
+    fn collate_in_parallel_across_unmaps() {
+        use std::borrow::Cow;
+        use std::env;
+        use std::fs;
+        use std::process;
+        use byteorder::{ByteOrder, LittleEndian};
+        use speedy::{Writable, Endianness};
+        use archive::{FramedPacket, Packet, BinaryId, Bitness, DwarfReg, ARCHIVE_MAGIC, ARCHIVE_VERSION};
+        use raw_data::CowRawData;
+        use arch::{self, Architecture};
+        use arch::x86::dwarf;
+
+        let binary = include_bytes!( "../test-data/bin/x86-minimal_usleep_in_a_loop_no_fp" );
+        let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+        let pid = 1000;
+        let range = 0x8048000..0x8049000;
+
+        // function() -> main() -> _start()
+        let stack_address = 0x7fff0000;
+        let mut stack = vec![ 0; 64 ];
+        LittleEndian::write_u32( &mut stack[ 28.. ], 0x80480b5 );
+        LittleEndian::write_u32( &mut stack[ 48.. ], 0 );
+        LittleEndian::write_u32( &mut stack[ 52.. ], 0x80480be );
+
+        let regs = vec![
+            DwarfReg { register: dwarf::RETURN_ADDRESS, value: 0x80480da },
+            DwarfReg { register: dwarf::ESP, value: stack_address },
+            DwarfReg { register: dwarf::EBP, value: stack_address + 48 }
+        ];
+
+        let sample = |timestamp: u64| Packet::RawSample {
+            timestamp,
+            pid,
+            tid: pid,
+            cpu: 0,
+            kernel_backtrace: Cow::Owned( Vec::new() ),
+            stack: CowRawData::Owned( stack.clone() ),
+            regs: Cow::Borrowed( &regs )
+        };
+
+        let mut packets = vec![
+            Packet::Header { magic: ARCHIVE_MAGIC, version: ARCHIVE_VERSION },
+            Packet::MachineInfo {
+                cpu_count: 1,
+                bitness: Bitness::B32,
+                endianness: Endianness::LittleEndian,
+                architecture: arch::x86::Arch::NAME.into()
+            },
+            Packet::ProcessInfo { pid, executable: Cow::Borrowed( b"file" ), binary_id: id.clone() },
+            Packet::BinaryInfo {
+                id: id.clone(),
+                is_shared_object: false,
+                symbol_table_count: 0,
+                path: Cow::Borrowed( b"file" ),
+                debuglink: Cow::Borrowed( b"" )
+            },
+            Packet::BinaryBlob { id: id.clone(), path: Cow::Borrowed( b"file" ), data: Cow::Borrowed( binary ) },
+            Packet::MemoryRegionMap {
+                pid,
+                range: range.clone(),
+                is_read: true,
+                is_write: false,
+                is_executable: true,
+                is_shared: false,
+                file_offset: 0,
+                inode: 1,
+                major: 0,
+                minor: 0,
+                name: Cow::Borrowed( b"file" )
+            },
+            Packet::BinaryMap { pid, id: id.clone(), base_address: range.start }
+        ];
+
+        // Enough samples to be processed in more than one batch.
+        packets.extend( (0..1500).map( &sample ) );
+        packets.push( Packet::BinaryUnmap { pid, id: id.clone(), base_address: range.start } );
+        packets.push( Packet::MemoryRegionUnmap { pid, range: range.clone() } );
+        packets.extend( (1500..2000).map( &sample ) );
+
+        let mut bytes = Vec::new();
+        for packet in packets {
+            bytes.extend( FramedPacket::Known( packet ).write_to_vec( Endianness::LittleEndian ).unwrap() );
+        }
+
+        let path = env::temp_dir().join( format!( "nperf-collate-in-parallel-{}.nperf", process::id() ) );
+        fs::write( &path, &bytes ).unwrap();
+        let sequential = collate_with_threads( &path, 1 );
+        let parallel = collate_with_threads( &path, 4 );
+        fs::remove_file( &path ).unwrap();
+
+        assert_eq!( parallel.stacks, sequential.stacks );
+        assert_eq!( parallel.stop_reasons, sequential.stop_reasons );
+
+        // The samples taken after the binary was unmapped can't be unwound.
+        let count = |reason: StopReason| parallel.stop_reasons.iter().filter( |&(key, _)| key.0 == reason ).map( |(_, count)| count ).sum::< u64 >();
+        assert_eq!( count( StopReason::EndOfStack ), 1500 );
+        assert_eq!( count( StopReason::MissingBinary ), 500 );
+        assert_eq!( parallel.stacks.values().sum::< u64 >(), 2000 );
+    }
+
+    #[test]
+    fn demangle_cpp_symbols() {
+        let mut cache = DemangleCache::new( false );
+        assert_eq!( cache.demangle( "_ZN3foo3barEv" ), Some( "foo::bar()" ) );
+        assert_eq!( cache.demangle( "_ZN3foo3barE" ), Some( "foo::bar" ) );
+        assert_eq!( cache.demangle( "main" ), None );
+    }
+
+    #[test]
+    fn demangle_rust_legacy_symbols() {
+        let mut cache = DemangleCache::new( false );
+        assert_eq!( cache.demangle( "_ZN4core3fmt5write17h0123456789abcdefE" ), Some( "core::fmt::write::h0123456789abcdef" ) );
+        assert_eq!( cache.demangle( "_ZN58_$LT$alloc..string..String$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE" ), Some( "<alloc::string::String as core::fmt::Debug>::fmt::h0123456789abcdef" ) );
+
+        let mut cache = DemangleCache::new( true );
+        assert_eq!( cache.demangle( "_ZN4core3fmt5write17h0123456789abcdefE" ), Some( "core::fmt::write" ) );
+        assert_eq!( cache.demangle( "_ZN3foo3barEv" ), Some( "foo::bar()" ) );
+    }
+
+    #[test]
+    fn demangle_rust_v0_symbols() {
+        let mut cache = DemangleCache::new( false );
+        assert_eq!( cache.demangle( "_RNvCs15kBYyAo9fc_7mycrate7example" ), Some( "mycrate[ca63f166dbe9294]::example" ) );
+        assert_eq!( cache.demangle( "_RNotAValidSymbol" ), None );
+
+        let mut cache = DemangleCache::new( true );
+        assert_eq!( cache.demangle( "_RNvCs15kBYyAo9fc_7mycrate7example" ), Some( "mycrate::example" ) );
+    }
+
     #[cfg(feature = "benches")]
     use test;
 
@@ -1299,6 +1973,7 @@ mod test {
         let regions = memory_regions.values().cloned().collect();
         address_space.reload( binary_source_map, regions, true );
 
+        let mut unwinder = address_space.create_unwinder();
         let mut user_backtrace = Vec::new();
         let mut dwarf_regs = DwarfRegs::new();
 
@@ -1312,7 +1987,7 @@ mod test {
                 let mut stack = &stack.as_slice()[..];
                 let reader = StackReader { stack: stack.into() };
 
-                address_space.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
+                unwinder.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
                 user_backtrace.clear();
             }
         });
@@ -17,6 +17,8 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
 
     let mut is_valid = false;
     let mut metadata = Metadata::default();
+    let mut dynamic_stack_sizes = Vec::new();
+    let mut truncated_sample_count = 0;
 
     while let Some( packet ) = reader.next() {
         let packet = packet.unwrap();
@@ -48,6 +50,12 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
                     debuglink
                 });
             },
+            Packet::SampleInfo { dynamic_stack_size, is_stack_truncated, .. } => {
+                dynamic_stack_sizes.push( dynamic_stack_size );
+                if is_stack_truncated {
+                    truncated_sample_count += 1;
+                }
+            },
             _ => {}
         }
     }
@@ -56,6 +64,19 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
         return Err( format!( "input {:?} is not a valid archive", args.input_path ).into() )
     }
 
+    if !dynamic_stack_sizes.is_empty() {
+        dynamic_stack_sizes.sort();
+        let percentile = |percent: usize| dynamic_stack_sizes[ (dynamic_stack_sizes.len() - 1) * percent / 100 ];
+        metadata.stack_usage = Some( metadata::StackUsage {
+            sample_count: dynamic_stack_sizes.len() as u64,
+            truncated_sample_count,
+            dynamic_stack_size_p50: percentile( 50 ),
+            dynamic_stack_size_p90: percentile( 90 ),
+            dynamic_stack_size_p99: percentile( 99 ),
+            dynamic_stack_size_max: percentile( 100 )
+        });
+    }
+
     println!( "{}", serde_json::to_string_pretty( &metadata ).unwrap() );
     Ok(())
 }
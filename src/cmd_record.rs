@@ -4,12 +4,13 @@ use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
 use std::borrow::Cow;
 use std::slice;
-use std::time::Instant;
+use std::time::{Instant, Duration};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::MetadataExt;
 use std::ops::{Deref, DerefMut, Range};
 use std::error::Error;
 use std::path::Path;
+use std::sync::mpsc;
 
 use chrono::prelude::*;
 use speedy::{Writable, Endianness};
@@ -21,15 +22,16 @@ use arch::{self, Architecture};
 use perf::{Event, CommEvent, Mmap2Event, EventSource};
 use perf_group::PerfGroup;
 use perf_arch::IntoDwarfRegs;
-use address_space::{IAddressSpace, AddressSpace, BinarySource};
-use utils::{SigintHandler, read_file, read_string_lossy, get_major, get_minor, get_ms};
+use address_space::{IAddressSpace, AddressSpace, AddressSpaceSnapshot, Unwinder, BinarySource};
+use utils::{SigintHandler, Timer, read_file, read_string_lossy, get_major, get_minor, get_ms};
 use archive::{FramedPacket, Packet, BinaryId, Bitness, DwarfReg, ARCHIVE_MAGIC, ARCHIVE_VERSION};
-use execution_queue::ExecutionQueue;
+use execution_queue::{ExecutionQueue, ExecutionPool};
 use ps::{wait_for_process, find_process};
-use stack_reader::StackReader;
+use stack_reader::{StackReader, LiveStackReader};
 use binary::BinaryData;
 use range_map::RangeMap;
 use dwarf_regs::DwarfRegs;
+use unwind_context::UnwindMode;
 
 pub enum TargetProcess {
     ByPid( u32 ),
@@ -444,16 +446,13 @@ impl PacketWriter {
             debug!( "Writing symbols of '{}'...", binary.name() );
             let mut strtab_done = HashSet::new();
             for symbol_table in binary.symbol_tables() {
-                let range = symbol_table.range.start as usize..symbol_table.range.end as usize;
-
                 if !strtab_done.contains( &symbol_table.strtab_range ) {
                     strtab_done.insert( symbol_table.strtab_range.clone() );
 
-                    let strtab_range = symbol_table.strtab_range.start as usize..symbol_table.strtab_range.end as usize;
                     self.write_packet( Packet::StringTable {
                         binary_id: binary.id().clone(),
                         offset: symbol_table.strtab_range.start,
-                        data: binary.as_bytes()[ strtab_range ].into()
+                        data: binary[ symbol_table.strtab_range.clone() ].into()
                     })?;
                 }
 
@@ -462,7 +461,7 @@ impl PacketWriter {
                     offset: symbol_table.range.start,
                     string_table_offset: symbol_table.strtab_range.start,
                     is_dynamic: symbol_table.is_dynamic,
-                    data: binary.as_bytes()[ range ].into()
+                    data: binary[ symbol_table.range.clone() ].into()
                 })?;
             }
         }
@@ -567,7 +566,14 @@ fn initialize(
     });
 
     info!( "Opening perf events for {}...", pid );
-    let mut perf = PerfGroup::open( pid, args.frequency, args.stack_size, args.event_source ).map_err( |err| format!( "failed to start profiling: {}", err ) )?;
+    let mut perf = PerfGroup::new( args.frequency, args.stack_size, args.event_source ).map_err( |err| format!( "failed to start profiling: {}", err ) )?;
+    if args.adaptive_stack_size {
+        perf.set_adaptive_stack_size( args.min_stack_size, args.max_stack_size );
+    }
+    if args.per_cpu_buffers {
+        perf.set_per_cpu_buffers();
+    }
+    perf.open_process( pid ).map_err( |err| format!( "failed to start profiling: {}", err ) )?;
 
     let mut maps = RangeMap::new();
     let mut new_maps = Vec::new();
@@ -583,6 +589,8 @@ fn initialize(
 
     let mut address_space = AddressSpace::< arch::native::Arch >::new();
     address_space.set_panic_on_partial_backtrace( args.panic_on_partial_backtrace );
+    address_space.set_unwind_mode( args.unwind_mode );
+    address_space.set_stack_scanning( args.stack_scanning );
 
     update_maps( &mut maps, &mut new_maps );
     process_maps( &maps, offline, pid, &mut address_space, &writer );
@@ -603,13 +611,86 @@ pub struct Args< 'a > {
     pub frequency: u64,
     pub event_source: EventSource,
     pub stack_size: u32,
+    pub adaptive_stack_size: bool,
+    pub min_stack_size: u32,
+    pub max_stack_size: u32,
+    pub per_cpu_buffers: bool,
     pub discard_all: bool,
     pub sample_count_limit: Option< u64 >,
     pub time_limit: Option< u64 >,
     pub output_path: Option< &'a OsStr >,
     pub lock_memory: bool,
     pub offline: bool,
-    pub panic_on_partial_backtrace: bool
+    pub panic_on_partial_backtrace: bool,
+    pub unwind_mode: UnwindMode,
+    pub stack_scanning: bool,
+    pub live_stack_reads: bool,
+    pub unwind_threads: usize
+}
+
+// A sample which still needs to be unwound; it owns all of its data
+// so that it can be sent to one of the unwinding threads.
+struct PendingSample {
+    timestamp: u64,
+    pid: u32,
+    tid: u32,
+    cpu: u32,
+    kernel_backtrace: Vec< u64 >,
+    stack: Vec< u8 >,
+    regs: DwarfRegs,
+    live_stack: Option< Range< u64 > >,
+    dynamic_stack_size: u64,
+    is_stack_truncated: bool
+}
+
+fn serialize_sample( sample_info: Packet, guessed_frames: Option< Packet >, sample: Packet ) -> Vec< u8 > {
+    let mut bytes = FramedPacket::Known( sample_info ).write_to_vec( Endianness::LittleEndian ).unwrap();
+    if let Some( guessed_frames ) = guessed_frames {
+        bytes.extend( FramedPacket::Known( guessed_frames ).write_to_vec( Endianness::LittleEndian ).unwrap() );
+    }
+    bytes.extend( FramedPacket::Known( sample ).write_to_vec( Endianness::LittleEndian ).unwrap() );
+    bytes
+}
+
+impl PendingSample {
+    fn unwind( mut self, unwinder: &mut Unwinder< arch::native::Arch >, snapshot: &AddressSpaceSnapshot< arch::native::Arch > ) -> Vec< u8 > {
+        let mut user_backtrace = Vec::new();
+        let mut used_live_stack_reads = false;
+        let stack = (&self.stack[..]).into();
+        if let Some( live_stack ) = self.live_stack.clone() {
+            let reader = LiveStackReader::new( stack, self.pid, live_stack.start, live_stack.end );
+            unwinder.unwind( snapshot, &mut self.regs, &reader, &mut user_backtrace );
+
+            used_live_stack_reads = reader.used_live_reads();
+        } else {
+            let reader = StackReader { stack };
+            unwinder.unwind( snapshot, &mut self.regs, &reader, &mut user_backtrace );
+        }
+
+        let sample_info = Packet::SampleInfo {
+            used_live_stack_reads,
+            dynamic_stack_size: self.dynamic_stack_size,
+            is_stack_truncated: self.is_stack_truncated
+        };
+
+        let indexes: Vec< u32 > = user_backtrace.iter().enumerate().filter( |&(_, frame)| frame.is_guessed ).map( |(index, _)| index as u32 ).collect();
+        let guessed_frames = if indexes.is_empty() {
+            None
+        } else {
+            Some( Packet::GuessedFrames { indexes: Cow::Owned( indexes ) } )
+        };
+
+        let sample = Packet::Sample {
+            timestamp: self.timestamp,
+            pid: self.pid,
+            tid: self.tid,
+            cpu: self.cpu,
+            kernel_backtrace: Cow::Borrowed( &self.kernel_backtrace ),
+            user_backtrace: Cow::Borrowed( &user_backtrace )
+        };
+
+        serialize_sample( sample_info, guessed_frames, sample )
+    }
 }
 
 fn handle_comm_event( event: CommEvent, writer: &ExecutionQueue< PacketWriter > ) {
@@ -660,25 +741,50 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
     let time_limit = args.time_limit;
     let discard_all = args.discard_all;
     let offline = args.offline;
+    let live_stack_reads = args.live_stack_reads;
+    let unwind_threads = args.unwind_threads;
 
     let sigint = SigintHandler::new();
     let (pid, mut maps, mut perf, mut address_space, writer) = initialize( &sigint, args )?;
 
+    // Unwinding is too slow to be done on the thread which drains the ring buffers,
+    // so it's done on a pool of threads; the samples are still written out in order
+    // since the writer waits for each of them to be unwound in turn.
+    let unwinders = if offline {
+        None
+    } else {
+        Some( ExecutionPool::new( unwind_threads, Unwinder::< arch::native::Arch >::new ) )
+    };
+
     info!( "Enabling perf events..." );
     perf.enable();
 
+    // Instead of checking for these all the time we just get woken up by them.
+    perf.watch_fd( sigint.fd() )?;
+    let timer = match time_limit {
+        Some( time_limit ) => {
+            let timer = Timer::new( Duration::from_secs( time_limit ) )?;
+            perf.watch_fd( timer.fd() )?;
+            Some( timer )
+        },
+        None => None
+    };
+
     info!( "Running..." );
     let mut counter = 0;
-    let profiling_started_ts = Instant::now();
 
     let mut new_maps = Vec::new();
     let mut address_space_needs_reload = false;
-    let mut wait = false;
+    let mut has_events = false;
+    let mut ready_fds = Vec::new();
     let mut pending_lost_events = 0;
     let mut total_lost_events = 0;
     let mut dwarf_regs = DwarfRegs::new();
+    let mut new_threads = Vec::new();
+    let mut stack_usage = Vec::new();
+    let mut is_stopping = false;
     loop {
-        if sigint.was_triggered() || perf.is_empty() {
+        if perf.is_empty() || is_stopping {
             break;
         }
 
@@ -688,30 +794,27 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
             }
         }
 
-        if let Some( time_limit ) = time_limit {
-            if profiling_started_ts.elapsed().as_secs() >= time_limit {
+        // As long as events keep coming we only check what's ready without blocking.
+        perf.wait( !has_events, &mut ready_fds );
+        if !ready_fds.is_empty() {
+            if sigint.was_triggered() {
+                is_stopping = true;
+            } else if timer.as_ref().map( |timer| timer.has_expired() ).unwrap_or( false ) {
                 info!( "Time limit exceeded; stopping!" );
-                break;
+                is_stopping = true;
             }
-        }
 
-        if wait {
-            wait = false;
-            perf.wait();
+            // Whatever is still below the wakeup watermark won't wake us up
+            // anymore, so we go through all of the members one last time.
+            if is_stopping {
+                perf.mark_pending_members_ready();
+            }
         }
 
         let iter = perf.iter();
-        if iter.len() == 0 {
-            wait = true;
-            continue;
-        }
-
+        has_events = iter.len() != 0;
         for event_ref in iter {
             let raw_event = event_ref.get();
-            if sigint.was_triggered() {
-                break;
-            }
-
             if let Some( limit ) = sample_count_limit {
                 if counter >= limit {
                     break;
@@ -750,6 +853,12 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
                     total_lost_events += event.count;
                     continue;
                 },
+                Event::Fork( event ) => {
+                    if event.pid == pid && event.tid != pid {
+                        new_threads.push( event.tid );
+                    }
+                    continue;
+                },
                 _ => {}
             }
 
@@ -772,43 +881,88 @@ pub fn main( args: Args ) -> Result< (), Box< Error > > {
             match event {
                 Event::Sample( event ) => {
                     counter += 1;
-                    let mut user_backtrace = Vec::new();
                     event.regs.copy_to_dwarf_regs( &mut dwarf_regs );
 
-                    let packet;
-                    if offline {
-                        packet = Packet::RawSample {
+                    // The kernel dumps as much of the stack as it can, so if the dump is full
+                    // then the stack most likely continues past it.
+                    let dynamic_stack_size = event.dynamic_stack_size;
+                    let is_stack_truncated = dynamic_stack_size >= event.stack.len() as u64;
+                    stack_usage.push( (event.tid, event.stack.len() as u64, dynamic_stack_size) );
+
+                    if let Some( ref unwinders ) = unwinders {
+                        let live_stack = if live_stack_reads {
+                            let stack_address = arch::native::Arch::get_stack_pointer( &dwarf_regs ).unwrap_or( 0 );
+
+                            // We don't track anonymous mappings, so we only know
+                            // where the main thread's stack ends; for any other
+                            // stack we don't know how far we can safely read.
+                            maps.get_value( stack_address ).map( |region| stack_address..region.end )
+                        } else {
+                            None
+                        };
+
+                        let sample = PendingSample {
                             timestamp: event.timestamp,
                             pid: event.pid,
                             tid: event.tid,
                             cpu: event.cpu,
-                            kernel_backtrace: Cow::Borrowed( &event.callchain ),
-                            stack: event.stack.into(),
-                            regs: Cow::Owned( dwarf_regs.iter().map( |(register, value)| DwarfReg { register, value } ).collect() )
+                            kernel_backtrace: event.callchain,
+                            stack: event.stack.as_slice().into_owned(),
+                            regs: dwarf_regs.clone(),
+                            live_stack,
+                            dynamic_stack_size,
+                            is_stack_truncated
                         };
+
+                        let snapshot = address_space.snapshot();
+                        let (tx, rx) = mpsc::sync_channel( 1 );
+                        unwinders.spawn( move |unwinder| {
+                            // If the writer is gone then there's nothing left to do.
+                            let _ = tx.send( sample.unwind( unwinder, &snapshot ) );
+                            Ok(())
+                        });
+
+                        writer.spawn( move |fp| {
+                            let bytes = rx.recv().map_err( |_| io::Error::new( io::ErrorKind::Other, "unwinding thread has died" ) )?;
+                            fp.write_all( &bytes )
+                        });
                     } else {
-                        let reader = StackReader { stack: event.stack };
-                        address_space.unwind( &mut dwarf_regs, &reader, &mut user_backtrace );
+                        let sample_info = Packet::SampleInfo {
+                            used_live_stack_reads: false,
+                            dynamic_stack_size,
+                            is_stack_truncated
+                        };
 
-                        packet = Packet::Sample {
+                        let sample = Packet::RawSample {
                             timestamp: event.timestamp,
                             pid: event.pid,
                             tid: event.tid,
                             cpu: event.cpu,
                             kernel_backtrace: Cow::Borrowed( &event.callchain ),
-                            user_backtrace: Cow::Borrowed( &user_backtrace )
+                            stack: event.stack.into(),
+                            regs: Cow::Owned( dwarf_regs.iter().map( |(register, value)| DwarfReg { register, value } ).collect() )
                         };
-                    }
 
-                    let framed = FramedPacket::Known( packet );
-                    let bytes = framed.write_to_vec( Endianness::LittleEndian ).unwrap();
-                    writer.spawn( move |fp| {
-                        fp.write_all( &bytes )
-                    });
+                        let bytes = serialize_sample( sample_info, None, sample );
+                        writer.spawn( move |fp| {
+                            fp.write_all( &bytes )
+                        });
+                    }
                 },
                 _ => {}
             }
         }
+
+        // The event iterator borrows the perf group, so we can only do this after the batch is processed.
+        for tid in new_threads.drain( .. ) {
+            if let Err( err ) = perf.open_thread( tid ) {
+                debug!( "Failed to open perf events for a new thread {}: {}", tid, err );
+            }
+        }
+
+        for (tid, stack_size, dynamic_stack_size) in stack_usage.drain( .. ) {
+            perf.observe_stack_usage( tid, stack_size, dynamic_stack_size );
+        }
     }
 
     if total_lost_events > 0 {
@@ -0,0 +1,109 @@
+use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::error::Error;
+
+use cmd_collate::{self, Decoder};
+use unwind_context::{UnwindMode, StopReason};
+
+pub struct Args< 'a > {
+    pub input_path: &'a OsStr,
+    pub debug_symbols: Vec< &'a OsStr >,
+    pub force_stack_size: Option< u32 >,
+    pub unwind_mode: UnwindMode,
+    pub stack_scanning: bool,
+    pub thread_count: usize
+}
+
+#[derive(Default)]
+struct Group {
+    total: u64,
+    by_reason: HashMap< StopReason, u64 >
+}
+
+impl Group {
+    fn add( &mut self, reason: StopReason, count: u64 ) {
+        self.total += count;
+        *self.by_reason.entry( reason ).or_insert( 0 ) += count;
+    }
+}
+
+fn sorted_by_count< K: Ord >( map: HashMap< K, u64 > ) -> Vec< (K, u64) > {
+    let mut entries: Vec< _ > = map.into_iter().collect();
+    entries.sort_by( |a, b| b.1.cmp( &a.1 ).then_with( || a.0.cmp( &b.0 ) ) );
+    entries
+}
+
+fn percentage( count: u64, total: u64 ) -> f64 {
+    count as f64 * 100.0 / total as f64
+}
+
+fn print_groups( title: &str, groups: HashMap< String, Group >, total: u64 ) {
+    let mut groups: Vec< _ > = groups.into_iter().collect();
+    groups.sort_by( |a, b| b.1.total.cmp( &a.1.total ).then_with( || a.0.cmp( &b.0 ) ) );
+
+    println!( "\n{}:", title );
+    for (name, group) in groups {
+        println!( "  {}: {} ({:.2}%)", name, group.total, percentage( group.total, total ) );
+
+        let by_reason = group.by_reason.into_iter().map( |(reason, count)| (reason.as_str(), count) ).collect();
+        for (reason, count) in sorted_by_count( by_reason ) {
+            println!( "    {}: {}", reason, count );
+        }
+    }
+}
+
+pub fn main( args: Args ) -> Result< (), Box< dyn Error > > {
+    let collate_args = cmd_collate::Args {
+        input_path: args.input_path,
+        debug_symbols: args.debug_symbols,
+        force_stack_size: args.force_stack_size,
+        omit_symbols: Vec::new(),
+        only_sample: None,
+        without_kernel_callstacks: true,
+        line_numbers: false,
+        expand_inlined_frames: false,
+        strip_rust_hashes: false,
+        unwind_mode: args.unwind_mode,
+        stack_scanning: args.stack_scanning,
+        thread_count: args.thread_count
+    };
+
+    let collation = cmd_collate::collate( collate_args )?;
+    if collation.stop_reasons.is_empty() {
+        return Err( "no samples to report on; only samples recorded with `--offline` are unwound here".into() );
+    }
+
+    let mut decoder = Decoder::new( &collation, false );
+
+    let mut total = 0;
+    let mut by_reason = HashMap::new();
+    let mut by_binary: HashMap< String, Group > = HashMap::new();
+    let mut by_function: HashMap< String, Group > = HashMap::new();
+    for (&(reason, ref frame), &count) in &collation.stop_reasons {
+        total += count;
+        *by_reason.entry( reason.as_str() ).or_insert( 0 ) += count;
+
+        let (binary, function) = match *frame {
+            Some( ref frame ) => {
+                let binary = decoder.get_binary_basename( frame ).unwrap_or( "[unknown]" ).to_owned();
+                let mut function = String::new();
+                decoder.write_frame( &mut function, frame );
+                (binary, function)
+            },
+            None => ("[unknown]".to_owned(), "[empty backtrace]".to_owned())
+        };
+
+        by_binary.entry( binary ).or_default().add( reason, count );
+        by_function.entry( function ).or_default().add( reason, count );
+    }
+
+    println!( "Unwound {} samples; backtraces ended because of:", total );
+    for (reason, count) in sorted_by_count( by_reason ) {
+        println!( "  {}: {} ({:.2}%)", reason, count, percentage( count, total ) );
+    }
+
+    print_groups( "Per binary of the outermost frame", by_binary, total );
+    print_groups( "Per function of the outermost frame", by_function, total );
+
+    Ok(())
+}
@@ -0,0 +1,562 @@
+use std::ops::Range;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use gimli::{
+    self,
+    Abbreviations,
+    AttributeValue,
+    CompilationUnitHeader,
+    DebugAbbrev,
+    DebugInfo as DebugInfoSection,
+    DebugLine,
+    DebugRanges,
+    DebugStr,
+    DebuggingInformationEntry,
+    EndianBuf,
+    LittleEndian,
+    BigEndian
+};
+
+use archive::Endianness;
+use arch::Endianity;
+use binary::BinaryData;
+use range_map::RangeMap;
+use utils::get_ms;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct SourceLocation {
+    pub file_index: usize,
+    pub line: u64
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InlinedFrame {
+    pub name_index: usize,
+    pub call_location: Option< SourceLocation >
+}
+
+#[derive(Clone, Debug)]
+struct InlinedRange {
+    range: Range< u64 >,
+    depth: usize,
+    frame_index: usize
+}
+
+// A function whose inlined frames we're currently gathering.
+struct FunctionScope {
+    depth: isize,
+    ranges: Vec< Range< u64 > >,
+    inlined_depths: Vec< isize >,
+    inlined_ranges: Vec< InlinedRange >
+}
+
+impl FunctionScope {
+    fn flush( self, inlined_ranges_by_function: &mut Vec< (Range< u64 >, Vec< InlinedRange >) > ) {
+        if self.inlined_ranges.is_empty() {
+            return;
+        }
+
+        for range in self.ranges {
+            inlined_ranges_by_function.push( (range, self.inlined_ranges.clone()) );
+        }
+    }
+}
+
+pub struct DebugInfo {
+    files: Vec< String >,
+    lines: RangeMap< SourceLocation >,
+    names: Vec< String >,
+    inlined_frames: Vec< InlinedFrame >,
+    inlined_ranges_by_function: RangeMap< Vec< InlinedRange > >
+}
+
+fn get_section< 'a, E: Endianity >( binary: &'a BinaryData, range: Option< Range< usize > > ) -> EndianBuf< 'a, E > {
+    let bytes = range.map( |range| binary.get_section_data( range ) ).unwrap_or( &[] );
+    EndianBuf::new( bytes, E::get() )
+}
+
+struct Interner {
+    values: Vec< String >,
+    index_by_value: HashMap< String, usize >
+}
+
+impl Interner {
+    fn new() -> Self {
+        Interner {
+            values: Vec::new(),
+            index_by_value: HashMap::new()
+        }
+    }
+
+    fn intern( &mut self, value: String ) -> usize {
+        if let Some( &index ) = self.index_by_value.get( &value ) {
+            return index;
+        }
+
+        let index = self.values.len();
+        self.values.push( value.clone() );
+        self.index_by_value.insert( value, index );
+        index
+    }
+}
+
+struct Sections< 'a, E: Endianity + 'a > {
+    debug_abbrev: DebugAbbrev< EndianBuf< 'a, E > >,
+    debug_line: DebugLine< EndianBuf< 'a, E > >,
+    debug_str: DebugStr< EndianBuf< 'a, E > >,
+    debug_ranges: DebugRanges< EndianBuf< 'a, E > >
+}
+
+type Unit< 'a, E > = CompilationUnitHeader< EndianBuf< 'a, E > >;
+type Entry< 'abbrev, 'unit, 'a, E > = DebuggingInformationEntry< 'abbrev, 'unit, EndianBuf< 'a, E > >;
+
+fn get_string< 'a, E: Endianity >( sections: &Sections< 'a, E >, entry: &Entry< '_, '_, 'a, E >, name: gimli::DwAt ) -> Option< EndianBuf< 'a, E > > {
+    entry.attr( name ).ok().and_then( |attr| attr ).and_then( |attr| attr.string_value( &sections.debug_str ) )
+}
+
+fn get_ranges< 'a, E: Endianity >( sections: &Sections< 'a, E >, unit: &Unit< 'a, E >, unit_base_address: u64, entry: &Entry< '_, '_, 'a, E >, output: &mut Vec< Range< u64 > > ) {
+    output.clear();
+
+    if let Ok( Some( AttributeValue::Addr( low_pc ) ) ) = entry.attr_value( gimli::DW_AT_low_pc ) {
+        let high_pc = match entry.attr( gimli::DW_AT_high_pc ) {
+            Ok( Some( attr ) ) => match attr.value() {
+                AttributeValue::Addr( high_pc ) => high_pc,
+                _ => match attr.udata_value() {
+                    Some( size ) => low_pc + size,
+                    None => return
+                }
+            },
+            _ => return
+        };
+
+        if high_pc > low_pc {
+            output.push( low_pc..high_pc );
+        }
+
+        return;
+    }
+
+    let offset = match entry.attr_value( gimli::DW_AT_ranges ) {
+        Ok( Some( AttributeValue::DebugRangesRef( offset ) ) ) => offset,
+        _ => return
+    };
+
+    let mut ranges = match sections.debug_ranges.ranges( offset, unit.address_size(), unit_base_address ) {
+        Ok( ranges ) => ranges,
+        Err( _ ) => return
+    };
+
+    while let Ok( Some( range ) ) = ranges.next() {
+        if range.end > range.begin {
+            output.push( range.begin..range.end );
+        }
+    }
+}
+
+fn get_function_name< 'a, E: Endianity >(
+    sections: &Sections< 'a, E >,
+    unit: &Unit< 'a, E >,
+    abbreviations: &Abbreviations,
+    entry: &Entry< '_, '_, 'a, E >,
+    recursion_limit: usize
+) -> Option< String > {
+    let name = get_string( sections, entry, gimli::DW_AT_linkage_name )
+        .or_else( || get_string( sections, entry, gimli::DW_AT_MIPS_linkage_name ) )
+        .or_else( || get_string( sections, entry, gimli::DW_AT_name ) );
+
+    if let Some( name ) = name {
+        return Some( name.to_string_lossy().into_owned() );
+    }
+
+    if recursion_limit == 0 {
+        return None;
+    }
+
+    for &attribute in &[ gimli::DW_AT_abstract_origin, gimli::DW_AT_specification ] {
+        let offset = match entry.attr_value( attribute ) {
+            Ok( Some( AttributeValue::UnitRef( offset ) ) ) => offset,
+            _ => continue
+        };
+
+        let mut entries = match unit.entries_at_offset( abbreviations, offset ) {
+            Ok( entries ) => entries,
+            Err( _ ) => continue
+        };
+
+        if let Ok( Some( _ ) ) = entries.next_entry() {
+            if let Some( origin ) = entries.current() {
+                if let Some( name ) = get_function_name( sections, unit, abbreviations, origin, recursion_limit - 1 ) {
+                    return Some( name );
+                }
+            }
+        }
+    }
+
+    None
+}
+
+impl DebugInfo {
+    pub fn load( binary: &BinaryData ) -> Option< Self > {
+        if binary.debug_info_range().is_none() || binary.debug_line_range().is_none() {
+            return None;
+        }
+
+        debug!( "Loading debug info for '{}'...", binary.name() );
+        let start_timestamp = Instant::now();
+
+        let debug_info = match binary.endianness() {
+            Endianness::LittleEndian => Self::load_impl::< LittleEndian >( binary ),
+            Endianness::BigEndian => Self::load_impl::< BigEndian >( binary )
+        };
+
+        let elapsed = start_timestamp.elapsed();
+        debug!(
+            "Loaded {} line number entries and {} inlined frames for '{}' in {}ms",
+            debug_info.lines.len(),
+            debug_info.inlined_frames.len(),
+            binary.name(),
+            get_ms( elapsed )
+        );
+
+        Some( debug_info )
+    }
+
+    fn load_impl< E: Endianity >( binary: &BinaryData ) -> Self {
+        let debug_info: DebugInfoSection< EndianBuf< E > > = get_section( binary, binary.debug_info_range() ).into();
+        let sections = Sections {
+            debug_abbrev: get_section( binary, binary.debug_abbrev_range() ).into(),
+            debug_line: get_section( binary, binary.debug_line_range() ).into(),
+            debug_str: get_section( binary, binary.debug_str_range() ).into(),
+            debug_ranges: get_section( binary, binary.debug_ranges_range() ).into()
+        };
+
+        let mut files = Interner::new();
+        let mut names = Interner::new();
+        let mut lines = Vec::new();
+        let mut inlined_frames = Vec::new();
+        let mut inlined_ranges_by_function = Vec::new();
+
+        let mut units = debug_info.units();
+        loop {
+            let unit = match units.next() {
+                Ok( Some( unit ) ) => unit,
+                Ok( None ) => break,
+                Err( error ) => {
+                    warn!( "Failed to iterate compilation units for '{}': {}", binary.name(), error );
+                    break;
+                }
+            };
+
+            let abbreviations = match unit.abbreviations( &sections.debug_abbrev ) {
+                Ok( abbreviations ) => abbreviations,
+                Err( error ) => {
+                    warn!( "Failed to parse abbreviations for '{}': {}", binary.name(), error );
+                    continue;
+                }
+            };
+
+            let global_file_index_by_file_index = Self::load_lines( binary, &sections, &unit, &abbreviations, &mut files, &mut lines );
+            Self::load_inlined_frames(
+                &sections,
+                &unit,
+                &abbreviations,
+                &global_file_index_by_file_index,
+                &mut names,
+                &mut inlined_frames,
+                &mut inlined_ranges_by_function
+            );
+        }
+
+        DebugInfo {
+            files: files.values,
+            lines: RangeMap::from_vec( lines ),
+            names: names.values,
+            inlined_frames,
+            inlined_ranges_by_function: RangeMap::from_vec( inlined_ranges_by_function )
+        }
+    }
+
+    fn load_lines< 'a, E: Endianity >(
+        binary: &BinaryData,
+        sections: &Sections< 'a, E >,
+        unit: &Unit< 'a, E >,
+        abbreviations: &Abbreviations,
+        files: &mut Interner,
+        lines: &mut Vec< (Range< u64 >, SourceLocation) >
+    ) -> HashMap< u64, usize > {
+        let mut global_file_index_by_file_index = HashMap::new();
+        let mut entries = unit.entries( abbreviations );
+        let root = match entries.next_dfs() {
+            Ok( Some( (_, root) ) ) => root,
+            _ => return global_file_index_by_file_index
+        };
+
+        let offset = match root.attr_value( gimli::DW_AT_stmt_list ) {
+            Ok( Some( AttributeValue::DebugLineRef( offset ) ) ) => offset,
+            _ => return global_file_index_by_file_index
+        };
+
+        let comp_dir = get_string( sections, root, gimli::DW_AT_comp_dir );
+        let comp_name = get_string( sections, root, gimli::DW_AT_name );
+        let program = match sections.debug_line.program( offset, unit.address_size(), comp_dir, comp_name ) {
+            Ok( program ) => program,
+            Err( error ) => {
+                warn!( "Failed to parse a line number program for '{}': {}", binary.name(), error );
+                return global_file_index_by_file_index;
+            }
+        };
+
+        for index in 1..program.header().file_names().len() as u64 + 1 {
+            let name = program.header().file( index ).map( |entry| entry.path_name().to_string_lossy().into_owned() ).unwrap_or_else( || "??".to_owned() );
+            global_file_index_by_file_index.insert( index, files.intern( name ) );
+        }
+
+        let mut previous: Option< (u64, SourceLocation) > = None;
+        let mut rows = program.rows();
+        loop {
+            let row = match rows.next_row() {
+                Ok( Some( (_, row) ) ) => row,
+                Ok( None ) => break,
+                Err( error ) => {
+                    warn!( "Failed to run a line number program for '{}': {}", binary.name(), error );
+                    break;
+                }
+            };
+
+            if let Some( (address, location) ) = previous.take() {
+                if row.address() > address {
+                    let range = address..row.address();
+                    let merged = match lines.last_mut() {
+                        Some( &mut (ref mut last_range, last_location) ) if last_range.end == range.start && last_location == location => {
+                            last_range.end = range.end;
+                            true
+                        },
+                        _ => false
+                    };
+
+                    if !merged {
+                        lines.push( (range, location) );
+                    }
+                }
+            }
+
+            if row.end_sequence() {
+                continue;
+            }
+
+            let line = match row.line() {
+                Some( line ) => line,
+                None => continue
+            };
+
+            let file_index = match global_file_index_by_file_index.get( &row.file_index() ) {
+                Some( &file_index ) => file_index,
+                None => continue
+            };
+
+            previous = Some( (row.address(), SourceLocation { file_index, line }) );
+        }
+
+        global_file_index_by_file_index
+    }
+
+    fn load_inlined_frames< 'a, E: Endianity >(
+        sections: &Sections< 'a, E >,
+        unit: &Unit< 'a, E >,
+        abbreviations: &Abbreviations,
+        global_file_index_by_file_index: &HashMap< u64, usize >,
+        names: &mut Interner,
+        inlined_frames: &mut Vec< InlinedFrame >,
+        inlined_ranges_by_function: &mut Vec< (Range< u64 >, Vec< InlinedRange >) >
+    ) {
+        let mut name_index_by_offset = HashMap::new();
+        let mut ranges = Vec::new();
+        let mut unit_base_address = 0;
+
+        // The functions we're currently in, from the outermost one; since functions
+        // can be nested inside of each other (and inside of lexical blocks) we need
+        // to keep track of all of them and attribute whatever was inlined to the
+        // innermost one.
+        let mut scopes: Vec< FunctionScope > = Vec::new();
+        let mut depth = 0;
+
+        let mut entries = unit.entries( abbreviations );
+        while let Ok( Some( (delta_depth, entry) ) ) = entries.next_dfs() {
+            depth += delta_depth;
+            while scopes.last().map( |scope| scope.depth >= depth ).unwrap_or( false ) {
+                scopes.pop().unwrap().flush( inlined_ranges_by_function );
+            }
+
+            if let Some( scope ) = scopes.last_mut() {
+                while scope.inlined_depths.last().map( |&inlined_depth| inlined_depth >= depth ).unwrap_or( false ) {
+                    scope.inlined_depths.pop();
+                }
+            }
+
+            match entry.tag() {
+                gimli::DW_TAG_compile_unit => {
+                    if let Ok( Some( AttributeValue::Addr( address ) ) ) = entry.attr_value( gimli::DW_AT_low_pc ) {
+                        unit_base_address = address;
+                    }
+                },
+                gimli::DW_TAG_subprogram => {
+                    // Abstract instances have no ranges; we still need a scope for them
+                    // so that nothing inside of them gets attributed to their parent.
+                    get_ranges( sections, unit, unit_base_address, entry, &mut ranges );
+                    scopes.push( FunctionScope {
+                        depth,
+                        ranges: ranges.clone(),
+                        inlined_depths: Vec::new(),
+                        inlined_ranges: Vec::new()
+                    });
+                },
+                gimli::DW_TAG_inlined_subroutine if !scopes.is_empty() => {
+                    get_ranges( sections, unit, unit_base_address, entry, &mut ranges );
+                    if ranges.is_empty() {
+                        continue;
+                    }
+
+                    let name_index = {
+                        let origin = match entry.attr_value( gimli::DW_AT_abstract_origin ) {
+                            Ok( Some( AttributeValue::UnitRef( offset ) ) ) => Some( offset ),
+                            _ => None
+                        };
+
+                        match origin.and_then( |offset| name_index_by_offset.get( &offset ).cloned() ) {
+                            Some( name_index ) => name_index,
+                            None => {
+                                let name = get_function_name( sections, unit, abbreviations, entry, 4 ).unwrap_or_else( || "??".to_owned() );
+                                let name_index = names.intern( name );
+                                if let Some( offset ) = origin {
+                                    name_index_by_offset.insert( offset, name_index );
+                                }
+
+                                name_index
+                            }
+                        }
+                    };
+
+                    let call_file = match entry.attr( gimli::DW_AT_call_file ) {
+                        Ok( Some( attr ) ) => match attr.value() {
+                            AttributeValue::FileIndex( index ) => Some( index ),
+                            _ => attr.udata_value()
+                        },
+                        _ => None
+                    };
+
+                    let call_line = match entry.attr( gimli::DW_AT_call_line ) {
+                        Ok( Some( attr ) ) => attr.udata_value(),
+                        _ => None
+                    };
+
+                    let call_location = match (call_file.and_then( |index| global_file_index_by_file_index.get( &index ) ), call_line) {
+                        (Some( &file_index ), Some( line )) => Some( SourceLocation { file_index, line } ),
+                        _ => None
+                    };
+
+                    let frame_index = inlined_frames.len();
+                    inlined_frames.push( InlinedFrame {
+                        name_index,
+                        call_location
+                    });
+
+                    let scope = scopes.last_mut().unwrap();
+                    scope.inlined_depths.push( depth );
+                    for range in ranges.drain( .. ) {
+                        scope.inlined_ranges.push( InlinedRange {
+                            range,
+                            depth: scope.inlined_depths.len(),
+                            frame_index
+                        });
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        while let Some( scope ) = scopes.pop() {
+            scope.flush( inlined_ranges_by_function );
+        }
+    }
+
+    /// Looks up the source location for a given address, which
+    /// should be relative to the binary's declared load addresses.
+    pub fn lookup_source_location( &self, address: u64 ) -> Option< SourceLocation > {
+        self.lines.get_value( address ).cloned()
+    }
+
+    /// Looks up the functions which were inlined at a given address;
+    /// their indexes are appended to the `output` starting from
+    /// the outermost one.
+    pub fn lookup_inlined_frames( &self, address: u64, output: &mut Vec< usize > ) {
+        let inlined_ranges = match self.inlined_ranges_by_function.get_value( address ) {
+            Some( inlined_ranges ) => inlined_ranges,
+            None => return
+        };
+
+        let mut matching: Vec< &InlinedRange > = inlined_ranges.iter()
+            .filter( |inlined| address >= inlined.range.start && address < inlined.range.end )
+            .collect();
+
+        matching.sort_by_key( |inlined| inlined.depth );
+        output.extend( matching.into_iter().map( |inlined| inlined.frame_index ) );
+    }
+
+    #[inline]
+    pub fn get_file_name( &self, file_index: usize ) -> &str {
+        &self.files[ file_index ]
+    }
+
+    #[inline]
+    pub fn get_inlined_frame( &self, frame_index: usize ) -> &InlinedFrame {
+        &self.inlined_frames[ frame_index ]
+    }
+
+    #[inline]
+    pub fn get_name( &self, name_index: usize ) -> &str {
+        &self.names[ name_index ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use binary::BinaryData;
+    use super::DebugInfo;
+
+    fn load( filename: &str ) -> DebugInfo {
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "bin" ).join( filename );
+        let binary = BinaryData::load_from_fs( None, path ).unwrap();
+        DebugInfo::load( &binary ).unwrap()
+    }
+
+    fn lookup( debug_info: &DebugInfo, address: u64 ) -> Option< (&str, u64) > {
+        debug_info.lookup_source_location( address ).map( |location| (debug_info.get_file_name( location.file_index ), location.line) )
+    }
+
+    #[test]
+    fn lookup_source_location_amd64() {
+        let debug_info = load( "amd64-usleep_in_a_loop_external_info.debug" );
+        assert_eq!( lookup( &debug_info, 0x400436 ), Some( ("start.S", 88) ) );
+        assert_eq!( lookup( &debug_info, 0x40043a ), Some( ("start.S", 90) ) );
+        assert_eq!( lookup( &debug_info, 0x40045a ), Some( ("start.S", 122) ) );
+        assert_eq!( lookup( &debug_info, 0x40045b ), None );
+    }
+
+    #[test]
+    fn lookup_source_location_amd64_compressed() {
+        for filename in &[ "amd64-usleep_in_a_loop_external_info_zlib.debug", "amd64-usleep_in_a_loop_external_info_zlib_gnu.debug" ] {
+            let debug_info = load( filename );
+            assert_eq!( lookup( &debug_info, 0x400436 ), Some( ("start.S", 88) ) );
+            assert_eq!( lookup( &debug_info, 0x40045a ), Some( ("start.S", 122) ) );
+        }
+    }
+
+    #[test]
+    fn lookup_source_location_mips64() {
+        let debug_info = load( "mips64-usleep_in_a_loop_fp" );
+        assert_eq!( lookup( &debug_info, 0x120000934 ), Some( ("start.S", 88) ) );
+        assert_eq!( lookup( &debug_info, 0x12000093c ), Some( ("start.S", 95) ) );
+    }
+}
@@ -1,45 +1,204 @@
+use std::mem;
+
 use gimli::{
     self,
     RegisterRule,
-    CfaRule
+    CfaRule,
+    Expression,
+    EvaluationResult,
+    Location,
+    Format
 };
 
 use arch::Architecture;
 use address_space::MemoryReader;
-use frame_descriptions::ContextCache;
-use unwind_context::UnwindFrame;
+use archive::Bitness;
+use dwarf_regs::DwarfRegs;
+use frame_descriptions::{ContextCache, CachedUnwindRule, CachedRegisterRule};
+use unwind_context::{UnwindFrame, StopReason};
+
+// A read from above the stack pointer which doesn't hit the stack
+// most likely went past the end of the stack we've captured.
+fn memory_error< A: Architecture, M: MemoryReader< A > >( memory: &M, regs: &DwarfRegs, address: u64 ) -> StopReason {
+    match A::get_stack_pointer( regs ) {
+        Some( stack_pointer ) if address >= stack_pointer && !memory.is_stack_address( address ) => StopReason::StackExhausted,
+        _ => StopReason::Other
+    }
+}
+
+fn dwarf_evaluate< A: Architecture, M: MemoryReader< A >, R: gimli::Reader >( nth_frame: usize, memory: &M, regs: &DwarfRegs, initial_value: Option< u64 >, expression: &Expression< R > ) -> Result< u64, StopReason > {
+    let address_size = match A::BITNESS {
+        Bitness::B32 => 4,
+        Bitness::B64 => 8
+    };
+
+    let mut evaluation = expression.clone().evaluation( address_size, Format::Dwarf32 );
+    if let Some( value ) = initial_value {
+        evaluation.set_initial_value( value );
+    }
+
+    let mut result = evaluation.evaluate();
+    loop {
+        result = match result {
+            Ok( EvaluationResult::Complete ) => break,
+            Ok( EvaluationResult::RequiresMemory { address, size, .. } ) => {
+                let value = match size {
+                    4 => memory.get_u32_at_address( A::ENDIANNESS, address ).map( |value| value as u64 ),
+                    8 => memory.get_u64_at_address( A::ENDIANNESS, address ),
+                    _ => None
+                };
+
+                match value {
+                    Some( value ) => evaluation.resume_with_memory( value ),
+                    None => {
+                        debug!( "Failed to evaluate DWARF expression for frame #{}: failed to fetch {} bytes from 0x{:016X}", nth_frame, size, address );
+                        return Err( memory_error( memory, regs, address ) );
+                    }
+                }
+            },
+            Ok( EvaluationResult::RequiresRegister( register ) ) => {
+                match regs.get( register as u16 ) {
+                    Some( value ) => evaluation.resume_with_register( value ),
+                    None => {
+                        debug!( "Failed to evaluate DWARF expression for frame #{}: register {:?} is unknown", nth_frame, A::register_name( register as u16 ) );
+                        return Err( StopReason::Other );
+                    }
+                }
+            },
+            Ok( result ) => {
+                debug!( "Failed to evaluate DWARF expression for frame #{}: unsupported requirement: {:?}", nth_frame, result );
+                return Err( StopReason::UnsupportedRule );
+            },
+            Err( error ) => {
+                debug!( "Failed to evaluate DWARF expression for frame #{}: {}", nth_frame, error );
+                return Err( StopReason::UnsupportedRule );
+            }
+        };
+    }
+
+    let pieces = evaluation.result();
+    if pieces.len() != 1 {
+        debug!( "Failed to evaluate DWARF expression for frame #{}: expected one piece, got {}", nth_frame, pieces.len() );
+        return Err( StopReason::UnsupportedRule );
+    }
+
+    match pieces[ 0 ].location {
+        Location::Address { address } => Ok( address ),
+        Location::Scalar { value } => Ok( value ),
+        Location::Register { register } => regs.get( register as u16 ).ok_or( StopReason::Other ),
+        _ => {
+            debug!( "Failed to evaluate DWARF expression for frame #{}: unsupported result: {:?}", nth_frame, pieces[ 0 ] );
+            Err( StopReason::UnsupportedRule )
+        }
+    }
+}
 
-fn dwarf_get_reg< A: Architecture, M: MemoryReader< A >, R: gimli::Reader >( nth_frame: usize, register: u16, memory: &M, cfa_value: u64, rule: &RegisterRule< R > ) -> Option< u64 > {
+fn dwarf_get_reg_at_offset< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, register: u16, memory: &M, regs: &DwarfRegs, cfa_value: u64, offset: i64 ) -> Result< u64, StopReason > {
+    let value_address = (cfa_value as i64 + offset) as u64;
+    match memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, value_address ) {
+        Some( value ) => Ok( value ),
+        None => {
+            debug!( "Cannot grab register {:?} for frame #{}: failed to fetch it from 0x{:016X}", A::register_name( register ), nth_frame, value_address );
+            Err( memory_error( memory, regs, value_address ) )
+        }
+    }
+}
+
+fn dwarf_get_reg< A: Architecture, M: MemoryReader< A >, R: gimli::Reader >( nth_frame: usize, register: u16, memory: &M, regs: &DwarfRegs, cfa_value: u64, rule: &RegisterRule< R > ) -> Result< u64, StopReason > {
     let value = match *rule {
         RegisterRule::Offset( offset ) => {
-            let value_address = (cfa_value as i64 + offset) as u64;
-            let value = match memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, value_address ) {
+            dwarf_get_reg_at_offset( nth_frame, register, memory, regs, cfa_value, offset )?
+        },
+        RegisterRule::ValOffset( offset ) => {
+            (cfa_value as i64 + offset) as u64
+        },
+        RegisterRule::SameValue => {
+            match regs.get( register ) {
+                Some( value ) => value,
+                None => {
+                    debug!( "Cannot grab register {:?} for frame #{}: its value is unknown", A::register_name( register ), nth_frame );
+                    return Err( StopReason::Other );
+                }
+            }
+        },
+        RegisterRule::Register( source ) => {
+            match regs.get( source as u16 ) {
+                Some( value ) => value,
+                None => {
+                    debug!( "Cannot grab register {:?} for frame #{}: register {:?} is unknown", A::register_name( register ), nth_frame, A::register_name( source as u16 ) );
+                    return Err( StopReason::Other );
+                }
+            }
+        },
+        RegisterRule::Expression( ref expression ) => {
+            let value_address = dwarf_evaluate( nth_frame, memory, regs, Some( cfa_value ), expression )?;
+            match memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, value_address ) {
                 Some( value ) => value,
                 None => {
                     debug!( "Cannot grab register {:?} for frame #{}: failed to fetch it from 0x{:016X}", A::register_name( register ), nth_frame, value_address );
-                    return None;
+                    return Err( memory_error( memory, regs, value_address ) );
                 }
-            };
-            value
+            }
+        },
+        RegisterRule::ValExpression( ref expression ) => {
+            dwarf_evaluate( nth_frame, memory, regs, Some( cfa_value ), expression )?
         },
         ref rule => {
             error!( "Handling for this register rule is unimplemented: {:?}", rule );
-            return None;
+            return Err( StopReason::UnsupportedRule );
         }
     };
 
     debug!( "Register {:?} at frame #{} is equal to 0x{:016X}", A::register_name( register ), nth_frame, value );
-    Some( value )
+    Ok( value )
+}
+
+fn dwarf_unwind_with_cached_rule< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, rule: &CachedUnwindRule, frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
+    frame.initial_address = Some( rule.initial_address );
+
+    let cfa_register_value = match frame.regs.get( rule.cfa_register ) {
+        Some( cfa_register_value ) => cfa_register_value,
+        None => {
+            debug!( "Failed to fetch CFA for frame #{}: failed to fetch register {:?}", nth_frame, A::register_name( rule.cfa_register ) );
+            frame.stop_reason = Some( StopReason::Other );
+            return false;
+        }
+    };
+
+    let cfa_value = (cfa_register_value as i64 + rule.cfa_offset) as u64;
+    frame.cfa = Some( cfa_value );
+
+    for &(register, register_rule) in &rule.registers {
+        match register_rule {
+            CachedRegisterRule::Undefined => next_frame.regs.remove( register ),
+            CachedRegisterRule::Offset( offset ) => {
+                match dwarf_get_reg_at_offset( nth_frame + 1, register, memory, &frame.regs, cfa_value, offset ) {
+                    Ok( value ) => next_frame.regs.append( register, value ),
+                    Err( reason ) if register == rule.return_address_register => frame.stop_reason = Some( reason ),
+                    Err( _ ) => {}
+                }
+            }
+        }
+    }
+
+    true
 }
 
 pub fn dwarf_unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize, memory: &M, ctx_cache: &mut ContextCache< A::Endianity >, frame: &mut UnwindFrame< A >, next_frame: &mut UnwindFrame< A > ) -> bool {
     let address = A::get_instruction_pointer( &frame.regs ).expect( "DWARF unwind: no instruction pointer" );
 
-    let binary = frame.binary.as_ref().expect( "DWARF unwind: no associated binary" );
+    let generation = frame.binary.as_ref().expect( "DWARF unwind: no associated binary" ).generation();
+    if let Some( rule ) = ctx_cache.get_unwind_rule( generation, address ) {
+        return dwarf_unwind_with_cached_rule( nth_frame, memory, rule, frame, next_frame );
+    }
+
+    let binary = frame.binary.as_ref().unwrap();
+
     let unwind_info = match binary.lookup_unwind_row( ctx_cache, address ) {
         Some( unwind_info ) => unwind_info,
         None => {
             debug!( "No unwind info for address 0x{:016X} in '{}'", address, binary.name() );
+            frame.stop_reason = Some( StopReason::MissingUnwindInfo );
             return false;
         }
     };
@@ -57,6 +216,7 @@ pub fn dwarf_unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize,
                 Some( cfa_register_value ) => cfa_register_value,
                 None => {
                     debug!( "Failed to fetch CFA for frame #{}: failed to fetch register {:?}", nth_frame, A::register_name( cfa_register as _ ) );
+                    frame.stop_reason = Some( StopReason::Other );
                     return false;
                 }
             };
@@ -65,9 +225,18 @@ pub fn dwarf_unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize,
             debug!( "Got CFA for frame #{}: {:?} (0x{:016X}) + {} = 0x{:016X}", nth_frame, A::register_name( cfa_register as _ ), cfa_register_value, cfa_offset, value );
             value
         },
-        ref cfa => {
-            error!( "Handling for this CFA rule is unimplemented: {:?}", cfa );
-            return false;
+        CfaRule::Expression( ref expression ) => {
+            let value = match dwarf_evaluate( nth_frame, memory, &frame.regs, None, expression ) {
+                Ok( value ) => value,
+                Err( reason ) => {
+                    debug!( "Failed to fetch CFA for frame #{}: failed to evaluate the CFA expression", nth_frame );
+                    frame.stop_reason = Some( reason );
+                    return false;
+                }
+            };
+
+            debug!( "Got CFA for frame #{}: 0x{:016X}", nth_frame, value );
+            value
         }
     };
 
@@ -75,10 +244,32 @@ pub fn dwarf_unwind< A: Architecture, M: MemoryReader< A > >( nth_frame: usize,
     for &(register, ref rule) in unwind_info.registers() {
         debug!( "  Register {:?}: {:?}", A::register_name( register as _ ), rule );
 
-        if let Some( value ) = dwarf_get_reg( nth_frame + 1, register as u16, memory, cfa_value, rule ) {
-            next_frame.regs.append( register as u16, value );
+        if !DwarfRegs::is_tracked( register as u16 ) {
+            continue;
+        }
+
+        // An undefined register has no value in the previous frame; if it's
+        // the return address then this is the outermost frame.
+        if let RegisterRule::Undefined = *rule {
+            next_frame.regs.remove( register as u16 );
+            continue;
+        }
+
+        // If we fail to get the return address then the caller will
+        // give up on this frame, so we remember why it has happened.
+        match dwarf_get_reg( nth_frame + 1, register as u16, memory, &frame.regs, cfa_value, rule ) {
+            Ok( value ) => next_frame.regs.append( register as u16, value ),
+            Err( reason ) if register as u16 == unwind_info.return_address_register() => frame.stop_reason = Some( reason ),
+            Err( _ ) => {}
         }
     }
 
+    let rule = unwind_info.compile( address );
+    mem::drop( unwind_info );
+
+    if let Some( rule ) = rule {
+        ctx_cache.cache_unwind_rule( address, rule );
+    }
+
     true
 }
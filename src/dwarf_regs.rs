@@ -1,3 +1,8 @@
+// We only keep track of the first 64 registers, which covers every register
+// which is needed for unwinding on the architectures we support. The rest
+// (e.g. the floating point registers on ARM and AArch64) are ignored.
+const REGISTER_COUNT: u16 = 64;
+
 #[derive(Clone)]
 pub struct DwarfRegs {
     regs: Vec< u64 >,
@@ -9,18 +14,23 @@ impl DwarfRegs {
     #[inline]
     pub fn new() -> DwarfRegs {
         let mut regs = Vec::new();
-        regs.resize( 64, 0 );
+        regs.resize( REGISTER_COUNT as usize, 0 );
 
         DwarfRegs {
             regs,
             regs_mask: 0,
-            regs_list: Vec::with_capacity( 64 )
+            regs_list: Vec::with_capacity( REGISTER_COUNT as usize )
         }
     }
 
+    #[inline]
+    pub fn is_tracked( register: u16 ) -> bool {
+        register < REGISTER_COUNT
+    }
+
     #[inline]
     pub fn get( &self, register: u16 ) -> Option< u64 > {
-        if self.regs_mask & (1_u64 << (register as u32)) == 0 || register as usize >= self.regs.len() {
+        if !Self::is_tracked( register ) || self.regs_mask & (1_u64 << (register as u32)) == 0 {
             None
         } else {
             Some( self.regs[ register as usize ] )
@@ -29,9 +39,26 @@ impl DwarfRegs {
 
     #[inline]
     pub fn append( &mut self, register: u16, value: u64 ) {
+        if !Self::is_tracked( register ) {
+            return;
+        }
+
+        if self.regs_mask & (1_u64 << (register as u32)) == 0 {
+            self.regs_list.push( register );
+        }
+
         self.regs_mask |= 1_u64 << (register as u32);
         self.regs[ register as usize ] = value;
-        self.regs_list.push( register );
+    }
+
+    #[inline]
+    pub fn remove( &mut self, register: u16 ) {
+        if !Self::is_tracked( register ) || self.regs_mask & (1_u64 << (register as u32)) == 0 {
+            return;
+        }
+
+        self.regs_mask &= !(1_u64 << (register as u32));
+        self.regs_list.retain( |&current| current != register );
     }
 
     #[inline]
@@ -244,6 +244,8 @@ define_iter! {
 #[macro_export]
 macro_rules! parse_elf {
     ($elf:expr, $callback:expr) => {{
+        // Not every callback calls the methods from the `Elf` trait.
+        #[allow(unused_imports)]
         use elf::{Elf, ElfKind, call_callback};
 
         match $elf {
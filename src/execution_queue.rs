@@ -1,9 +1,11 @@
 use std::io;
-use std::sync::mpsc;
+use std::sync::{Arc, mpsc};
 use std::marker::PhantomData;
 use std::thread;
 use std::mem;
 
+use parking_lot::Mutex;
+
 trait Callback< T >: Send {
     fn call_fn( self: Box< Self >, value: &mut T ) -> io::Result< () >;
 }
@@ -50,3 +52,83 @@ impl< T: Send > Drop for ExecutionQueue< T > {
         }
     }
 }
+
+// Like `ExecutionQueue`, but the callbacks are executed on multiple threads,
+// each one with its own state, so they can finish in any order.
+pub struct ExecutionPool< T: Send > {
+    tx: Option< mpsc::SyncSender< Box< dyn Callback< T > > > >,
+    handles: Vec< thread::JoinHandle< () > >,
+    phantom: PhantomData< T >
+}
+
+impl< T: Send + 'static > ExecutionPool< T > {
+    pub fn new< F >( thread_count: usize, mut create_state: F ) -> Self where F: FnMut() -> T {
+        assert!( thread_count > 0 );
+
+        let (tx, rx) = mpsc::sync_channel( 32 );
+        let rx: Arc< Mutex< mpsc::Receiver< Box< dyn Callback< T > > > > > = Arc::new( Mutex::new( rx ) );
+        let handles = (0..thread_count).map( |_| {
+            let rx = rx.clone();
+            let mut state = create_state();
+            thread::spawn( move || {
+                loop {
+                    let cb = match rx.lock().recv() {
+                        Ok( cb ) => cb,
+                        Err( _ ) => break
+                    };
+
+                    Callback::< T >::call_fn( cb, &mut state ).unwrap();
+                }
+            })
+        }).collect();
+
+        ExecutionPool {
+            tx: Some( tx ),
+            handles,
+            phantom: PhantomData
+        }
+    }
+
+    #[inline]
+    pub fn spawn< F >( &self, callback: F ) where F: Send + 'static + for <'a> FnOnce( &'a mut T ) -> io::Result< () > {
+        self.tx.as_ref().unwrap().send( Box::new( callback ) ).unwrap();
+    }
+}
+
+impl< T: Send > Drop for ExecutionPool< T > {
+    fn drop( &mut self ) {
+        mem::drop( self.tx.take() );
+        for handle in self.handles.drain( .. ) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_execution_pool() {
+    let (tx, rx) = mpsc::channel();
+    {
+        let mut next_id = 0;
+        let pool = ExecutionPool::new( 4, || {
+            next_id += 1;
+            next_id
+        });
+
+        for value in 0..100 {
+            let tx = tx.clone();
+            pool.spawn( move |thread_id: &mut u32| {
+                tx.send( (*thread_id, value) ).unwrap();
+                Ok(())
+            });
+        }
+    }
+
+    mem::drop( tx );
+    let mut values: Vec< _ > = rx.iter().map( |(thread_id, value)| {
+        assert!( (1..=4).contains( &thread_id ) );
+        value
+    }).collect();
+
+    values.sort();
+    assert_eq!( values, (0..100).collect::< Vec< _ > >() );
+}
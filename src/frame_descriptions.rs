@@ -1,13 +1,23 @@
+use std::borrow::Cow;
 use std::ops::{Deref, Range};
 use std::sync::Arc;
 use std::mem::{self, ManuallyDrop};
 use std::ptr;
 use std::cell::UnsafeCell;
 use std::time::Instant;
+use std::collections::HashMap;
+
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+use parking_lot::{Mutex, RwLock};
 
 use gimli::{
     BaseAddresses,
     EhFrame,
+    EhFrameHdr,
+    EhFrameOffset,
+    ParsedEhFrameHdr,
+    Pointer,
+    Reader,
     DebugFrame,
     InitializedUnwindContext,
     UninitializedUnwindContext,
@@ -17,17 +27,56 @@ use gimli::{
     FrameDescriptionEntry,
     EndianBuf,
     UnwindTable,
-    UnwindTableRow
+    UnwindTableRow,
+    CfaRule,
+    RegisterRule
 };
 
 use utils::get_ms;
 use binary::BinaryData;
 use arch::Endianity;
+use archive::Bitness;
 use range_map::RangeMap;
+use dwarf_regs::DwarfRegs;
+
+// How many unwind rules we keep around before we start from scratch.
+const MAX_CACHED_UNWIND_RULES: usize = 16384;
+
+#[derive(Copy, Clone, Debug)]
+pub enum CachedRegisterRule {
+    Undefined,
+    Offset( i64 )
+}
+
+// A precompiled unwind row for the most common case where the CFA is a register
+// plus an offset and every register is either undefined or saved at an offset
+// from the CFA, which is cheap to evaluate without going through the FDE again.
+#[derive(Clone, Debug)]
+pub struct CachedUnwindRule {
+    pub initial_address: u64,
+    pub return_address_register: u16,
+    pub cfa_register: u16,
+    pub cfa_offset: i64,
+    pub registers: Vec< (u16, CachedRegisterRule) >
+}
+
+// Whether the frame at a given address is a signal frame, which is
+// something we'd otherwise have to figure out on every unwind.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FrameKind {
+    Regular,
+    Signal,
+    // For architectures which have multiple kinds of signal frames;
+    // the value is the offset of the saved registers from the stack pointer.
+    SignalAt( u64 )
+}
 
 pub struct ContextCache< E: Endianity > {
     cached_eh_frame: Vec< UninitializedUnwindContext< EhFrame< EndianBuf< 'static, E > >, EndianBuf< 'static, E > > >,
-    cached_debug_frame: Vec< UninitializedUnwindContext< DebugFrame< EndianBuf< 'static, E > >, EndianBuf< 'static, E > > >
+    cached_debug_frame: Vec< UninitializedUnwindContext< DebugFrame< EndianBuf< 'static, E > >, EndianBuf< 'static, E > > >,
+    unwind_rules: HashMap< u64, CachedUnwindRule >,
+    frame_kinds: HashMap< u64, FrameKind >,
+    generation: u64
 }
 
 trait CachableSection< 'a, E: Endianity >: UnwindSection< EndianBuf< 'a, E > > where <Self as UnwindSection< EndianBuf< 'a, E > >>::Offset: UnwindOffset {
@@ -71,8 +120,51 @@ impl< E: Endianity > ContextCache< E > {
     pub fn new() -> Self {
         ContextCache {
             cached_eh_frame: Vec::new(),
-            cached_debug_frame: Vec::new()
+            cached_debug_frame: Vec::new(),
+            unwind_rules: HashMap::new(),
+            frame_kinds: HashMap::new(),
+            generation: 0
+        }
+    }
+
+    // The cached rules and frame kinds are keyed by absolute addresses, so they're only valid
+    // for as long as the address space they came from doesn't change.
+    #[inline]
+    fn update_generation( &mut self, generation: u64 ) {
+        if self.generation != generation {
+            self.unwind_rules.clear();
+            self.frame_kinds.clear();
+            self.generation = generation;
+        }
+    }
+
+    #[inline]
+    pub fn get_unwind_rule( &mut self, generation: u64, address: u64 ) -> Option< &CachedUnwindRule > {
+        self.update_generation( generation );
+        self.unwind_rules.get( &address )
+    }
+
+    pub fn cache_unwind_rule( &mut self, address: u64, rule: CachedUnwindRule ) {
+        if self.unwind_rules.len() >= MAX_CACHED_UNWIND_RULES {
+            self.unwind_rules.clear();
         }
+
+        self.unwind_rules.insert( address, rule );
+    }
+
+    pub fn get_frame_kind< F: FnOnce() -> FrameKind >( &mut self, generation: u64, address: u64, classify: F ) -> FrameKind {
+        self.update_generation( generation );
+        if let Some( &kind ) = self.frame_kinds.get( &address ) {
+            return kind;
+        }
+
+        if self.frame_kinds.len() >= MAX_CACHED_UNWIND_RULES {
+            self.frame_kinds.clear();
+        }
+
+        let kind = classify();
+        self.frame_kinds.insert( address, kind );
+        kind
     }
 
     #[inline]
@@ -91,12 +183,140 @@ pub struct AddressMapping {
     pub size: u64
 }
 
+fn translate_address( mappings: &[AddressMapping], address: u64 ) -> u64 {
+    if let Some( mapping ) = mappings.iter().find( |mapping| address >= mapping.actual_address && address < (mapping.actual_address + mapping.size) ) {
+        address - mapping.actual_address + mapping.declared_address
+    } else {
+        address
+    }
+}
+
 type EhFrameDescription< 'a, E > = FrameDescriptionEntry< EhFrame< EndianBuf< 'a, E > >, EndianBuf< 'a, E > >;
 type DebugFrameDescription< 'a, E > = FrameDescriptionEntry< DebugFrame< EndianBuf< 'a, E > >, EndianBuf< 'a, E > >;
+type SharedEhFrameDescriptions< E > = Arc< RangeMap< EhFrameDescription< 'static, E > > >;
+
+// An FDE parsed on its own from a copy of its entry. Its CIE pointer is relative
+// to where the entry is, so in the copy we make it point to the copy itself to
+// keep gimli happy and take the CIE from the real `.eh_frame` instead.
+struct DetachedDescription< 'a, E: Endianity > {
+    description: EhFrameDescription< 'a, E >,
+    // This is what the `description` points into, so it must be dropped after it.
+    _data: Box< [u8] >
+}
+
+struct EhFrameHdrTable< 'a, E: Endianity > {
+    header: ParsedEhFrameHdr< EndianBuf< 'a, E > >,
+    header_address: u64,
+    eh_frame: EndianBuf< 'a, E >,
+    eh_frame_address: u64,
+    text_address: Option< u64 >,
+    data_address: Option< u64 >,
+    // Every FDE we've already looked up, keyed by its offset in the `.eh_frame`.
+    descriptions: RwLock< HashMap< usize, Option< Arc< DetachedDescription< 'a, E > > > > >
+}
+
+// We don't keep any `BaseAddresses` around since gimli mutates them while
+// parsing, so we build fresh ones for every lookup instead.
+fn eh_frame_hdr_bases( address: u64 ) -> BaseAddresses {
+    BaseAddresses::default()
+        .set_cfi( address )
+        .set_data( address )
+}
+
+impl< 'a, E: Endianity > EhFrameHdrTable< 'a, E > {
+    fn bases_at( &self, offset: usize ) -> BaseAddresses {
+        let mut bases = BaseAddresses::default().set_cfi( self.eh_frame_address + offset as u64 );
+        bases.text = self.text_address;
+        bases.data = self.data_address;
+        bases
+    }
+
+    fn find_description( &self, address: u64 ) -> Option< EhFrameDescription< 'a, E > > {
+        let table = self.header.table()?;
+        let fde_address = match table.lookup( address, &eh_frame_hdr_bases( self.header_address ) ) {
+            Ok( Pointer::Direct( fde_address ) ) => fde_address,
+            Ok( Pointer::Indirect( _ ) ) => return None,
+            Err( error ) => {
+                debug!( "Failed to look up 0x{:016X} in .eh_frame_hdr: {}", address, error );
+                return None;
+            }
+        };
+
+        let offset = fde_address.checked_sub( self.eh_frame_address )? as usize;
+        let detached = self.description_at( offset )?;
+        if detached.description.contains( address ) {
+            Some( detached.description.clone() )
+        } else {
+            None
+        }
+    }
+
+    fn description_at( &self, offset: usize ) -> Option< Arc< DetachedDescription< 'a, E > > > {
+        if let Some( detached ) = self.descriptions.read().get( &offset ) {
+            return detached.clone();
+        }
+
+        let detached = self.parse_description( offset ).map( Arc::new );
+        self.descriptions.write().insert( offset, detached.clone() );
+        detached
+    }
+
+    fn parse_description( &self, offset: usize ) -> Option< DetachedDescription< 'a, E > > {
+        let mut input = self.eh_frame;
+        input.skip( offset ).ok()?;
+
+        // The 64-bit format is never used in `.eh_frame`; if we see it something's wrong.
+        let length = input.read_u32().ok()? as usize;
+        if length < 4 || length == 0xFFFF_FFFF {
+            debug!( "Unexpected FDE length {} at offset {} in .eh_frame", length, offset );
+            return None;
+        }
+
+        let cie_pointer = input.read_u32().ok()? as usize;
+        let cie_offset = (offset + 4).checked_sub( cie_pointer )?;
+
+        let mut data: Box< [u8] > = self.eh_frame.buf().get( offset..offset + 4 + length )?.into();
+        if E::get().is_big_endian() {
+            BigEndian::write_u32( &mut data[ 4..8 ], 4 );
+        } else {
+            LittleEndian::write_u32( &mut data[ 4..8 ], 4 );
+        }
+
+        let entry: &'a [u8] = unsafe { mem::transmute( &data[..] ) };
+        let entry = EhFrame::new( entry, E::get() );
+        let bases = self.bases_at( offset );
+        let partial = match entry.entries( &bases ).next() {
+            Ok( Some( CieOrFde::Fde( partial ) ) ) => partial,
+            Ok( _ ) => return None,
+            Err( error ) => {
+                debug!( "Failed to read FDE at offset {} in .eh_frame: {}", offset, error );
+                return None;
+            }
+        };
+
+        let eh_frame = EhFrame::from( self.eh_frame );
+        let description = partial.parse( |_| eh_frame.cie_from_offset( &self.bases_at( 0 ), EhFrameOffset( cie_offset ) ) );
+        match description {
+            Ok( description ) => Some( DetachedDescription {
+                description,
+                _data: data
+            }),
+            Err( error ) => {
+                debug!( "Failed to parse FDE at offset {} in .eh_frame: {}", offset, error );
+                None
+            }
+        }
+    }
+}
 
 pub struct FrameDescriptions< E: Endianity > {
     binary: ManuallyDrop< Arc< BinaryData > >,
+    debug_binary: ManuallyDrop< Option< Arc< BinaryData > > >,
     eh_descriptions: ManuallyDrop< RangeMap< EhFrameDescription< 'static, E > > >,
+    eh_frame_hdr_table: ManuallyDrop< Option< EhFrameHdrTable< 'static, E > > >,
+    // All of the FDEs from the `.eh_frame`, which we only load if the `.eh_frame_hdr`
+    // fails us, since it can be incomplete or broken.
+    eh_fallback_descriptions: ManuallyDrop< Mutex< Option< SharedEhFrameDescriptions< E > > > >,
     debug_descriptions: ManuallyDrop< RangeMap< DebugFrameDescription< 'static, E > > >
 }
 
@@ -105,24 +325,42 @@ impl< E: Endianity > Drop for FrameDescriptions< E > {
     fn drop( &mut self ) {
         unsafe {
             ManuallyDrop::drop( &mut self.eh_descriptions );
+            ManuallyDrop::drop( &mut self.eh_frame_hdr_table );
+            ManuallyDrop::drop( &mut self.eh_fallback_descriptions );
             ManuallyDrop::drop( &mut self.debug_descriptions );
             ManuallyDrop::drop( &mut self.binary );
+            ManuallyDrop::drop( &mut self.debug_binary );
         }
     }
 }
 
 impl< E: Endianity > FrameDescriptions< E > {
-    pub fn load( binary: &Arc< BinaryData > ) -> Option< Self > {
-        let debug_descriptions: RangeMap< DebugFrameDescription< E > > = Self::load_debug_frame( binary );
-        let eh_descriptions: RangeMap< EhFrameDescription< E > > = Self::load_eh_frame( binary );
+    pub fn load( binary: &Arc< BinaryData >, debug_binary: Option< &Arc< BinaryData > > ) -> Option< Self > {
+        // The `.debug_frame` is usually stripped from the binary itself,
+        // so if it's missing we try to use the one from the external debug file.
+        let debug_binary = debug_binary.filter( |_| binary.debug_frame_range().is_none() );
+        let debug_descriptions: RangeMap< DebugFrameDescription< E > > = Self::load_debug_frame( debug_binary.unwrap_or( binary ) );
+
+        // If we have the `.eh_frame_hdr` then we'll only parse the FDEs we need
+        // on demand; otherwise we have to parse all of them upfront.
+        let eh_frame_hdr_table: Option< EhFrameHdrTable< E > > = Self::load_eh_frame_hdr( binary );
+        let eh_descriptions: RangeMap< EhFrameDescription< E > > = if eh_frame_hdr_table.is_some() {
+            RangeMap::new()
+        } else {
+            Self::load_eh_frame( binary )
+        };
 
         let debug_descriptions: RangeMap< DebugFrameDescription< 'static, E > > = unsafe { mem::transmute( debug_descriptions ) };
         let eh_descriptions: RangeMap< EhFrameDescription< 'static, E > > = unsafe { mem::transmute( eh_descriptions ) };
+        let eh_frame_hdr_table: Option< EhFrameHdrTable< 'static, E > > = unsafe { mem::transmute( eh_frame_hdr_table ) };
 
         Some( FrameDescriptions {
             binary: ManuallyDrop::new( binary.clone() ),
+            debug_binary: ManuallyDrop::new( debug_binary.cloned() ),
             debug_descriptions: ManuallyDrop::new( debug_descriptions ),
-            eh_descriptions: ManuallyDrop::new( eh_descriptions )
+            eh_descriptions: ManuallyDrop::new( eh_descriptions ),
+            eh_frame_hdr_table: ManuallyDrop::new( eh_frame_hdr_table ),
+            eh_fallback_descriptions: ManuallyDrop::new( Mutex::new( None ) )
         })
     }
 
@@ -161,7 +399,7 @@ impl< E: Endianity > FrameDescriptions< E > {
             bases = bases.set_cfi( base );
         }
 
-        let debug_frame_data: &[u8] = &binary.as_bytes()[ debug_frame_range.clone() ];
+        let debug_frame_data: &[u8] = binary.get_section_data( debug_frame_range.clone() );
         let debug_frame_data: &'static [u8] = unsafe { mem::transmute( debug_frame_data ) };
         let debug_frame = DebugFrame::new( debug_frame_data, E::get() );
 
@@ -209,6 +447,43 @@ impl< E: Endianity > FrameDescriptions< E > {
         descriptions
     }
 
+    fn load_eh_frame_hdr< 'a >( binary: &'a Arc< BinaryData > ) -> Option< EhFrameHdrTable< 'a, E > > {
+        let eh_frame_hdr_range = binary.eh_frame_hdr_range()?;
+        let eh_frame_range = binary.eh_frame_range()?;
+        let eh_frame_hdr_address = Self::get_base( binary, Some( eh_frame_hdr_range.clone() ) )?;
+        let eh_frame_address = Self::get_base( binary, Some( eh_frame_range.clone() ) )?;
+
+        let address_size = match binary.bitness() {
+            Bitness::B32 => 4,
+            Bitness::B64 => 8
+        };
+
+        let eh_frame_hdr_data = &binary.as_bytes()[ eh_frame_hdr_range ];
+        let header = match EhFrameHdr::new( eh_frame_hdr_data, E::get() ).parse( &eh_frame_hdr_bases( eh_frame_hdr_address ), address_size ) {
+            Ok( header ) => header,
+            Err( error ) => {
+                warn!( "Failed to parse .eh_frame_hdr for '{}': {}", binary.name(), error );
+                return None;
+            }
+        };
+
+        if header.table().is_none() {
+            debug!( "The .eh_frame_hdr for '{}' has no search table", binary.name() );
+            return None;
+        }
+
+        debug!( "Using .eh_frame_hdr for '{}'", binary.name() );
+        Some( EhFrameHdrTable {
+            header,
+            header_address: eh_frame_hdr_address,
+            eh_frame: EndianBuf::new( &binary.as_bytes()[ eh_frame_range ], E::get() ),
+            eh_frame_address,
+            text_address: Self::get_base( binary, binary.text_range() ),
+            data_address: Self::get_base( binary, binary.data_range() ),
+            descriptions: RwLock::new( HashMap::new() )
+        })
+    }
+
     fn load_section< 'a, U: UnwindSection< EndianBuf< 'a, E > > >( bases: BaseAddresses, binary: &Arc< BinaryData >, section: U ) -> RangeMap< FrameDescriptionEntry< U, EndianBuf< 'a, E > > >
         where <U as UnwindSection< EndianBuf< 'a, E > >>::Offset: UnwindOffset
     {
@@ -239,33 +514,72 @@ impl< E: Endianity > FrameDescriptions< E > {
     }
 
     pub fn find_unwind_info< 'a >( &'a self, ctx_cache: &'a mut ContextCache< E >, mappings: &[AddressMapping], address: u64 ) -> Option< UnwindInfo< 'a, E > > {
-        let address = if let Some( mapping ) = mappings.iter().find( |mapping| address >= mapping.actual_address && address < (mapping.actual_address + mapping.size) ) {
-            address - mapping.actual_address + mapping.declared_address
-        } else {
-            address
-        };
+        let address = translate_address( mappings, address );
 
         // HACK: Returning the first `info` invalidates the `ctx_cache` mutable reference,
         //       so we keep a raw pointer to use it again.
         let ctx_cache_ptr = ctx_cache as *mut _;
-        {
-            let info = Self::find_unwind_info_impl( &self.debug_descriptions, ctx_cache, address );
+        if let Some( fde ) = self.debug_descriptions.get_value( address ) {
+            let info = Self::find_unwind_info_impl( fde, ctx_cache, address );
             if info.is_some() {
                 return info;
             }
         }
 
-        Self::find_unwind_info_impl( &self.eh_descriptions, unsafe { &mut *ctx_cache_ptr }, address )
+        let fde = self.find_eh_description( address )?;
+        Self::find_unwind_info_impl( &fde, unsafe { &mut *ctx_cache_ptr }, address )
     }
 
-    fn find_unwind_info_impl< 'a, U: CachableSection< 'a, E > >( descriptions: &RangeMap< FrameDescriptionEntry< U, EndianBuf< 'a, E > > >, ctx_cache: &'a mut ContextCache< E >, address: u64 ) -> Option< UnwindInfo< 'a, E > >
-        where <U as UnwindSection< EndianBuf< 'a, E > >>::Offset: UnwindOffset
-    {
-        if descriptions.is_empty() {
-            return None;
+    pub fn has_unwind_info( &self, mappings: &[AddressMapping], address: u64 ) -> bool {
+        let address = translate_address( mappings, address );
+        if self.debug_descriptions.get_value( address ).is_some() {
+            return true;
         }
 
-        let fde = descriptions.get_value( address )?;
+        self.find_eh_description( address ).is_some()
+    }
+
+    pub fn is_signal_trampoline( &self, mappings: &[AddressMapping], address: u64 ) -> bool {
+        let address = translate_address( mappings, address );
+
+        if let Some( fde ) = self.debug_descriptions.get_value( address ) {
+            return fde.is_signal_trampoline();
+        }
+
+        self.find_eh_description( address ).map( |fde| fde.is_signal_trampoline() ).unwrap_or( false )
+    }
+
+    fn find_eh_description< 'a >( &'a self, address: u64 ) -> Option< Cow< 'a, EhFrameDescription< 'a, E > > > {
+        let eh_frame_hdr_table = match *self.eh_frame_hdr_table {
+            Some( ref eh_frame_hdr_table ) => eh_frame_hdr_table,
+            None => return self.eh_descriptions.get_value( address ).map( Cow::Borrowed )
+        };
+
+        if let Some( fde ) = eh_frame_hdr_table.find_description( address ) {
+            return Some( Cow::Owned( fde ) );
+        }
+
+        let fallback_descriptions = self.eh_fallback_descriptions();
+        fallback_descriptions.get_value( address ).map( |fde| Cow::Owned( fde.clone() ) )
+    }
+
+    fn eh_fallback_descriptions( &self ) -> SharedEhFrameDescriptions< E > {
+        let mut fallback_descriptions = self.eh_fallback_descriptions.lock();
+        if let Some( ref descriptions ) = *fallback_descriptions {
+            return descriptions.clone();
+        }
+
+        debug!( "Falling back to a full scan of .eh_frame for '{}'", self.binary.name() );
+        let descriptions: RangeMap< EhFrameDescription< E > > = Self::load_eh_frame( &self.binary );
+        let descriptions: RangeMap< EhFrameDescription< 'static, E > > = unsafe { mem::transmute( descriptions ) };
+        let descriptions = Arc::new( descriptions );
+        *fallback_descriptions = Some( descriptions.clone() );
+        descriptions
+    }
+
+    fn find_unwind_info_impl< 'a, U: CachableSection< 'a, E > >( fde: &FrameDescriptionEntry< U, EndianBuf< 'a, E > >, ctx_cache: &'a mut ContextCache< E >, address: u64 ) -> Option< UnwindInfo< 'a, E > >
+        where <U as UnwindSection< EndianBuf< 'a, E > >>::Offset: UnwindOffset
+    {
         let ctx = U::get( ctx_cache );
         let ctx = match ctx.initialize( fde.cie() ) {
             Ok( ctx ) => ctx,
@@ -276,6 +590,7 @@ impl< E: Endianity > FrameDescriptions< E > {
         };
 
         let initial_address = fde.initial_address();
+        let return_address_register = fde.cie().return_address_register() as u16;
         let ctx = UnsafeCell::new( ctx );
         let mut table = UnwindTable::new( unsafe { &mut *ctx.get() }, &fde );
         while let Ok( Some( row ) ) = table.next_row() {
@@ -283,6 +598,7 @@ impl< E: Endianity > FrameDescriptions< E > {
                 let row = row.clone();
                 return Some( UnwindInfo {
                     offset_to_initial_address: (initial_address as i64) - (address as i64),
+                    return_address_register,
                     iuc: ManuallyDrop::new( U::wrap_context( ctx ) ),
                     row: ManuallyDrop::new( row ),
                     cache: ctx_cache
@@ -304,6 +620,7 @@ enum IUC< 'a, E: Endianity > {
 
 pub struct UnwindInfo< 'a, E: Endianity + 'a > {
     offset_to_initial_address: i64,
+    return_address_register: u16,
     iuc: ManuallyDrop< IUC< 'a, E > >,
     row: ManuallyDrop< UnwindTableRow< EndianBuf< 'a, E > > >,
     cache: &'a mut ContextCache< E >
@@ -314,6 +631,41 @@ impl< 'a, E: Endianity > UnwindInfo< 'a, E > {
     pub fn offset_to_initial_address( &self ) -> i64 {
         self.offset_to_initial_address
     }
+
+    #[inline]
+    pub fn return_address_register( &self ) -> u16 {
+        self.return_address_register
+    }
+
+    pub fn compile( &self, address: u64 ) -> Option< CachedUnwindRule > {
+        let (cfa_register, cfa_offset) = match *self.row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => (register as u16, offset),
+            _ => return None
+        };
+
+        let mut registers = Vec::new();
+        for &(register, ref rule) in self.row.registers() {
+            if !DwarfRegs::is_tracked( register as u16 ) {
+                continue;
+            }
+
+            let rule = match *rule {
+                RegisterRule::Undefined => CachedRegisterRule::Undefined,
+                RegisterRule::Offset( offset ) => CachedRegisterRule::Offset( offset ),
+                _ => return None
+            };
+
+            registers.push( (register as u16, rule) );
+        }
+
+        Some( CachedUnwindRule {
+            initial_address: ((address as i64) + self.offset_to_initial_address) as u64,
+            return_address_register: self.return_address_register,
+            cfa_register,
+            cfa_offset,
+            registers
+        })
+    }
 }
 
 impl< 'a, E: Endianity > Drop for UnwindInfo< 'a, E > {
@@ -344,3 +696,77 @@ impl< 'a, E: Endianity > Deref for UnwindInfo< 'a, E > {
         &self.row
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+    use gimli::{LittleEndian, DW_EH_PE_datarel, DW_EH_PE_sdata4};
+    use archive::BinaryId;
+    use binary::BinaryData;
+    use super::{FrameDescriptions, ContextCache, FrameKind};
+
+    fn load( filename: &str ) -> Arc< BinaryData > {
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "bin" ).join( filename );
+        Arc::new( BinaryData::load_from_fs( None, path ).unwrap() )
+    }
+
+    #[test]
+    fn eh_frame_hdr_lookup_matches_full_scan_amd64() {
+        let binary = load( "amd64-usleep_in_a_loop_fp" );
+        let eh_frame_hdr_table = FrameDescriptions::< LittleEndian >::load_eh_frame_hdr( &binary ).unwrap();
+        let descriptions = FrameDescriptions::< LittleEndian >::load_eh_frame( &binary );
+        assert_ne!( descriptions.len(), 0 );
+
+        for expected in descriptions.values() {
+            for address in &[ expected.initial_address(), expected.initial_address() + expected.len() - 1 ] {
+                let fde = eh_frame_hdr_table.find_description( *address ).unwrap();
+                assert_eq!( fde.initial_address(), expected.initial_address() );
+                assert_eq!( fde.len(), expected.len() );
+            }
+        }
+
+        // Every FDE is only parsed once.
+        assert_eq!( eh_frame_hdr_table.descriptions.read().len(), descriptions.len() );
+        assert!( eh_frame_hdr_table.find_description( 0 ).is_none() );
+    }
+
+    #[test]
+    fn eh_frame_is_scanned_when_eh_frame_hdr_is_broken() {
+        let binary = load( "amd64-usleep_in_a_loop_fp" );
+        let descriptions = FrameDescriptions::< LittleEndian >::load_eh_frame( &binary );
+
+        // Make every entry of the search table point to the first FDE.
+        let range = binary.eh_frame_hdr_range().unwrap();
+        let mut bytes = binary.as_bytes().to_vec();
+        assert_eq!( bytes[ range.start + 3 ], DW_EH_PE_datarel.0 | DW_EH_PE_sdata4.0 );
+        let table_start = range.start + 12;
+        let first_fde: Vec< u8 > = bytes[ table_start + 4..table_start + 8 ].into();
+        for entry in bytes[ table_start..range.end ].chunks_mut( 8 ) {
+            entry[ 4..8 ].copy_from_slice( &first_fde );
+        }
+
+        let id = BinaryId { inode: 1, dev_major: 0, dev_minor: 0 };
+        let broken_binary = Arc::new( BinaryData::load_from_owned_bytes( "broken", id, bytes ).unwrap() );
+        let frame_descriptions = FrameDescriptions::< LittleEndian >::load( &broken_binary, None ).unwrap();
+        let last = descriptions.values().next_back().unwrap();
+        assert!( frame_descriptions.eh_frame_hdr_table.as_ref().unwrap().find_description( last.initial_address() ).is_none() );
+
+        for expected in descriptions.values() {
+            assert!( frame_descriptions.has_unwind_info( &[], expected.initial_address() ) );
+        }
+    }
+
+    #[test]
+    fn frame_kinds_are_cached_until_the_generation_changes() {
+        let mut ctx_cache = ContextCache::< LittleEndian >::new();
+        let mut count = 0;
+        assert_eq!( ctx_cache.get_frame_kind( 1, 0x1000, || { count += 1; FrameKind::Signal } ), FrameKind::Signal );
+        assert_eq!( ctx_cache.get_frame_kind( 1, 0x1000, || { count += 1; FrameKind::Regular } ), FrameKind::Signal );
+        assert_eq!( count, 1 );
+
+        assert_eq!( ctx_cache.get_frame_kind( 1, 0x2000, || { count += 1; FrameKind::SignalAt( 8 ) } ), FrameKind::SignalAt( 8 ) );
+        assert_eq!( ctx_cache.get_frame_kind( 2, 0x1000, || { count += 1; FrameKind::Regular } ), FrameKind::Regular );
+        assert_eq!( count, 3 );
+    }
+}
@@ -19,6 +19,9 @@ extern crate parking_lot;
 extern crate num_cpus;
 extern crate chrono;
 extern crate cpp_demangle;
+extern crate rustc_demangle;
+extern crate lzma_rs;
+extern crate flate2;
 extern crate speedy;
 #[macro_use]
 extern crate speedy_derive;
@@ -59,6 +62,7 @@ mod arch;
 mod binary;
 mod symbols;
 mod frame_descriptions;
+mod debug_info;
 mod archive;
 mod execution_queue;
 mod kallsyms;
@@ -68,6 +72,7 @@ mod metadata;
 mod cmd_record;
 mod cmd_collate;
 mod cmd_metadata;
+mod cmd_unwind_report;
 
 use std::env;
 use std::error::Error;
@@ -75,6 +80,29 @@ use std::process::exit;
 use clap::{Arg, App, AppSettings, SubCommand};
 
 use cmd_record::TargetProcess;
+use unwind_context::UnwindMode;
+
+fn parse_unwind_mode( value: &str ) -> UnwindMode {
+    match value {
+        "dwarf" => UnwindMode::Dwarf,
+        "fp" => UnwindMode::FramePointer,
+        "dwarf-then-fp" => UnwindMode::DwarfThenFramePointer,
+        _ => unreachable!()
+    }
+}
+
+fn parse_thread_count( value: Option< &str > ) -> Result< usize, Box< dyn Error > > {
+    let thread_count = match value {
+        Some( value ) => value.parse().map_err( |_| "invalid thread count specified in --threads" )?,
+        None => num_cpus::get()
+    };
+
+    if thread_count == 0 {
+        return Err( "the thread count specified in --threads must be at least 1".into() );
+    }
+
+    Ok( thread_count )
+}
 
 fn main_impl() -> Result< (), Box< Error >  > {
     if env::var( "RUST_LOG" ).is_err() {
@@ -116,7 +144,32 @@ fn main_impl() -> Result< (), Box< Error >  > {
                         .long( "stack-size" )
                         .takes_value( true )
                         .default_value( "24576" )
-                        .help( "Size of the gathered stack payloads (in bytes)" )
+                        .help( "Size of the gathered stack payloads (in bytes); the `metadata` subcommand shows how much of it was actually used" )
+                )
+                .arg(
+                    Arg::with_name( "adaptive-stack-size" )
+                        .long( "adaptive-stack-size" )
+                        .help( "Adjusts the size of the gathered stack payloads separately for every thread based on how much of its stack is actually used, starting with `--stack-size`" )
+                )
+                .arg(
+                    Arg::with_name( "min-stack-size" )
+                        .long( "min-stack-size" )
+                        .takes_value( true )
+                        .default_value( "4096" )
+                        .help( "The smallest size of the gathered stack payloads (in bytes) when using `--adaptive-stack-size`" )
+                )
+                .arg(
+                    Arg::with_name( "max-stack-size" )
+                        .long( "max-stack-size" )
+                        .takes_value( true )
+                        .default_value( "65528" )
+                        .help( "The biggest size of the gathered stack payloads (in bytes) when using `--adaptive-stack-size`" )
+                )
+                .arg(
+                    Arg::with_name( "per-cpu-buffers" )
+                        .long( "per-cpu-buffers" )
+                        .conflicts_with( "adaptive-stack-size" )
+                        .help( "Makes all of the threads of the profiled process write into a single ring buffer on every CPU instead of each having their own; greatly reduces the memory used by the ring buffers when profiling processes with many threads (every thread which already exists when profiling starts still needs its own perf event)" )
                 )
                 .arg(
                     Arg::with_name( "output" )
@@ -176,6 +229,36 @@ fn main_impl() -> Result< (), Box< Error >  > {
                         .long( "offline" )
                         .help( "Disable online backtracing" )
                 )
+                .arg(
+                    Arg::with_name( "unwind" )
+                        .long( "unwind" )
+                        .takes_value( true )
+                        .possible_values( &[
+                            "dwarf",
+                            "fp",
+                            "dwarf-then-fp"
+                        ])
+                        .default_value( "dwarf" )
+                        .help( "The method used for online backtracing; `fp` walks the frame pointers, and `dwarf-then-fp` uses them only when there is no unwinding info" )
+                )
+                .arg(
+                    Arg::with_name( "stack-scanning" )
+                        .long( "stack-scanning" )
+                        .help( "When online backtracing stops early scans the stack for return addresses to recover the rest of the frames; such frames are marked as guessed" )
+                )
+                .arg(
+                    Arg::with_name( "live-stack-reads" )
+                        .long( "live-stack-reads" )
+                        .conflicts_with( "offline" )
+                        .help( "Reads the parts of the stack which don't fit in --stack-size directly from the profiled process during online backtracing; this only works for the main thread's stack, and since the process keeps on running those reads are only used when the end of the sampled stack still matches the live one" )
+                )
+                .arg(
+                    Arg::with_name( "unwind-threads" )
+                        .long( "unwind-threads" )
+                        .takes_value( true )
+                        .default_value( "1" )
+                        .help( "The number of threads used for online backtracing, separate from the one which reads the events from the kernel; raise it if events are getting lost at high frequencies" )
+                )
                 .arg(
                     Arg::with_name( "panic-on-partial-backtrace" )
                         .long( "panic-on-partial-backtrace" )
@@ -217,12 +300,96 @@ fn main_impl() -> Result< (), Box< Error >  > {
                         .long( "without-kernel-callstacks" )
                         .help( "Completely ignores kernel callstacks" )
                 )
+                .arg(
+                    Arg::with_name( "line-numbers" )
+                        .long( "line-numbers" )
+                        .help( "Resolves frames to source file names and line numbers; requires the binaries or the debug symbols to have DWARF debug info" )
+                )
+                .arg(
+                    Arg::with_name( "expand-inlined-frames" )
+                        .long( "expand-inlined-frames" )
+                        .help( "Emits separate frames for inlined functions; requires the binaries or the debug symbols to have DWARF debug info" )
+                )
+                .arg(
+                    Arg::with_name( "strip-rust-hashes" )
+                        .long( "strip-rust-hashes" )
+                        .help( "Strips the hashes from demangled Rust symbols" )
+                )
+                .arg(
+                    Arg::with_name( "unwind" )
+                        .long( "unwind" )
+                        .takes_value( true )
+                        .possible_values( &[
+                            "dwarf",
+                            "fp",
+                            "dwarf-then-fp"
+                        ])
+                        .default_value( "dwarf" )
+                        .help( "The method used for offline backtracing; `fp` walks the frame pointers, and `dwarf-then-fp` uses them only when there is no unwinding info" )
+                )
+                .arg(
+                    Arg::with_name( "stack-scanning" )
+                        .long( "stack-scanning" )
+                        .help( "When offline backtracing stops early scans the stack for return addresses to recover the rest of the frames; such frames are marked as guessed" )
+                )
+                .arg(
+                    Arg::with_name( "threads" )
+                        .long( "threads" )
+                        .takes_value( true )
+                        .help( "The number of threads used to process the samples; defaults to the number of CPUs" )
+                )
                 .arg(
                     Arg::with_name( "INPUT" )
                         .required( true )
                         .help( "The input file to use; record it with the `record` subcommand" )
                 )
         )
+        .subcommand(
+            SubCommand::with_name( "unwind-report" )
+                .about( "Summarizes why the offline backtraces have ended; useful to check whether a profile suffers from bad unwinding" )
+                .arg(
+                    Arg::with_name( "debug-symbols" )
+                        .short( "d" )
+                        .long( "debug-symbols" )
+                        .multiple( true )
+                        .takes_value( true )
+                        .help( "A file or directory with extra debugging symbols; can be specified multiple times" )
+                )
+                .arg(
+                    Arg::with_name( "force-stack-size" )
+                        .long( "force-stack-size" )
+                        .takes_value( true )
+                        .hidden( true )
+                )
+                .arg(
+                    Arg::with_name( "unwind" )
+                        .long( "unwind" )
+                        .takes_value( true )
+                        .possible_values( &[
+                            "dwarf",
+                            "fp",
+                            "dwarf-then-fp"
+                        ])
+                        .default_value( "dwarf" )
+                        .help( "The method used for offline backtracing; `fp` walks the frame pointers, and `dwarf-then-fp` uses them only when there is no unwinding info" )
+                )
+                .arg(
+                    Arg::with_name( "stack-scanning" )
+                        .long( "stack-scanning" )
+                        .help( "When offline backtracing stops early scans the stack for return addresses to recover the rest of the frames" )
+                )
+                .arg(
+                    Arg::with_name( "threads" )
+                        .long( "threads" )
+                        .takes_value( true )
+                        .help( "The number of threads used to process the samples; defaults to the number of CPUs" )
+                )
+                .arg(
+                    Arg::with_name( "INPUT" )
+                        .required( true )
+                        .help( "The input file to use; record it with the `record` subcommand and `--offline`" )
+                )
+        )
         .subcommand(
             SubCommand::with_name( "metadata" )
                 .about( "Outputs rudimentary JSON-formatted metadata" )
@@ -256,6 +423,16 @@ fn main_impl() -> Result< (), Box< Error >  > {
 
         let frequency = matches.value_of( "frequency" ).unwrap().parse().map_err( |_| "invalid frequency specified in -F/--frequency" )?;
         let stack_size = matches.value_of( "stack-size" ).unwrap().parse().map_err( |_| "invalid stack size specified in --stack-size" )?;
+        let adaptive_stack_size = matches.occurrences_of( "adaptive-stack-size" ) > 0;
+        let per_cpu_buffers = matches.occurrences_of( "per-cpu-buffers" ) > 0;
+        let min_stack_size = matches.value_of( "min-stack-size" ).unwrap().parse().map_err( |_| "invalid stack size specified in --min-stack-size" )?;
+        let max_stack_size = matches.value_of( "max-stack-size" ).unwrap().parse().map_err( |_| "invalid stack size specified in --max-stack-size" )?;
+        if max_stack_size > perf_group::MAX_STACK_SIZE {
+            return Err( format!( "the stack size specified in --max-stack-size can't be bigger than {}", perf_group::MAX_STACK_SIZE ).into() );
+        }
+        if min_stack_size > max_stack_size {
+            return Err( "the stack size specified in --min-stack-size can't be bigger than the one in --max-stack-size".into() );
+        }
         let sample_count_limit = if let Some( value ) = matches.value_of( "sample-count" ) {
             Some( value.parse().map_err( |_| "invalid sample count specified in --sample-count" )? )
         } else {
@@ -289,18 +466,33 @@ fn main_impl() -> Result< (), Box< Error >  > {
             _ => unreachable!()
         };
 
+        let unwind_mode = parse_unwind_mode( matches.value_of( "unwind" ).unwrap() );
+        let stack_scanning = matches.occurrences_of( "stack-scanning" ) > 0;
+        let live_stack_reads = matches.occurrences_of( "live-stack-reads" ) > 0;
+        let unwind_threads = matches.value_of( "unwind-threads" ).unwrap().parse().map_err( |_| "invalid thread count specified in --unwind-threads" )?;
+        if unwind_threads == 0 {
+            return Err( "the thread count specified in --unwind-threads must be at least 1".into() );
+        }
         let args = cmd_record::Args {
             target_process,
             frequency,
             event_source,
             stack_size,
+            adaptive_stack_size,
+            per_cpu_buffers,
+            min_stack_size,
+            max_stack_size,
             discard_all,
             sample_count_limit,
             time_limit,
             output_path,
             lock_memory,
             offline,
-            panic_on_partial_backtrace
+            panic_on_partial_backtrace,
+            unwind_mode,
+            stack_scanning,
+            live_stack_reads,
+            unwind_threads
         };
 
         cmd_record::main( args )?;
@@ -320,16 +512,50 @@ fn main_impl() -> Result< (), Box< Error >  > {
         };
 
         let without_kernel_callstacks = matches.occurrences_of( "without-kernel-callstacks" ) > 0;
+        let line_numbers = matches.occurrences_of( "line-numbers" ) > 0;
+        let expand_inlined_frames = matches.occurrences_of( "expand-inlined-frames" ) > 0;
+        let strip_rust_hashes = matches.occurrences_of( "strip-rust-hashes" ) > 0;
+        let unwind_mode = parse_unwind_mode( matches.value_of( "unwind" ).unwrap() );
+        let stack_scanning = matches.occurrences_of( "stack-scanning" ) > 0;
+        let thread_count = parse_thread_count( matches.value_of( "threads" ) )?;
         let args = cmd_collate::Args {
             input_path,
             debug_symbols,
             force_stack_size,
             omit_symbols,
             only_sample,
-            without_kernel_callstacks
+            without_kernel_callstacks,
+            line_numbers,
+            expand_inlined_frames,
+            strip_rust_hashes,
+            unwind_mode,
+            stack_scanning,
+            thread_count
         };
 
         cmd_collate::main( args )?;
+    } else if let Some( matches ) = matches.subcommand_matches( "unwind-report" ) {
+        let input_path = matches.value_of_os( "INPUT" ).unwrap();
+        let debug_symbols = matches.values_of_os( "debug-symbols" ).map( |args| args.collect() ).unwrap_or( Vec::new() );
+        let force_stack_size = if let Some( size ) = matches.value_of( "force-stack-size" ) {
+            Some( size.parse().map_err( |_| "invalid size specified in --force-stack-size" )? )
+        } else {
+            None
+        };
+
+        let unwind_mode = parse_unwind_mode( matches.value_of( "unwind" ).unwrap() );
+        let stack_scanning = matches.occurrences_of( "stack-scanning" ) > 0;
+        let thread_count = parse_thread_count( matches.value_of( "threads" ) )?;
+        let args = cmd_unwind_report::Args {
+            input_path,
+            debug_symbols,
+            force_stack_size,
+            unwind_mode,
+            stack_scanning,
+            thread_count
+        };
+
+        cmd_unwind_report::main( args )?;
     } else if let Some( matches ) = matches.subcommand_matches( "metadata" ) {
         let input_path = matches.value_of_os( "INPUT" ).unwrap();
 
@@ -15,9 +15,20 @@ pub struct Binary {
     pub debuglink: Option< String >
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StackUsage {
+    pub sample_count: u64,
+    pub truncated_sample_count: u64,
+    pub dynamic_stack_size_p50: u64,
+    pub dynamic_stack_size_p90: u64,
+    pub dynamic_stack_size_p99: u64,
+    pub dynamic_stack_size_max: u64
+}
+
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct Metadata {
     pub machine_info: Option< MachineInfo >,
     pub processes: Vec< Process >,
-    pub binaries: Vec< Binary >
+    pub binaries: Vec< Binary >,
+    pub stack_usage: Option< StackUsage >
 }
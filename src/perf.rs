@@ -8,7 +8,7 @@ use std::sync::Arc;
 use std::fmt;
 use std::slice;
 use std::ops::Range;
-use std::cmp::max;
+use std::cmp::{min, max};
 use std::cell::Cell;
 
 use libc::{self, pid_t, c_void};
@@ -426,6 +426,12 @@ fn next_raw_event( buffer: *const u8, size: u64, position_cell: &Cell< u64 > ) -
     Some( raw_event_location )
 }
 
+// Which fraction of the ring buffer has to be filled before we're woken up.
+const WAKEUP_WATERMARK_DIVISOR: u32 = 4;
+
+// The biggest ring buffer we'll allocate for threads which share it; 4MB per CPU.
+const MAX_SHARED_RING_BUFFER_PAGE_COUNT: u32 = 1024;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum EventSource {
     HwCpuCycles,
@@ -434,73 +440,148 @@ pub enum EventSource {
     SwPageFaults
 }
 
-impl Perf {
-    pub fn open( pid: u32, cpu: u32, frequency: u64, stack_size: u32, event_source: EventSource ) -> io::Result< Self > {
-        assert_eq!( mem::size_of::< PerfEventMmapPage >(), 1088 );
+// Every thread which writes into the ring buffer gets as much space as it would have
+// with a ring buffer of its own, up to a limit.
+fn ring_buffer_page_count( stack_size: u32, thread_count: u32 ) -> u32 {
+    let required_space = stack_size * 8;
+    let n = (1..26).into_iter().find( |n| (1_u32 << n) * 4096_u32 >= required_space ).expect( "cannot find appropriate page count for given stack size" );
+    let page_count = max( 1 << n, 16 );
+    if thread_count <= 1 {
+        return page_count;
+    }
+
+    let shared_page_count = page_count.saturating_mul( thread_count ).checked_next_power_of_two().unwrap_or( !0 );
+    max( min( shared_page_count, MAX_SHARED_RING_BUFFER_PAGE_COUNT ), page_count )
+}
+
+fn open_event( pid: u32, cpu: u32, frequency: u64, stack_size: u32, event_source: EventSource, inherit: bool, page_count: u32 ) -> io::Result< RawFd > {
+    assert_eq!( mem::size_of::< PerfEventMmapPage >(), 1088 );
 
-        if cfg!( target_arch = "x86_64" ) {
-            assert_eq!( PERF_EVENT_IOC_ENABLE, 9216 );
-        } else if cfg!( target_arch = "mips64" ) {
-            assert_eq!( PERF_EVENT_IOC_ENABLE, 536880128 );
+    if cfg!( target_arch = "x86_64" ) {
+        assert_eq!( PERF_EVENT_IOC_ENABLE, 9216 );
+    } else if cfg!( target_arch = "mips64" ) {
+        assert_eq!( PERF_EVENT_IOC_ENABLE, 536880128 );
+    }
+
+    let mut attr: PerfEventAttr = unsafe { mem::zeroed() };
+    attr.size = mem::size_of::< PerfEventAttr >() as u32;
+
+    match event_source {
+        EventSource::HwCpuCycles => {
+            attr.kind = PERF_TYPE_HARDWARE;
+            attr.config = PERF_COUNT_HW_CPU_CYCLES;
+        },
+        EventSource::HwRefCpuCycles => {
+            attr.kind = PERF_TYPE_HARDWARE;
+            attr.config = PERF_COUNT_HW_REF_CPU_CYCLES;
+        },
+        EventSource::SwCpuClock => {
+            attr.kind = PERF_TYPE_SOFTWARE;
+            attr.config = PERF_COUNT_SW_CPU_CLOCK;
+        },
+        EventSource::SwPageFaults => {
+            attr.kind = PERF_TYPE_SOFTWARE;
+            attr.config = PERF_COUNT_SW_PAGE_FAULTS;
         }
+    }
 
-        let mut attr: PerfEventAttr = unsafe { mem::zeroed() };
-        attr.size = mem::size_of::< PerfEventAttr >() as u32;
+    attr.sample_type =
+        PERF_SAMPLE_IP |
+        PERF_SAMPLE_TID |
+        PERF_SAMPLE_TIME |
+        PERF_SAMPLE_CALLCHAIN |
+        PERF_SAMPLE_CPU |
+        PERF_SAMPLE_PERIOD |
+        PERF_SAMPLE_REGS_USER |
+        PERF_SAMPLE_STACK_USER;
+    attr.sample_regs_user = perf_arch::native::REG_MASK;
+    attr.sample_stack_user = stack_size;
+    attr.sample_period_or_freq = frequency;
+
+    attr.flags =
+        PERF_ATTR_FLAG_DISABLED |
+        PERF_ATTR_FLAG_MMAP |
+        PERF_ATTR_FLAG_MMAP2 |
+        PERF_ATTR_FLAG_MMAP_DATA |
+        PERF_ATTR_FLAG_COMM |
+        PERF_ATTR_FLAG_FREQ |
+        PERF_ATTR_FLAG_EXCLUDE_CALLCHAIN_USER |
+        PERF_ATTR_FLAG_TASK;
+
+    if inherit {
+        attr.flags |= PERF_ATTR_FLAG_INHERIT;
+    }
 
-        match event_source {
-            EventSource::HwCpuCycles => {
-                attr.kind = PERF_TYPE_HARDWARE;
-                attr.config = PERF_COUNT_HW_CPU_CYCLES;
-            },
-            EventSource::HwRefCpuCycles => {
-                attr.kind = PERF_TYPE_HARDWARE;
-                attr.config = PERF_COUNT_HW_REF_CPU_CYCLES;
-            },
-            EventSource::SwCpuClock => {
-                attr.kind = PERF_TYPE_SOFTWARE;
-                attr.config = PERF_COUNT_SW_CPU_CLOCK;
-            },
-            EventSource::SwPageFaults => {
-                attr.kind = PERF_TYPE_SOFTWARE;
-                attr.config = PERF_COUNT_SW_PAGE_FAULTS;
-            }
+    // Only wake us up once the ring buffer has filled up a little instead of after every event;
+    // whatever stays below the watermark is periodically drained by `PerfGroup::wait`.
+    attr.flags |= PERF_ATTR_FLAG_WATERMARK;
+    attr.wakeup_events_or_watermark = 4096 * page_count / WAKEUP_WATERMARK_DIVISOR;
+
+    let fd = sys_perf_event_open( &attr, pid as pid_t, cpu as _, -1, PERF_FLAG_FD_CLOEXEC );
+    if fd < 0 {
+        let err = io::Error::from_raw_os_error( -fd );
+        error!( "The perf_event_open syscall failed for PID {}: {}", pid, err );
+        return Err( err );
+    }
+
+    Ok( fd )
+}
+
+fn enable_event( fd: RawFd ) {
+    let result = unsafe {
+        libc::ioctl( fd, PERF_EVENT_IOC_ENABLE as _ )
+    };
+
+    assert!( result != -1 );
+}
+
+// An event which doesn't have a ring buffer of its own and instead
+// writes its samples into the ring buffer of another event.
+pub struct RedirectedPerf {
+    fd: RawFd
+}
+
+impl Drop for RedirectedPerf {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.fd );
         }
+    }
+}
+
+impl RedirectedPerf {
+    pub fn open( pid: u32, cpu: u32, frequency: u64, stack_size: u32, event_source: EventSource, inherit: bool, output: &Perf ) -> io::Result< Self > {
+        let fd = open_event( pid, cpu, frequency, stack_size, event_source, inherit, (output.size / 4096) as u32 )?;
+        let perf = RedirectedPerf { fd };
 
-        attr.sample_type =
-            PERF_SAMPLE_IP |
-            PERF_SAMPLE_TID |
-            PERF_SAMPLE_TIME |
-            PERF_SAMPLE_CALLCHAIN |
-            PERF_SAMPLE_CPU |
-            PERF_SAMPLE_PERIOD |
-            PERF_SAMPLE_REGS_USER |
-            PERF_SAMPLE_STACK_USER;
-        attr.sample_regs_user = perf_arch::native::REG_MASK;
-        attr.sample_stack_user = stack_size;
-        attr.sample_period_or_freq = frequency;
-
-        attr.flags =
-            PERF_ATTR_FLAG_DISABLED |
-            PERF_ATTR_FLAG_MMAP |
-            PERF_ATTR_FLAG_MMAP2 |
-            PERF_ATTR_FLAG_MMAP_DATA |
-            PERF_ATTR_FLAG_COMM |
-            PERF_ATTR_FLAG_FREQ |
-            PERF_ATTR_FLAG_EXCLUDE_CALLCHAIN_USER |
-            PERF_ATTR_FLAG_INHERIT |
-            PERF_ATTR_FLAG_TASK;
-
-        let fd = sys_perf_event_open( &attr, pid as pid_t, cpu as _, -1, PERF_FLAG_FD_CLOEXEC );
-        if fd < 0 {
-            let err = io::Error::from_raw_os_error( -fd );
-            error!( "The perf_event_open syscall failed for PID {}: {}", pid, err );
+        // The kernel only allows this when both events are on the same CPU.
+        let result = unsafe { libc::ioctl( fd, PERF_EVENT_IOC_SET_OUTPUT as _, output.fd ) };
+        if result < 0 {
+            let err = io::Error::last_os_error();
+            error!( "Failed to redirect the perf events of PID {} on CPU {}: {}", pid, cpu, err );
             return Err( err );
         }
 
-        let required_space = stack_size * 8;
+        Ok( perf )
+    }
+
+    pub fn enable( &mut self ) {
+        enable_event( self.fd );
+    }
+
+    #[inline]
+    pub fn fd( &self ) -> RawFd {
+        self.fd
+    }
+}
+
+impl Perf {
+    // The `thread_count` is how many threads are going to write into this ring buffer.
+    pub fn open( pid: u32, cpu: u32, frequency: u64, stack_size: u32, event_source: EventSource, inherit: bool, thread_count: u32 ) -> io::Result< Self > {
         let page_size = 4096;
-        let n = (1..26).into_iter().find( |n| (1_u32 << n) * 4096_u32 >= required_space ).expect( "cannot find appropriate page count for given stack size" );
-        let page_count: u32 = max( 1 << n, 16 );
+        let page_count = ring_buffer_page_count( stack_size, thread_count );
+        let fd = open_event( pid, cpu, frequency, stack_size, event_source, inherit, page_count )?;
+
         debug!( "Allocating {} + 1 pages for the ring buffer for PID {} on CPU {}", page_count, pid, cpu );
 
         let full_size = (page_size * (page_count + 1)) as usize;
@@ -527,11 +608,7 @@ impl Perf {
     }
 
     pub fn enable( &mut self ) {
-        let result = unsafe {
-            libc::ioctl( self.fd, PERF_EVENT_IOC_ENABLE as _ )
-        };
-
-        assert!( result != -1 );
+        enable_event( self.fd );
     }
 
     #[allow(dead_code)]
@@ -698,3 +775,15 @@ impl< 'a > Iterator for EventIter< 'a > {
         Some( event )
     }
 }
+
+#[test]
+fn test_ring_buffer_page_count() {
+    assert_eq!( ring_buffer_page_count( 8, 1 ), 16 );
+    assert_eq!( ring_buffer_page_count( 24576, 1 ), 64 );
+
+    // Threads which share a ring buffer get as much space as they would have on their own.
+    assert_eq!( ring_buffer_page_count( 24576, 2 ), 128 );
+    assert_eq!( ring_buffer_page_count( 24576, 3 ), 256 );
+    assert_eq!( ring_buffer_page_count( 24576, 1000 ), MAX_SHARED_RING_BUFFER_PAGE_COUNT );
+    assert_eq!( ring_buffer_page_count( 24576, !0 ), MAX_SHARED_RING_BUFFER_PAGE_COUNT );
+}
@@ -127,6 +127,82 @@ pub mod amd64 {
     );
 }
 
+pub mod x86 {
+    use perf_sys::*;
+    use arch::x86::dwarf::*;
+
+    #[allow(dead_code)]
+    fn validate( register: u16, value: u64 ) -> bool {
+        // Just as on AMD64 the EBP we get can be invalid
+        // if the call chain crosses the kernel space boundary.
+        if register == EBP && value as u32 == !0 {
+            return false;
+        }
+
+        return true;
+    }
+
+    define_regs!(
+        PERF_REG_X86_AX => EAX,
+        PERF_REG_X86_BX => EBX,
+        PERF_REG_X86_CX => ECX,
+        PERF_REG_X86_DX => EDX,
+        PERF_REG_X86_SI => ESI,
+        PERF_REG_X86_DI => EDI,
+        PERF_REG_X86_BP => EBP,
+        PERF_REG_X86_SP => ESP,
+        PERF_REG_X86_IP => RETURN_ADDRESS,
+        PERF_REG_X86_FLAGS => FLAGS,
+        PERF_REG_X86_CS => CS,
+        PERF_REG_X86_SS => SS,
+        PERF_REG_X86_DS => DS,
+        PERF_REG_X86_ES => ES,
+        PERF_REG_X86_FS => FS,
+        PERF_REG_X86_GS => GS
+    );
+}
+
+pub mod mips {
+    use perf_sys::*;
+    use arch::mips::dwarf::*;
+
+    #[allow(dead_code)]
+    fn validate( _register: u16, _value: u64 ) -> bool { return true; }
+
+    define_regs!(
+        PERF_REG_MIPS_PC => PC,
+        PERF_REG_MIPS_R1 => R1,
+        PERF_REG_MIPS_R2 => R2,
+        PERF_REG_MIPS_R3 => R3,
+        PERF_REG_MIPS_R4 => R4,
+        PERF_REG_MIPS_R5 => R5,
+        PERF_REG_MIPS_R6 => R6,
+        PERF_REG_MIPS_R7 => R7,
+        PERF_REG_MIPS_R8 => R8,
+        PERF_REG_MIPS_R9 => R9,
+        PERF_REG_MIPS_R10 => R10,
+        PERF_REG_MIPS_R11 => R11,
+        PERF_REG_MIPS_R12 => R12,
+        PERF_REG_MIPS_R13 => R13,
+        PERF_REG_MIPS_R14 => R14,
+        PERF_REG_MIPS_R15 => R15,
+        PERF_REG_MIPS_R16 => R16,
+        PERF_REG_MIPS_R17 => R17,
+        PERF_REG_MIPS_R18 => R18,
+        PERF_REG_MIPS_R19 => R19,
+        PERF_REG_MIPS_R20 => R20,
+        PERF_REG_MIPS_R21 => R21,
+        PERF_REG_MIPS_R22 => R22,
+        PERF_REG_MIPS_R23 => R23,
+        PERF_REG_MIPS_R24 => R24,
+        PERF_REG_MIPS_R25 => R25,
+        PERF_REG_MIPS_R28 => R28,
+        PERF_REG_MIPS_R29 => R29,
+        PERF_REG_MIPS_R30 => R30,
+        PERF_REG_MIPS_R31 => R31
+    );
+}
+
 pub mod mips64 {
     use perf_sys::*;
     use arch::mips64::dwarf::*;
@@ -193,13 +269,112 @@ pub mod arm {
     );
 }
 
+pub mod aarch64 {
+    use perf_sys::*;
+    use arch::aarch64::dwarf::*;
+
+    #[allow(dead_code)]
+    fn validate( _register: u16, _value: u64 ) -> bool { return true; }
+
+    define_regs!(
+        PERF_REG_ARM64_X0 => X0,
+        PERF_REG_ARM64_X1 => X1,
+        PERF_REG_ARM64_X2 => X2,
+        PERF_REG_ARM64_X3 => X3,
+        PERF_REG_ARM64_X4 => X4,
+        PERF_REG_ARM64_X5 => X5,
+        PERF_REG_ARM64_X6 => X6,
+        PERF_REG_ARM64_X7 => X7,
+        PERF_REG_ARM64_X8 => X8,
+        PERF_REG_ARM64_X9 => X9,
+        PERF_REG_ARM64_X10 => X10,
+        PERF_REG_ARM64_X11 => X11,
+        PERF_REG_ARM64_X12 => X12,
+        PERF_REG_ARM64_X13 => X13,
+        PERF_REG_ARM64_X14 => X14,
+        PERF_REG_ARM64_X15 => X15,
+        PERF_REG_ARM64_X16 => X16,
+        PERF_REG_ARM64_X17 => X17,
+        PERF_REG_ARM64_X18 => X18,
+        PERF_REG_ARM64_X19 => X19,
+        PERF_REG_ARM64_X20 => X20,
+        PERF_REG_ARM64_X21 => X21,
+        PERF_REG_ARM64_X22 => X22,
+        PERF_REG_ARM64_X23 => X23,
+        PERF_REG_ARM64_X24 => X24,
+        PERF_REG_ARM64_X25 => X25,
+        PERF_REG_ARM64_X26 => X26,
+        PERF_REG_ARM64_X27 => X27,
+        PERF_REG_ARM64_X28 => X28,
+        PERF_REG_ARM64_X29 => X29,
+        PERF_REG_ARM64_LR => X30,
+        PERF_REG_ARM64_SP => SP,
+        PERF_REG_ARM64_PC => PC
+    );
+}
+
+pub mod riscv64 {
+    use perf_sys::*;
+    use arch::riscv64::dwarf::*;
+
+    #[allow(dead_code)]
+    fn validate( _register: u16, _value: u64 ) -> bool { return true; }
+
+    define_regs!(
+        PERF_REG_RISCV_PC => PC,
+        PERF_REG_RISCV_RA => X1,
+        PERF_REG_RISCV_SP => X2,
+        PERF_REG_RISCV_GP => X3,
+        PERF_REG_RISCV_TP => X4,
+        PERF_REG_RISCV_T0 => X5,
+        PERF_REG_RISCV_T1 => X6,
+        PERF_REG_RISCV_T2 => X7,
+        PERF_REG_RISCV_S0 => X8,
+        PERF_REG_RISCV_S1 => X9,
+        PERF_REG_RISCV_A0 => X10,
+        PERF_REG_RISCV_A1 => X11,
+        PERF_REG_RISCV_A2 => X12,
+        PERF_REG_RISCV_A3 => X13,
+        PERF_REG_RISCV_A4 => X14,
+        PERF_REG_RISCV_A5 => X15,
+        PERF_REG_RISCV_A6 => X16,
+        PERF_REG_RISCV_A7 => X17,
+        PERF_REG_RISCV_S2 => X18,
+        PERF_REG_RISCV_S3 => X19,
+        PERF_REG_RISCV_S4 => X20,
+        PERF_REG_RISCV_S5 => X21,
+        PERF_REG_RISCV_S6 => X22,
+        PERF_REG_RISCV_S7 => X23,
+        PERF_REG_RISCV_S8 => X24,
+        PERF_REG_RISCV_S9 => X25,
+        PERF_REG_RISCV_S10 => X26,
+        PERF_REG_RISCV_S11 => X27,
+        PERF_REG_RISCV_T3 => X28,
+        PERF_REG_RISCV_T4 => X29,
+        PERF_REG_RISCV_T5 => X30,
+        PERF_REG_RISCV_T6 => X31
+    );
+}
+
 pub mod native {
     #[cfg(target_arch = "x86_64")]
     pub use super::amd64::*;
 
+    #[cfg(target_arch = "x86")]
+    pub use super::x86::*;
+
+    #[cfg(target_arch = "mips")]
+    pub use super::mips::*;
+
     #[cfg(target_arch = "mips64")]
     pub use super::mips64::*;
 
     #[cfg(target_arch = "arm")]
     pub use super::arm::*;
+
+    #[cfg(target_arch = "aarch64")]
+    pub use super::aarch64::*;
+
+    #[cfg(target_arch = "riscv64")]
+    pub use super::riscv64::*;
 }
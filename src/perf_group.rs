@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::{self, Read};
 use std::fs::{self, File};
 use std::os::unix::io::RawFd;
@@ -6,12 +6,13 @@ use std::ops::{Deref, DerefMut};
 use std::cell::Cell;
 use std::vec;
 use std::mem;
+use std::cmp::{min, max};
 
 use num_cpus;
 use libc;
 
 use utils::read_string_lossy;
-use perf::{Perf, EventRef, Event, CommEvent, Mmap2Event, EventSource};
+use perf::{Perf, RedirectedPerf, EventRef, Event, CommEvent, Mmap2Event, EventSource};
 use maps;
 
 struct StoppedProcess( u32 );
@@ -37,20 +38,51 @@ impl Drop for StoppedProcess {
     }
 }
 
+// The kernel only accepts stack sizes which are a multiple of 8 and fit in 16 bits.
+pub const MAX_STACK_SIZE: u32 = 65528;
+
+// How many samples we look at before trying to shrink the stack size of a thread.
+const STACK_SIZE_SHRINK_WINDOW: u32 = 256;
+
+// How long we wait at most before draining the events which haven't reached the wakeup watermark.
+const DRAIN_PERIOD_MS: i32 = 1000;
+
 struct Member {
     perf: Perf,
+    tid: u32,
+    cpu: u32,
+    stack_size: u32,
     is_closed: Cell< bool >
 }
 
 impl Member {
-    fn new( perf: Perf ) -> Self {
+    fn new( perf: Perf, tid: u32, cpu: u32, stack_size: u32 ) -> Self {
         Member {
             perf,
+            tid,
+            cpu,
+            stack_size,
             is_closed: Cell::new( false )
         }
     }
 }
 
+struct ThreadStackSize {
+    stack_size: u32,
+    sample_count: u32,
+    max_dynamic_stack_size: u64
+}
+
+impl ThreadStackSize {
+    fn new( stack_size: u32 ) -> Self {
+        ThreadStackSize {
+            stack_size,
+            sample_count: 0,
+            max_dynamic_stack_size: 0
+        }
+    }
+}
+
 impl Deref for Member {
     type Target = Perf;
     fn deref( &self ) -> &Self::Target {
@@ -67,37 +99,29 @@ impl DerefMut for Member {
 pub struct PerfGroup {
     event_buffer: Vec< EventRef >,
     members: BTreeMap< RawFd, Member >,
-    poll_fds: Vec< libc::pollfd >,
+    // Events which write into the ring buffer of another member, along with
+    // the file descriptor of that member.
+    redirected_members: BTreeMap< RawFd, (RawFd, RedirectedPerf) >,
+    epoll_fd: RawFd,
+    epoll_events: Vec< libc::epoll_event >,
+    // The members which have something for us since the last `iter`.
+    ready_members: BTreeSet< RawFd >,
     frequency: u64,
     stack_size: u32,
     event_source: EventSource,
     initial_events: Vec< Event< 'static > >,
-    stopped_processes: Vec< StoppedProcess >
+    stopped_processes: Vec< StoppedProcess >,
+    is_stack_size_adaptive: bool,
+    min_stack_size: u32,
+    max_stack_size: u32,
+    thread_stack_sizes: HashMap< u32, ThreadStackSize >,
+    has_pending_resizes: bool,
+    is_per_cpu: bool
 }
 
-fn poll_events< 'a, I >( poll_fds: &mut Vec< libc::pollfd >, iter: I ) where I: IntoIterator< Item = &'a Member >, <I as IntoIterator>::IntoIter: Clone {
-    let iter = iter.into_iter();
-
-    poll_fds.clear();
-    poll_fds.extend( iter.clone().map( |member| {
-        libc::pollfd {
-            fd: member.fd(),
-            events: libc::POLLIN | libc::POLLHUP,
-            revents: 0
-        }
-    }));
-
-    let ok = unsafe { libc::poll( poll_fds.as_ptr() as *mut _, poll_fds.len() as _, 1000 ) };
-    if ok == -1 {
-        let err = io::Error::last_os_error();
-        if err.kind() != io::ErrorKind::Interrupted {
-            panic!( "poll failed: {}", err );
-        }
-    }
-
-    for (member, poll_fd) in iter.zip( poll_fds.iter() ) {
-        member.is_closed.set( poll_fd.revents & libc::POLLHUP != 0 );
-    }
+fn clamp_stack_size( stack_size: u64, min_stack_size: u32, max_stack_size: u32 ) -> u32 {
+    let stack_size = min( max( stack_size, min_stack_size as u64 ), max_stack_size as u64 ) as u32;
+    stack_size & !7
 }
 
 fn get_threads( pid: u32 ) -> Result< Vec< (u32, Option< Vec< u8 > >) >, io::Error > {
@@ -132,45 +156,159 @@ fn get_threads( pid: u32 ) -> Result< Vec< (u32, Option< Vec< u8 > >) >, io::Err
     Ok( output )
 }
 
+impl Drop for PerfGroup {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.epoll_fd );
+        }
+    }
+}
+
 impl PerfGroup {
-    pub fn new( frequency: u64, stack_size: u32, event_source: EventSource ) -> Self {
+    pub fn new( frequency: u64, stack_size: u32, event_source: EventSource ) -> Result< Self, io::Error > {
+        let epoll_fd = unsafe { libc::epoll_create1( libc::EPOLL_CLOEXEC ) };
+        if epoll_fd < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
         let group = PerfGroup {
             event_buffer: Vec::new(),
             members: Default::default(),
-            poll_fds: Vec::new(),
+            redirected_members: BTreeMap::new(),
+            epoll_fd,
+            epoll_events: Vec::new(),
+            ready_members: BTreeSet::new(),
             frequency,
             stack_size,
             event_source,
             initial_events: Vec::new(),
-            stopped_processes: Vec::new()
+            stopped_processes: Vec::new(),
+            is_stack_size_adaptive: false,
+            min_stack_size: stack_size,
+            max_stack_size: stack_size,
+            thread_stack_sizes: HashMap::new(),
+            has_pending_resizes: false,
+            is_per_cpu: false
         };
 
-        group
+        Ok( group )
     }
 
-    pub fn open( pid: u32, frequency: u64, stack_size: u32, event_source: EventSource ) -> Result< Self, io::Error > {
-        let mut group = PerfGroup::new( frequency, stack_size, event_source );
-        group.open_process( pid )?;
-        Ok( group )
+    fn epoll_ctl( &self, operation: libc::c_int, fd: RawFd ) -> Result< (), io::Error > {
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLHUP) as u32,
+            u64: fd as u64
+        };
+
+        let result = unsafe { libc::epoll_ctl( self.epoll_fd, operation, fd, &mut event ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok(())
+    }
+
+    fn insert_member( &mut self, member: Member ) -> Result< (), io::Error > {
+        self.epoll_ctl( libc::EPOLL_CTL_ADD, member.fd() )?;
+        self.members.insert( member.fd(), member );
+        Ok(())
+    }
+
+    fn remove_member( &mut self, fd: RawFd ) -> Option< Member > {
+        let member = self.members.remove( &fd )?;
+        let _ = self.epoll_ctl( libc::EPOLL_CTL_DEL, fd );
+        self.ready_members.remove( &fd );
+        Some( member )
+    }
+
+    fn has_redirected_members( &self, output_fd: RawFd ) -> bool {
+        self.redirected_members.values().any( |&(fd, _)| fd == output_fd )
+    }
+
+    // Makes `wait` also wake up when the given file descriptor becomes readable.
+    pub fn watch_fd( &mut self, fd: RawFd ) -> Result< (), io::Error > {
+        self.epoll_ctl( libc::EPOLL_CTL_ADD, fd )
+    }
+
+    // Makes every thread get its own stack size which follows how much of the stack
+    // it actually uses. Since we can only change the stack size by reopening the events
+    // they can't be inherited, so every new thread has to be registered with `open_thread`.
+    pub fn set_adaptive_stack_size( &mut self, min_stack_size: u32, max_stack_size: u32 ) {
+        assert!( !self.is_per_cpu, "an adaptive stack size can't be used with per-CPU buffers" );
+        self.is_stack_size_adaptive = true;
+        self.min_stack_size = min_stack_size;
+        self.max_stack_size = max_stack_size;
+        self.stack_size = clamp_stack_size( self.stack_size as u64, min_stack_size, max_stack_size );
+    }
+
+    // Makes all of the threads of a process share a single ring buffer on every CPU,
+    // so instead of a ring buffer per every thread and CPU we only need one per CPU.
+    // The samples are told apart by their thread ID anyway.
+    pub fn set_per_cpu_buffers( &mut self ) {
+        assert!( !self.is_stack_size_adaptive, "per-CPU buffers can't be used with an adaptive stack size" );
+        self.is_per_cpu = true;
+    }
+
+    fn open_member( &self, tid: u32, cpu: u32, stack_size: u32, thread_count: u32 ) -> Result< Member, io::Error > {
+        let perf = Perf::open( tid, cpu, self.frequency, stack_size, self.event_source, !self.is_stack_size_adaptive, thread_count )?;
+        Ok( Member::new( perf, tid, cpu, stack_size ) )
+    }
+
+    // Opens a member for `pid` on every CPU and makes the events of every thread
+    // from `tids` write into its ring buffer.
+    fn open_shared_members( &mut self, pid: u32, tids: &[u32] ) -> Result< (), io::Error > {
+        let mut members = Vec::new();
+        let mut redirected_members = Vec::new();
+        for cpu in 0..num_cpus::get() {
+            let member = self.open_member( pid, cpu as _, self.stack_size, tids.len() as u32 + 1 )?;
+            for &tid in tids {
+                let perf = RedirectedPerf::open( tid, cpu as _, self.frequency, self.stack_size, self.event_source, true, &member )?;
+                redirected_members.push( (member.fd(), perf) );
+            }
+
+            members.push( member );
+        }
+
+        for member in members {
+            self.insert_member( member )?;
+        }
+
+        // Each of these events hangs up on its own when its thread exits,
+        // so we have to watch them separately from the member they write into.
+        for (output_fd, perf) in redirected_members {
+            self.epoll_ctl( libc::EPOLL_CTL_ADD, perf.fd() )?;
+            self.redirected_members.insert( perf.fd(), (output_fd, perf) );
+        }
+
+        Ok(())
     }
 
     pub fn open_process( &mut self, pid: u32 ) -> Result< (), io::Error > {
         self.stopped_processes.push( StoppedProcess::new( pid )? );
-        let mut perf_events = Vec::new();
         let threads = get_threads( pid )?;
 
-        for cpu in 0..num_cpus::get() {
-            let perf = Perf::open( pid, cpu as _, self.frequency, self.stack_size, self.event_source )?;
-            perf_events.push( perf );
+        if self.is_per_cpu {
+            let tids: Vec< _ > = threads.iter().map( |&(tid, _)| tid ).collect();
+            self.open_shared_members( pid, &tids )?;
+        } else {
+            let mut members = Vec::new();
+            for cpu in 0..num_cpus::get() {
+                members.push( self.open_member( pid, cpu as _, self.stack_size, 1 )? );
+                for &(tid, _) in &threads {
+                    members.push( self.open_member( tid, cpu as _, self.stack_size, 1 )? );
+                }
+            }
 
-            for &(tid, _) in &threads {
-                let perf = Perf::open( tid, cpu as _, self.frequency, self.stack_size, self.event_source )?;
-                perf_events.push( perf );
+            for member in members {
+                self.insert_member( member )?;
             }
         }
 
-        for perf in perf_events {
-            self.members.insert( perf.fd(), Member::new( perf ) );
+        if self.is_stack_size_adaptive {
+            self.thread_stack_sizes.insert( pid, ThreadStackSize::new( self.stack_size ) );
+            for &(tid, _) in &threads {
+                self.thread_stack_sizes.insert( tid, ThreadStackSize::new( self.stack_size ) );
+            }
         }
 
         let maps = read_string_lossy( &format!( "/proc/{}/maps", pid ) )?;
@@ -222,6 +360,111 @@ impl PerfGroup {
         Ok(())
     }
 
+    pub fn open_thread( &mut self, tid: u32 ) -> Result< (), io::Error > {
+        if !self.is_stack_size_adaptive || self.thread_stack_sizes.contains_key( &tid ) {
+            return Ok(());
+        }
+
+        let mut members = Vec::new();
+        for cpu in 0..num_cpus::get() {
+            let mut member = self.open_member( tid, cpu as _, self.stack_size, 1 )?;
+            member.enable();
+            members.push( member );
+        }
+
+        debug!( "Opened perf events for a new thread {}", tid );
+        for member in members {
+            self.insert_member( member )?;
+        }
+
+        self.thread_stack_sizes.insert( tid, ThreadStackSize::new( self.stack_size ) );
+        Ok(())
+    }
+
+    // Takes into account a sample taken with the given stack size and decides
+    // whether the stack size of its thread should be changed.
+    pub fn observe_stack_usage( &mut self, tid: u32, stack_size: u64, dynamic_stack_size: u64 ) {
+        let (min_stack_size, max_stack_size) = (self.min_stack_size, self.max_stack_size);
+        let thread = match self.thread_stack_sizes.get_mut( &tid ) {
+            Some( thread ) => thread,
+            None => return
+        };
+
+        // This sample was taken before the last change, so it's not relevant anymore.
+        if stack_size != thread.stack_size as u64 {
+            return;
+        }
+
+        thread.sample_count += 1;
+        thread.max_dynamic_stack_size = max( thread.max_dynamic_stack_size, dynamic_stack_size );
+
+        let new_stack_size = if dynamic_stack_size >= stack_size {
+            // The stack didn't fit, so we grow it right away.
+            clamp_stack_size( stack_size * 2, min_stack_size, max_stack_size )
+        } else if thread.sample_count >= STACK_SIZE_SHRINK_WINDOW {
+            // Leave twice as much space as the thread has recently needed.
+            let stack_size = clamp_stack_size( (thread.max_dynamic_stack_size * 2).next_power_of_two(), min_stack_size, max_stack_size );
+            min( stack_size, thread.stack_size )
+        } else {
+            return;
+        };
+
+        thread.sample_count = 0;
+        thread.max_dynamic_stack_size = 0;
+
+        if new_stack_size != thread.stack_size {
+            debug!( "Changing the stack size of thread {} from {} to {}", tid, thread.stack_size, new_stack_size );
+            thread.stack_size = new_stack_size;
+            self.has_pending_resizes = true;
+        }
+    }
+
+    fn reopen_resized_members( &mut self ) {
+        let mut is_done = true;
+        let mut fds_to_reopen = Vec::new();
+        for (&fd, member) in &self.members {
+            let stack_size = match self.thread_stack_sizes.get( &member.tid ) {
+                Some( thread ) => thread.stack_size,
+                None => continue
+            };
+
+            if member.stack_size == stack_size {
+                continue;
+            }
+
+            // We don't want to lose the samples which are still in the ring buffer,
+            // and they might not be enough to wake us up on their own.
+            if member.are_events_pending() {
+                self.ready_members.insert( fd );
+                is_done = false;
+                continue;
+            }
+
+            fds_to_reopen.push( (fd, stack_size) );
+        }
+
+        for (fd, stack_size) in fds_to_reopen {
+            let (tid, cpu) = {
+                let member = &self.members[ &fd ];
+                (member.tid, member.cpu)
+            };
+
+            let result = self.open_member( tid, cpu, stack_size, 1 ).and_then( |mut member| {
+                member.enable();
+                self.remove_member( fd );
+                self.insert_member( member )
+            });
+
+            if let Err( err ) = result {
+                // Most likely the thread has exited in the meantime.
+                debug!( "Failed to reopen the perf events for thread {} on CPU {}: {}", tid, cpu, err );
+                self.remove_member( fd );
+            }
+        }
+
+        self.has_pending_resizes = !is_done;
+    }
+
     pub fn take_initial_events( &mut self ) -> Vec< Event< 'static > > {
         let mut events = Vec::new();
         mem::swap( &mut events, &mut self.initial_events );
@@ -237,40 +480,275 @@ impl PerfGroup {
             perf.enable();
         }
 
+        for &mut (_, ref mut perf) in self.redirected_members.values_mut() {
+            perf.enable();
+        }
+
         self.stopped_processes.clear();
     }
 
-    pub fn wait( &mut self ) {
-        for member in self.members.values() {
-            if member.are_events_pending() {
-                return;
+    // Waits until any of the members has something for us or any of the watched file
+    // descriptors becomes readable; the latter are returned through `ready_fds`.
+    //
+    // Since the members only wake us up once their ring buffer fills up past the watermark
+    // we never block for longer than `DRAIN_PERIOD_MS`, and if nothing woke us up until then
+    // we drain whatever is in there anyway so that the events don't sit there indefinitely.
+    pub fn wait( &mut self, should_block: bool, ready_fds: &mut Vec< RawFd > ) {
+        ready_fds.clear();
+
+        let capacity = max( self.members.len() + 8, 64 );
+        self.epoll_events.clear();
+        self.epoll_events.reserve( capacity );
+
+        let timeout = if should_block { DRAIN_PERIOD_MS } else { 0 };
+        let count = unsafe { libc::epoll_wait( self.epoll_fd, self.epoll_events.as_mut_ptr(), capacity as _, timeout ) };
+        if count < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                panic!( "epoll_wait failed: {}", err );
+            }
+
+            return;
+        }
+
+        if count == 0 && should_block {
+            self.mark_pending_members_ready();
+            return;
+        }
+
+        unsafe {
+            self.epoll_events.set_len( count as usize );
+        }
+
+        let mut closed_redirected_fds = Vec::new();
+        for event in &self.epoll_events {
+            let fd = event.u64 as RawFd;
+            let is_hup = event.events & libc::EPOLLHUP as u32 != 0;
+            if let Some( member ) = self.members.get( &fd ) {
+                if is_hup {
+                    member.is_closed.set( true );
+                }
+
+                self.ready_members.insert( fd );
+            } else if let Some( &(output_fd, _) ) = self.redirected_members.get( &fd ) {
+                // Any of the events which share a ring buffer can be the one which wakes us up.
+                if is_hup {
+                    closed_redirected_fds.push( fd );
+                }
+
+                self.ready_members.insert( output_fd );
+            } else {
+                ready_fds.push( fd );
             }
         }
 
-        poll_events( &mut self.poll_fds, self.members.values() );
+        // Whatever these have written stays in the ring buffer they've been writing into.
+        for fd in closed_redirected_fds {
+            let _ = self.epoll_ctl( libc::EPOLL_CTL_DEL, fd );
+            self.redirected_members.remove( &fd );
+        }
+    }
+
+    // Members only become ready once their ring buffer fills up past the watermark,
+    // so before we stop we have to look for any leftover events in all of them.
+    pub fn mark_pending_members_ready( &mut self ) {
+        for (&fd, member) in &self.members {
+            if member.are_events_pending() {
+                self.ready_members.insert( fd );
+            }
+        }
     }
 
     pub fn iter( &mut self ) -> vec::Drain< EventRef > {
         self.event_buffer.clear();
 
+        if self.has_pending_resizes {
+            self.reopen_resized_members();
+        }
+
         let mut fds_to_remove = Vec::new();
-        for perf in self.members.values_mut() {
-            if !perf.are_events_pending() {
-                if perf.is_closed.get() {
-                    fds_to_remove.push( perf.fd() );
+        for fd in mem::take( &mut self.ready_members ) {
+            let (is_closed, are_events_pending) = match self.members.get( &fd ) {
+                Some( member ) => (member.is_closed.get(), member.are_events_pending()),
+                None => continue
+            };
+
+            if is_closed && self.has_redirected_members( fd ) {
+                // The thread which owns the ring buffer is gone, but the other threads still
+                // write into it. Its event hangs up for good, so from now on we only watch theirs.
+                let _ = self.epoll_ctl( libc::EPOLL_CTL_DEL, fd );
+            } else if is_closed {
+                if !are_events_pending {
+                    fds_to_remove.push( fd );
                     continue;
                 }
 
-                continue;
+                // We might not be watching it anymore, so we come back to it ourselves.
+                self.ready_members.insert( fd );
             }
 
-            self.event_buffer.extend( perf.iter() );
+            if are_events_pending {
+                let perf = self.members.get_mut( &fd ).unwrap();
+                self.event_buffer.extend( perf.iter() );
+            }
         }
 
         for fd in fds_to_remove {
-            self.members.remove( &fd );
+            if let Some( member ) = self.remove_member( fd ) {
+                // The thread ID can be reused, so we need to forget about the old thread.
+                if !self.members.values().any( |other| other.tid == member.tid ) {
+                    self.thread_stack_sizes.remove( &member.tid );
+                }
+            }
         }
 
         self.event_buffer.drain( .. )
     }
 }
+
+#[test]
+fn test_adaptive_stack_size() {
+    let mut group = PerfGroup::new( 900, 24576, EventSource::SwCpuClock ).unwrap();
+    group.set_adaptive_stack_size( 4096, MAX_STACK_SIZE );
+    group.thread_stack_sizes.insert( 1000, ThreadStackSize::new( 24576 ) );
+
+    // A truncated stack makes it grow right away.
+    group.observe_stack_usage( 1000, 24576, 24576 );
+    assert_eq!( group.thread_stack_sizes[ &1000 ].stack_size, 49152 );
+    assert!( group.has_pending_resizes );
+
+    // Samples taken before the change are ignored.
+    group.observe_stack_usage( 1000, 24576, 24576 );
+    assert_eq!( group.thread_stack_sizes[ &1000 ].stack_size, 49152 );
+
+    group.observe_stack_usage( 1000, 49152, 49152 );
+    assert_eq!( group.thread_stack_sizes[ &1000 ].stack_size, MAX_STACK_SIZE );
+
+    // A shallow thread shrinks only after enough samples.
+    for _ in 0..STACK_SIZE_SHRINK_WINDOW - 1 {
+        group.observe_stack_usage( 1000, MAX_STACK_SIZE as u64, 1500 );
+    }
+    assert_eq!( group.thread_stack_sizes[ &1000 ].stack_size, MAX_STACK_SIZE );
+
+    group.observe_stack_usage( 1000, MAX_STACK_SIZE as u64, 1500 );
+    assert_eq!( group.thread_stack_sizes[ &1000 ].stack_size, 4096 );
+
+    // Threads which we don't know about are ignored.
+    group.observe_stack_usage( 2000, 24576, 24576 );
+    assert!( !group.thread_stack_sizes.contains_key( &2000 ) );
+}
+
+#[test]
+fn test_wait_for_watched_fd() {
+    use std::time::Duration;
+    use utils::Timer;
+
+    let mut group = PerfGroup::new( 900, 24576, EventSource::SwCpuClock ).unwrap();
+    let timer = Timer::new( Duration::from_millis( 100 ) ).unwrap();
+    group.watch_fd( timer.fd() ).unwrap();
+
+    let mut ready_fds = Vec::new();
+    group.wait( false, &mut ready_fds );
+    assert!( ready_fds.is_empty() );
+    assert!( !timer.has_expired() );
+
+    group.wait( true, &mut ready_fds );
+    assert_eq!( ready_fds, vec![ timer.fd() ] );
+    assert!( timer.has_expired() );
+    assert_eq!( group.iter().len(), 0 );
+}
+
+#[test]
+fn test_mark_pending_members_ready() {
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut child = Command::new( "sh" ).arg( "-c" ).arg( "while :; do :; done" ).spawn().unwrap();
+    let mut group = PerfGroup::new( 100, 8, EventSource::SwCpuClock ).unwrap();
+    group.open_process( child.id() ).unwrap();
+    group.enable();
+    thread::sleep( Duration::from_millis( 100 ) );
+
+    // A handful of samples isn't enough to reach the watermark.
+    let mut ready_fds = Vec::new();
+    group.wait( false, &mut ready_fds );
+    let count = group.iter().len();
+
+    group.mark_pending_members_ready();
+    let leftover_count = group.iter().len();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!( count, 0 );
+    assert_ne!( leftover_count, 0 );
+}
+
+#[test]
+fn test_wait_drains_events_below_the_watermark() {
+    use std::process::Command;
+
+    let mut child = Command::new( "sh" ).arg( "-c" ).arg( "while :; do :; done" ).spawn().unwrap();
+    let mut group = PerfGroup::new( 100, 8, EventSource::SwCpuClock ).unwrap();
+    group.open_process( child.id() ).unwrap();
+    group.enable();
+
+    // Nothing will ever reach the watermark here, so only the timeout can wake us up.
+    let mut ready_fds = Vec::new();
+    group.wait( true, &mut ready_fds );
+    let count = group.iter().len();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!( ready_fds.is_empty() );
+    assert_ne!( count, 0 );
+}
+
+#[test]
+fn test_shared_ring_buffer_outlives_its_owner() {
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+    use perf::Event;
+
+    let mut owner = Command::new( "sh" ).arg( "-c" ).arg( "while :; do :; done" ).spawn().unwrap();
+    let mut other = Command::new( "sh" ).arg( "-c" ).arg( "while :; do :; done" ).spawn().unwrap();
+
+    let mut group = PerfGroup::new( 100, 8, EventSource::SwCpuClock ).unwrap();
+    group.set_per_cpu_buffers();
+    group.open_shared_members( owner.id(), &[other.id()] ).unwrap();
+    group.enable();
+    thread::sleep( Duration::from_millis( 50 ) );
+
+    // The events of the owner hang up once it exits...
+    owner.kill().unwrap();
+    owner.wait().unwrap();
+
+    let mut ready_fds = Vec::new();
+    group.wait( true, &mut ready_fds );
+    while group.iter().len() != 0 {}
+
+    // ...but the other process still writes into the same ring buffers.
+    let member_count = group.members.len();
+    let redirected_count = group.redirected_members.len();
+    thread::sleep( Duration::from_millis( 100 ) );
+    group.mark_pending_members_ready();
+    let samples_from_other = group.iter().filter( |event_ref| {
+        match event_ref.get().parse() {
+            Event::Sample( event ) => event.pid == other.id(),
+            _ => false
+        }
+    }).count();
+
+    other.kill().unwrap();
+    other.wait().unwrap();
+    while !group.is_empty() {
+        group.wait( true, &mut ready_fds );
+        while group.iter().len() != 0 {}
+    }
+
+    assert_eq!( member_count, num_cpus::get() );
+    assert_eq!( redirected_count, num_cpus::get() );
+    assert_ne!( samples_from_other, 0 );
+    assert!( group.redirected_members.is_empty() );
+}
@@ -129,6 +129,75 @@ pub const PERF_REG_ARM_LR: u64 = 14;
 pub const PERF_REG_ARM_PC: u64 = 15;
 pub const PERF_REG_ARM_MAX: u64 = 16;
 
+pub const PERF_REG_ARM64_X0: u64 = 0;
+pub const PERF_REG_ARM64_X1: u64 = 1;
+pub const PERF_REG_ARM64_X2: u64 = 2;
+pub const PERF_REG_ARM64_X3: u64 = 3;
+pub const PERF_REG_ARM64_X4: u64 = 4;
+pub const PERF_REG_ARM64_X5: u64 = 5;
+pub const PERF_REG_ARM64_X6: u64 = 6;
+pub const PERF_REG_ARM64_X7: u64 = 7;
+pub const PERF_REG_ARM64_X8: u64 = 8;
+pub const PERF_REG_ARM64_X9: u64 = 9;
+pub const PERF_REG_ARM64_X10: u64 = 10;
+pub const PERF_REG_ARM64_X11: u64 = 11;
+pub const PERF_REG_ARM64_X12: u64 = 12;
+pub const PERF_REG_ARM64_X13: u64 = 13;
+pub const PERF_REG_ARM64_X14: u64 = 14;
+pub const PERF_REG_ARM64_X15: u64 = 15;
+pub const PERF_REG_ARM64_X16: u64 = 16;
+pub const PERF_REG_ARM64_X17: u64 = 17;
+pub const PERF_REG_ARM64_X18: u64 = 18;
+pub const PERF_REG_ARM64_X19: u64 = 19;
+pub const PERF_REG_ARM64_X20: u64 = 20;
+pub const PERF_REG_ARM64_X21: u64 = 21;
+pub const PERF_REG_ARM64_X22: u64 = 22;
+pub const PERF_REG_ARM64_X23: u64 = 23;
+pub const PERF_REG_ARM64_X24: u64 = 24;
+pub const PERF_REG_ARM64_X25: u64 = 25;
+pub const PERF_REG_ARM64_X26: u64 = 26;
+pub const PERF_REG_ARM64_X27: u64 = 27;
+pub const PERF_REG_ARM64_X28: u64 = 28;
+pub const PERF_REG_ARM64_X29: u64 = 29;
+pub const PERF_REG_ARM64_LR: u64 = 30;
+pub const PERF_REG_ARM64_SP: u64 = 31;
+pub const PERF_REG_ARM64_PC: u64 = 32;
+pub const PERF_REG_ARM64_MAX: u64 = 33;
+
+pub const PERF_REG_RISCV_PC: u64 = 0;
+pub const PERF_REG_RISCV_RA: u64 = 1;
+pub const PERF_REG_RISCV_SP: u64 = 2;
+pub const PERF_REG_RISCV_GP: u64 = 3;
+pub const PERF_REG_RISCV_TP: u64 = 4;
+pub const PERF_REG_RISCV_T0: u64 = 5;
+pub const PERF_REG_RISCV_T1: u64 = 6;
+pub const PERF_REG_RISCV_T2: u64 = 7;
+pub const PERF_REG_RISCV_S0: u64 = 8;
+pub const PERF_REG_RISCV_S1: u64 = 9;
+pub const PERF_REG_RISCV_A0: u64 = 10;
+pub const PERF_REG_RISCV_A1: u64 = 11;
+pub const PERF_REG_RISCV_A2: u64 = 12;
+pub const PERF_REG_RISCV_A3: u64 = 13;
+pub const PERF_REG_RISCV_A4: u64 = 14;
+pub const PERF_REG_RISCV_A5: u64 = 15;
+pub const PERF_REG_RISCV_A6: u64 = 16;
+pub const PERF_REG_RISCV_A7: u64 = 17;
+pub const PERF_REG_RISCV_S2: u64 = 18;
+pub const PERF_REG_RISCV_S3: u64 = 19;
+pub const PERF_REG_RISCV_S4: u64 = 20;
+pub const PERF_REG_RISCV_S5: u64 = 21;
+pub const PERF_REG_RISCV_S6: u64 = 22;
+pub const PERF_REG_RISCV_S7: u64 = 23;
+pub const PERF_REG_RISCV_S8: u64 = 24;
+pub const PERF_REG_RISCV_S9: u64 = 25;
+pub const PERF_REG_RISCV_S10: u64 = 26;
+pub const PERF_REG_RISCV_S11: u64 = 27;
+pub const PERF_REG_RISCV_T3: u64 = 28;
+pub const PERF_REG_RISCV_T4: u64 = 29;
+pub const PERF_REG_RISCV_T5: u64 = 30;
+pub const PERF_REG_RISCV_T6: u64 = 31;
+pub const PERF_REG_RISCV_MAX: u64 = 32;
+
 pub const PERF_REG_MIPS_PC: u64 = 0;
 pub const PERF_REG_MIPS_R1: u64 = 1;
 pub const PERF_REG_MIPS_R2: u64 = 2;
@@ -212,6 +281,7 @@ macro_rules! io {
 
 pub const PERF_EVENT_IOC_ENABLE: c_ulong = io!( b'$', 0 );
 pub const PERF_EVENT_IOC_DISABLE: c_ulong = io!( b'$', 1 );
+pub const PERF_EVENT_IOC_SET_OUTPUT: c_ulong = io!( b'$', 5 );
 
 #[repr(C)]
 pub struct PerfEventAttr {
@@ -136,11 +136,6 @@ impl< T > RangeMap< T > {
         self.values.iter().map( |&(_, ref value)| value )
     }
 
-    #[inline]
-    pub fn is_empty( &self ) -> bool {
-        self.values.is_empty()
-    }
-
     #[inline]
     pub fn len( &self ) -> usize {
         self.values.len()
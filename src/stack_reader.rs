@@ -1,5 +1,9 @@
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::mem;
 
+use libc;
+
 use archive::Endianness;
 use raw_data::RawData;
 use address_space::{Primitive, BufferReader};
@@ -38,3 +42,235 @@ impl< 'a > BufferReader for StackReader< 'a > {
         self.get_value_at_offset::< u64 >( endianness, offset )
     }
 }
+
+// The size of the chunks in which we read the stack from the live process.
+const LIVE_READ_CHUNK_SIZE: usize = 4096;
+
+// How much of the end of the snapshot we read back from the live process
+// to check whether its stack still looks the same as when it was sampled.
+const LIVE_READ_CHECK_SIZE: usize = 64;
+
+fn read_process_memory( pid: u32, address: u64, buffer: &mut [u8] ) -> usize {
+    let local = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len()
+    };
+
+    let remote = libc::iovec {
+        iov_base: address as usize as *mut libc::c_void,
+        iov_len: buffer.len()
+    };
+
+    let result = unsafe {
+        libc::process_vm_readv( pid as libc::pid_t, &local, 1, &remote, 1, 0 )
+    };
+
+    if result < 0 {
+        0
+    } else {
+        result as usize
+    }
+}
+
+// Reads the parts of the stack which are past the captured snapshot
+// directly from the process. Since the process keeps on running while
+// we're unwinding what we'll read here might not match the snapshot,
+// so before we read anything we check that the end of the snapshot
+// is still on the live stack; if it isn't the thread has already
+// returned from the frames we're unwinding and we don't read at all.
+pub struct LiveStackReader< 'a > {
+    stack: RawData< 'a >,
+    pid: u32,
+    stack_address: u64,
+    stack_end: u64,
+    live_stack: RefCell< Vec< u8 > >,
+    live_stack_end: Cell< Option< usize > >,
+    used_live_reads: Cell< bool >
+}
+
+impl< 'a > LiveStackReader< 'a > {
+    pub fn new( stack: RawData< 'a >, pid: u32, stack_address: u64, stack_end: u64 ) -> Self {
+        LiveStackReader {
+            stack,
+            pid,
+            stack_address,
+            stack_end: cmp::max( stack_end, stack_address ),
+            live_stack: RefCell::new( Vec::new() ),
+            live_stack_end: Cell::new( None ),
+            used_live_reads: Cell::new( false )
+        }
+    }
+
+    pub fn used_live_reads( &self ) -> bool {
+        self.used_live_reads.get()
+    }
+
+    fn is_snapshot_still_live( &self ) -> bool {
+        let snapshot_length = self.stack.len();
+        let check_size = cmp::min( LIVE_READ_CHECK_SIZE, snapshot_length );
+        let offset = snapshot_length - check_size;
+        let mut live = [0; LIVE_READ_CHECK_SIZE];
+        let live = &mut live[ ..check_size ];
+        if read_process_memory( self.pid, self.stack_address + offset as u64, live ) != check_size {
+            return false;
+        }
+
+        *self.stack.get( offset..snapshot_length ).as_slice() == *live
+    }
+
+    fn fetch_live_stack( &self, end: usize ) -> bool {
+        let snapshot_length = self.stack.len();
+        let mut live_stack = self.live_stack.borrow_mut();
+        if end <= snapshot_length + live_stack.len() {
+            return true;
+        }
+
+        if end > self.len() {
+            return false;
+        }
+
+        // We've already hit the end of what we can read; don't try again.
+        if self.live_stack_end.get().is_some() {
+            return false;
+        }
+
+        let old_length = live_stack.len();
+        if old_length == 0 && !self.is_snapshot_still_live() {
+            debug!( "Live stack of PID {} doesn't match the sampled one anymore", self.pid );
+            self.live_stack_end.set( Some( snapshot_length ) );
+            return false;
+        }
+
+        let new_length = (end - snapshot_length + LIVE_READ_CHUNK_SIZE - 1) & !(LIVE_READ_CHUNK_SIZE - 1);
+        let new_length = cmp::min( new_length, self.len() - snapshot_length );
+        live_stack.resize( new_length, 0 );
+
+        let address = self.stack_address + (snapshot_length + old_length) as u64;
+        let count = read_process_memory( self.pid, address, &mut live_stack[ old_length.. ] );
+        if count < new_length - old_length {
+            debug!( "Live stack of PID {} is not readable past 0x{:016X}", self.pid, address + count as u64 );
+            self.live_stack_end.set( Some( snapshot_length + old_length + count ) );
+        }
+
+        live_stack.truncate( old_length + count );
+        if count > 0 {
+            debug!( "Read {} bytes of the live stack at 0x{:016X} from PID {}", count, address, self.pid );
+            self.used_live_reads.set( true );
+        }
+
+        end <= snapshot_length + live_stack.len()
+    }
+
+    #[inline]
+    fn get_value_at_offset< T: Primitive >( &self, endianness: Endianness, offset: u64 ) -> Option< T > {
+        let offset = offset as usize;
+        let size = mem::size_of::< T >();
+        let snapshot_length = self.stack.len();
+        if offset + size <= snapshot_length {
+            let slice = self.stack.get( offset..offset + size ).as_slice();
+            return Some( T::read_from_slice( endianness, &slice ) );
+        }
+
+        if offset < snapshot_length || !self.fetch_live_stack( offset + size ) {
+            return None;
+        }
+
+        let live_stack = self.live_stack.borrow();
+        let offset = offset - snapshot_length;
+        Some( T::read_from_slice( endianness, &live_stack[ offset..offset + size ] ) )
+    }
+}
+
+impl< 'a > BufferReader for LiveStackReader< 'a > {
+    #[inline]
+    fn len( &self ) -> usize {
+        cmp::max( (self.stack_end - self.stack_address) as usize, self.stack.len() )
+    }
+
+    fn get_u32_at_offset( &self, endianness: Endianness, offset: u64 ) -> Option< u32 > {
+        self.get_value_at_offset::< u32 >( endianness, offset )
+    }
+
+    fn get_u64_at_offset( &self, endianness: Endianness, offset: u64 ) -> Option< u64 > {
+        self.get_value_at_offset::< u64 >( endianness, offset )
+    }
+}
+
+#[test]
+fn test_live_stack_reader() {
+    use byteorder::{ByteOrder, LittleEndian};
+    use address_space::BufferReader;
+
+    let mut stack = vec![ 0; 8192 ];
+    for (index, chunk) in stack.chunks_mut( 8 ).enumerate() {
+        LittleEndian::write_u64( chunk, index as u64 );
+    }
+
+    let stack_address = stack.as_ptr() as u64;
+    let reader = LiveStackReader::new( stack[ ..64 ].into(), unsafe { libc::getpid() } as u32, stack_address, stack_address + stack.len() as u64 );
+    assert_eq!( reader.len(), stack.len() );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 56 ), Some( 7 ) );
+    assert!( !reader.used_live_reads() );
+
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 64 ), Some( 8 ) );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 8184 ), Some( 1023 ) );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 8192 ), None );
+    assert!( reader.used_live_reads() );
+}
+
+#[test]
+fn test_live_stack_reader_ignores_stale_stacks() {
+    use byteorder::{ByteOrder, LittleEndian};
+    use address_space::BufferReader;
+
+    let mut stack = vec![ 0; 8192 ];
+    for (index, chunk) in stack.chunks_mut( 8 ).enumerate() {
+        LittleEndian::write_u64( chunk, index as u64 );
+    }
+
+    // The thread has moved on since the sample was taken.
+    let mut snapshot = stack[ ..64 ].to_vec();
+    snapshot[ 56 ] = 0xFF;
+
+    let stack_address = stack.as_ptr() as u64;
+    let reader = LiveStackReader::new( snapshot[..].into(), unsafe { libc::getpid() } as u32, stack_address, stack_address + stack.len() as u64 );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 48 ), Some( 6 ) );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, 64 ), None );
+    assert!( !reader.used_live_reads() );
+}
+
+#[test]
+fn test_live_stack_reader_does_not_retry_failed_reads() {
+    use std::ptr;
+    use address_space::BufferReader;
+
+    let page_size = unsafe { libc::sysconf( libc::_SC_PAGESIZE ) } as usize;
+    let length = page_size * 2;
+    let pointer = unsafe {
+        libc::mmap( ptr::null_mut(), length, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0 )
+    };
+    assert_ne!( pointer, libc::MAP_FAILED );
+
+    let second_page = (pointer as usize + page_size) as *mut libc::c_void;
+    unsafe {
+        assert_eq!( libc::munmap( second_page, page_size ), 0 );
+    }
+
+    let stack = [ 0; 64 ];
+    let stack_address = pointer as u64;
+    let reader = LiveStackReader::new( stack[..].into(), unsafe { libc::getpid() } as u32, stack_address, stack_address + length as u64 );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, page_size as u64 - 8 ), Some( 0 ) );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, page_size as u64 ), None );
+
+    // Even if the memory becomes readable we shouldn't try to read it again.
+    let remapped = unsafe {
+        libc::mmap( second_page, page_size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED, -1, 0 )
+    };
+    assert_eq!( remapped, second_page );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, page_size as u64 ), None );
+    assert_eq!( reader.get_u64_at_offset( Endianness::LittleEndian, page_size as u64 - 8 ), Some( 0 ) );
+
+    unsafe {
+        libc::munmap( pointer, length );
+    }
+}
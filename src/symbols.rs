@@ -158,3 +158,29 @@ impl< T: StableIndex + Index< Range< u64 >, Output = [u8] > > Symbols< T > {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::sync::Arc;
+    use binary::BinaryData;
+    use super::Symbols;
+
+    fn load( filename: &str ) -> Symbols< BinaryData > {
+        let path = Path::new( env!( "CARGO_MANIFEST_DIR" ) ).join( "test-data" ).join( "bin" ).join( filename );
+        let binary = BinaryData::load_from_fs( None, path ).unwrap();
+        Symbols::load_from_binary_data( &Arc::new( binary ) )
+    }
+
+    fn lookup( symbols: &Symbols< BinaryData >, address: u64 ) -> Option< &str > {
+        symbols.get_symbol_index( address ).and_then( |index| symbols.get_symbol_by_index( index ) ).map( |(_, name)| name )
+    }
+
+    #[test]
+    fn load_symbols_from_mini_debug_info() {
+        let symbols = load( "amd64-usleep_in_a_loop_mini_debuginfo" );
+        assert_eq!( lookup( &symbols, 0x400410 ), Some( "main" ) );
+        assert_eq!( lookup( &symbols, 0x400510 ), Some( "function" ) );
+        assert_eq!( lookup( &symbols, 0x400430 ), Some( "_start" ) );
+    }
+}
@@ -1,14 +1,52 @@
+use std::cmp;
 use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
 use arch::Architecture;
+use archive::Bitness;
 use dwarf_regs::DwarfRegs;
 use utils::{HexValue, HexRange};
 use address_space::{MemoryReader, BinaryHandle};
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UnwindMode {
+    Dwarf,
+    FramePointer,
+    DwarfThenFramePointer
+}
+
+// Why the unwinding has stopped.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum StopReason {
+    EndOfStack,
+    MissingBinary,
+    MissingUnwindInfo,
+    UnsupportedRule,
+    StackExhausted,
+    CantUnwind,
+    Other
+}
+
+impl StopReason {
+    pub fn as_str( &self ) -> &'static str {
+        match *self {
+            StopReason::EndOfStack => "end of stack",
+            StopReason::MissingBinary => "missing binary",
+            StopReason::MissingUnwindInfo => "no unwind info for address",
+            StopReason::UnsupportedRule => "unsupported CFI rule",
+            StopReason::StackExhausted => "stack snapshot exhausted",
+            StopReason::CantUnwind => "exidx cantunwind",
+            StopReason::Other => "other"
+        }
+    }
+}
+
 pub struct EmptyUnwindContext< A: Architecture > {
     state: A::State,
     panic_on_partial_backtrace: bool,
+    unwind_mode: UnwindMode,
+    stack_scanning: bool,
+    stop_reason: StopReason,
     regs_buffer_1: DwarfRegs,
     regs_buffer_2: DwarfRegs,
     phantom: PhantomData< A >
@@ -21,6 +59,12 @@ pub struct UnwindContext< A: Architecture > {
     state: A::State,
     is_done: bool,
     panic_on_partial_backtrace: bool,
+    unwind_mode: UnwindMode,
+    stack_scanning: bool,
+    scan_start: u64,
+    is_guessed: bool,
+    next_frame_is_guessed: bool,
+    stop_reason: StopReason,
 
     phantom: PhantomData< A >
 }
@@ -29,7 +73,9 @@ pub struct UnwindFrame< A: Architecture > {
     pub initial_address: Option< u64 >,
     pub binary: Option< BinaryHandle< A > >,
     pub regs: DwarfRegs,
-    pub cfa: Option< u64 >
+    pub cfa: Option< u64 >,
+    // Set when this frame couldn't be unwound.
+    pub stop_reason: Option< StopReason >
 }
 
 impl< A: Architecture > UnwindFrame< A > {
@@ -39,7 +85,8 @@ impl< A: Architecture > UnwindFrame< A > {
             initial_address: None,
             binary: None,
             regs,
-            cfa: None
+            cfa: None,
+            stop_reason: None
         }
     }
 
@@ -49,6 +96,15 @@ impl< A: Architecture > UnwindFrame< A > {
         self.binary = None;
         self.regs.clear();
         self.cfa = None;
+        self.stop_reason = None;
+    }
+
+    // Not finding the return address without running into any
+    // other problem means that we've reached the outermost frame.
+    pub fn mark_end_of_stack( &mut self ) {
+        if self.stop_reason.is_none() {
+            self.stop_reason = Some( StopReason::EndOfStack );
+        }
     }
 }
 
@@ -88,6 +144,9 @@ impl< A: Architecture > EmptyUnwindContext< A > {
         EmptyUnwindContext {
             state: A::initial_state(),
             panic_on_partial_backtrace: false,
+            unwind_mode: UnwindMode::Dwarf,
+            stack_scanning: false,
+            stop_reason: StopReason::Other,
             regs_buffer_1: DwarfRegs::new(),
             regs_buffer_2: DwarfRegs::new(),
             phantom: PhantomData
@@ -98,6 +157,19 @@ impl< A: Architecture > EmptyUnwindContext< A > {
         self.panic_on_partial_backtrace = value;
     }
 
+    pub fn set_unwind_mode( &mut self, value: UnwindMode ) {
+        self.unwind_mode = value;
+    }
+
+    pub fn set_stack_scanning( &mut self, value: bool ) {
+        self.stack_scanning = value;
+    }
+
+    // Why the last unwinding which used this context has stopped.
+    pub fn stop_reason( &self ) -> StopReason {
+        self.stop_reason
+    }
+
     pub fn start< M: MemoryReader< A > >( mut self, memory: &M, regs: &mut DwarfRegs ) -> UnwindContext< A > {
         debug!( "Starting unwinding at: 0x{:016X}", A::get_instruction_pointer( &regs ).unwrap() );
 
@@ -109,6 +181,12 @@ impl< A: Architecture > EmptyUnwindContext< A > {
             next_frame: UnwindFrame::new_with_regs( self.regs_buffer_2 ),
             state: self.state,
             panic_on_partial_backtrace: self.panic_on_partial_backtrace,
+            unwind_mode: self.unwind_mode,
+            stack_scanning: self.stack_scanning,
+            scan_start: 0,
+            is_guessed: false,
+            next_frame_is_guessed: false,
+            stop_reason: StopReason::Other,
             is_done: false,
             phantom: PhantomData
         };
@@ -119,12 +197,12 @@ impl< A: Architecture > EmptyUnwindContext< A > {
                 panic!( "Partial backtrace!" );
             }
 
-            ctx.is_done = true;
+            ctx.is_done = !ctx.scan_stack( memory );
             return ctx;
         }
 
-        if !A::unwind( 0, memory, &mut ctx.state, &mut ctx.current_frame, &mut ctx.next_frame, ctx.panic_on_partial_backtrace ) {
-            ctx.is_done = true;
+        if !ctx.unwind_current_frame( memory ) {
+            ctx.stop();
         }
 
         ctx
@@ -155,6 +233,7 @@ impl< A: Architecture > UnwindContext< A > {
                 }
 
                 self.is_done = true;
+                self.stop_reason = StopReason::MissingBinary;
                 return;
             }
         };
@@ -178,17 +257,27 @@ impl< A: Architecture > UnwindContext< A > {
 
         debug!( "Unwinding #{} -> #{} at: 0x{:016X}", self.nth_frame, self.nth_frame + 1, A::get_instruction_pointer( &self.current_frame.regs ).unwrap() );
 
-        mem::swap( &mut self.current_frame, &mut self.next_frame );
-        self.next_frame.clear();
+        self.advance();
         self.nth_frame += 1;
 
         self.assign_binary_to_current_address( memory );
         if self.is_done {
-            return Err( self.end() );
+            // The address we've unwound to is most likely garbage,
+            // so the best we can do is to look for the next frame on the stack.
+            if !self.scan_stack( memory ) {
+                return Err( self.end() );
+            }
+
+            self.is_done = false;
+            self.advance();
+            self.assign_binary_to_current_address( memory );
+            if self.is_done {
+                return Err( self.end() );
+            }
         }
 
-        if !A::unwind( self.nth_frame, memory, &mut self.state, &mut self.current_frame, &mut self.next_frame, self.panic_on_partial_backtrace ) {
-            self.is_done = true;
+        if !self.unwind_current_frame( memory ) {
+            self.stop();
         } else {
             debug!( "Current address on frame #{}: 0x{:016X}", self.nth_frame, A::get_instruction_pointer( &self.current_frame().regs ).unwrap() );
         }
@@ -196,10 +285,107 @@ impl< A: Architecture > UnwindContext< A > {
         Ok( self )
     }
 
+    fn stop( &mut self ) {
+        self.is_done = true;
+        self.stop_reason = self.current_frame.stop_reason.unwrap_or( StopReason::Other );
+    }
+
+    fn advance( &mut self ) {
+        mem::swap( &mut self.current_frame, &mut self.next_frame );
+        self.next_frame.clear();
+
+        // Everything we unwind through after a guessed frame is a guess too.
+        if self.next_frame_is_guessed {
+            self.next_frame_is_guessed = false;
+            self.is_guessed = true;
+        }
+    }
+
+    fn unwind_current_frame< M: MemoryReader< A > >( &mut self, memory: &M ) -> bool {
+        let result = match self.unwind_mode {
+            UnwindMode::Dwarf => {
+                A::unwind( self.nth_frame, memory, &mut self.state, &mut self.current_frame, &mut self.next_frame, self.panic_on_partial_backtrace )
+            },
+            UnwindMode::FramePointer => {
+                A::unwind_with_frame_pointer( self.nth_frame, memory, &mut self.current_frame, &mut self.next_frame )
+            },
+            UnwindMode::DwarfThenFramePointer => {
+                if A::unwind( self.nth_frame, memory, &mut self.state, &mut self.current_frame, &mut self.next_frame, false ) {
+                    true
+                } else {
+                    debug!( "Falling back to frame pointer based unwinding for frame #{}", self.nth_frame );
+                    self.next_frame.clear();
+                    if A::unwind_with_frame_pointer( self.nth_frame, memory, &mut self.current_frame, &mut self.next_frame ) {
+                        true
+                    } else {
+                        match self.current_frame.stop_reason {
+                            Some( StopReason::EndOfStack ) | Some( StopReason::CantUnwind ) => {},
+                            _ => {
+                                if self.panic_on_partial_backtrace {
+                                    panic!( "Partial backtrace!" );
+                                }
+                            }
+                        }
+
+                        false
+                    }
+                }
+            }
+        };
+
+        result || self.scan_stack( memory )
+    }
+
+    // Looks for something which looks like a return address on the stack above
+    // the current frame, and if found uses it to initialize the next frame.
+    fn scan_stack< M: MemoryReader< A > >( &mut self, memory: &M ) -> bool {
+        if !self.stack_scanning {
+            return false;
+        }
+
+        let stack_pointer = match A::get_stack_pointer( &self.current_frame.regs ) {
+            Some( stack_pointer ) => stack_pointer,
+            None => return false
+        };
+
+        let word_size = match A::BITNESS {
+            Bitness::B32 => 4,
+            Bitness::B64 => 8
+        };
+
+        // We never scan the same part of the stack twice, otherwise we could loop forever.
+        let mut address = cmp::max( stack_pointer, self.scan_start );
+        debug!( "Scanning the stack for frame #{} starting at 0x{:016X}...", self.nth_frame + 1, address );
+
+        while memory.is_stack_address( address ) {
+            if let Some( value ) = memory.get_pointer_at_address( A::ENDIANNESS, A::BITNESS, address ) {
+                let is_executable = memory.get_region_at_address( value ).map( |region| region.is_executable() ).unwrap_or( false );
+                if is_executable {
+                    self.next_frame.clear();
+                    if A::recover_frame_from_stack( memory, address, value, &mut self.next_frame ) {
+                        debug!( "Found a return address 0x{:016X} at 0x{:016X} for frame #{}", value, address, self.nth_frame + 1 );
+                        self.scan_start = address + word_size;
+                        self.next_frame_is_guessed = true;
+                        return true;
+                    }
+                }
+            }
+
+            address += word_size;
+        }
+
+        debug!( "Previous frame not found: stack scanning failed for frame #{}", self.nth_frame );
+        self.next_frame.clear();
+        false
+    }
+
     fn end( self ) -> EmptyUnwindContext< A > {
         EmptyUnwindContext {
             state: self.state,
             panic_on_partial_backtrace: self.panic_on_partial_backtrace,
+            unwind_mode: self.unwind_mode,
+            stack_scanning: self.stack_scanning,
+            stop_reason: self.stop_reason,
             regs_buffer_1: self.current_frame.regs,
             regs_buffer_2: self.next_frame.regs,
             phantom: PhantomData
@@ -210,6 +396,10 @@ impl< A: Architecture > UnwindContext< A > {
         self.current_frame().initial_address
     }
 
+    pub fn is_current_frame_guessed( &self ) -> bool {
+        self.is_guessed
+    }
+
     pub fn current_address( &self ) -> u64 {
         A::get_instruction_pointer( &self.current_frame().regs ).unwrap()
     }
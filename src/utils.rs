@@ -1,10 +1,14 @@
 use std::io::{self, Read};
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::Path;
 use std::fmt;
 use std::sync::atomic::{Ordering, AtomicBool};
 use std::time::Duration;
 use std::ops::Range;
+use std::os::unix::io::RawFd;
+use std::mem;
+use std::ptr;
+use std::cell::Cell;
 
 use libc;
 
@@ -44,30 +48,139 @@ pub fn read_string_lossy< P: AsRef< Path > >( path: P ) -> io::Result< String >
     Ok( String::from_utf8_lossy( &data ).into_owned() )
 }
 
-lazy_static! {
-    static ref SIGINT_FLAG: AtomicBool = AtomicBool::new( false );
+// Instead of interrupting us SIGINT is delivered through a file descriptor,
+// so it can be waited on along with everything else.
+pub struct SigintHandler {
+    fd: RawFd,
+    was_triggered: AtomicBool
 }
 
-#[derive(Clone)]
-pub struct SigintHandler {
+fn thread_count() -> usize {
+    fs::read_dir( "/proc/self/task" ).map( |entries| entries.count() ).unwrap_or( 1 )
 }
 
 impl SigintHandler {
+    // The signal mask is only inherited by the threads spawned after it's set,
+    // so this has to be created before any other threads are spawned, otherwise
+    // those would still get interrupted by SIGINT.
+    //
+    // Every process spawned from now on would inherit it too since `Command`
+    // doesn't reset the signal mask, so if we ever spawn anything the child
+    // has to unblock SIGINT itself before it calls exec.
     pub fn new() -> Self {
-        SIGINT_FLAG.store( false, Ordering::Relaxed ); // To initialize the `lazy_static`.
+        assert_eq!( thread_count(), 1, "SigintHandler has to be created before any other threads are spawned" );
+
+        let fd = unsafe {
+            let mut mask: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset( &mut mask );
+            libc::sigaddset( &mut mask, libc::SIGINT );
 
-        extern fn handler( _: libc::c_int ) {
-            SIGINT_FLAG.store( true, Ordering::Relaxed );
+            let result = libc::pthread_sigmask( libc::SIG_BLOCK, &mask, ptr::null_mut() );
+            assert_eq!( result, 0, "cannot block SIGINT" );
+
+            libc::signalfd( -1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC )
+        };
+
+        if fd < 0 {
+            panic!( "signalfd failed: {}", io::Error::last_os_error() );
         }
 
-        unsafe {
-            libc::signal( libc::SIGINT, handler as libc::size_t );
+        SigintHandler {
+            fd,
+            was_triggered: AtomicBool::new( false )
         }
-        SigintHandler {}
+    }
+
+    #[inline]
+    pub fn fd( &self ) -> RawFd {
+        self.fd
     }
 
     pub fn was_triggered( &self ) -> bool {
-        SIGINT_FLAG.load( Ordering::Relaxed )
+        if self.was_triggered.load( Ordering::Relaxed ) {
+            return true;
+        }
+
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::< libc::signalfd_siginfo >();
+        let result = unsafe { libc::read( self.fd, &mut info as *mut _ as *mut libc::c_void, size ) };
+        if result == size as isize {
+            self.was_triggered.store( true, Ordering::Relaxed );
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Drop for SigintHandler {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.fd );
+        }
+    }
+}
+
+// A one-shot timer which can be waited on through its file descriptor.
+pub struct Timer {
+    fd: RawFd,
+    has_expired: Cell< bool >
+}
+
+impl Timer {
+    pub fn new( duration: Duration ) -> io::Result< Self > {
+        let fd = unsafe { libc::timerfd_create( libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC ) };
+        if fd < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        let timer = Timer {
+            fd,
+            has_expired: Cell::new( false )
+        };
+        let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+        spec.it_value.tv_sec = duration.as_secs() as _;
+        spec.it_value.tv_nsec = duration.subsec_nanos() as _;
+
+        // A zero value would disarm the timer instead.
+        if duration == Duration::from_secs( 0 ) {
+            spec.it_value.tv_nsec = 1;
+        }
+
+        let result = unsafe { libc::timerfd_settime( timer.fd, 0, &spec, ptr::null_mut() ) };
+        if result < 0 {
+            return Err( io::Error::last_os_error() );
+        }
+
+        Ok( timer )
+    }
+
+    #[inline]
+    pub fn fd( &self ) -> RawFd {
+        self.fd
+    }
+
+    pub fn has_expired( &self ) -> bool {
+        if self.has_expired.get() {
+            return true;
+        }
+
+        let mut expirations: u64 = 0;
+        let result = unsafe { libc::read( self.fd, &mut expirations as *mut _ as *mut libc::c_void, mem::size_of::< u64 >() ) };
+        if result == mem::size_of::< u64 >() as isize {
+            self.has_expired.set( true );
+            return true;
+        }
+
+        false
+    }
+}
+
+impl Drop for Timer {
+    fn drop( &mut self ) {
+        unsafe {
+            libc::close( self.fd );
+        }
     }
 }
 
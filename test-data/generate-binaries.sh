@@ -19,8 +19,21 @@ function initialize_toolchain {
     set -e
 
     BASENAME=`basename $OECORE_TARGET_SYSROOT`
+    SDK_VERSION=2.4.1
 
-    if [[ "$BASENAME" = "qemumips64" ]]; then
+    if [[ "$BASENAME" = "qemumips" && "$TARGET_PREFIX" = mipsel-* ]]; then
+        PREFIX=mipsel
+        TARGET=mipsel-unknown-linux-gnu
+        EXTRA_RUSTFLAGS=""
+    elif [[ "$BASENAME" = "qemumips" ]]; then
+        PREFIX=mips
+        TARGET=mips-unknown-linux-gnu
+        EXTRA_RUSTFLAGS=""
+    elif [[ "$BASENAME" = "qemumips64" && "$TARGET_PREFIX" = mips64el-* ]]; then
+        PREFIX=mips64el
+        TARGET=mips64el-unknown-linux-gnuabi64
+        EXTRA_RUSTFLAGS="-C target-feature=-soft-float"
+    elif [[ "$BASENAME" = "qemumips64" ]]; then
         PREFIX=mips64
         TARGET=mips64-unknown-linux-gnuabi64
         EXTRA_RUSTFLAGS="-C target-feature=-soft-float"
@@ -28,6 +41,19 @@ function initialize_toolchain {
         PREFIX=arm
         TARGET=armv7-unknown-linux-musleabihf
         EXTRA_RUSTFLAGS="-C target-cpu=cortex-a8 -C target-feature=+neon,+armv7-a"
+    elif [[ "$BASENAME" = "qemuarm64" ]]; then
+        PREFIX=aarch64
+        TARGET=aarch64-unknown-linux-gnu
+        EXTRA_RUSTFLAGS=""
+    elif [[ "$BASENAME" = "qemuriscv64" ]]; then
+        PREFIX=riscv64
+        TARGET=riscv64gc-unknown-linux-gnu
+        EXTRA_RUSTFLAGS=""
+        SDK_VERSION=3.1
+    elif [[ "$BASENAME" = "qemux86" ]]; then
+        PREFIX=x86
+        TARGET=i686-unknown-linux-musl
+        EXTRA_RUSTFLAGS=""
     elif [[ "$BASENAME" = "qemux86-64" ]]; then
         PREFIX=amd64
         TARGET=x86_64-unknown-linux-musl
@@ -39,8 +65,8 @@ function initialize_toolchain {
 
     echo "Detected SDK: $PREFIX $OECORE_SDK_VERSION"
 
-    if [[ "$OECORE_SDK_VERSION" != "2.4.1" ]]; then
-        echo "Wrong SDK version detected! (expected 2.4.1, got $OECORE_SDK_VERSION)"
+    if [[ "$OECORE_SDK_VERSION" != "$SDK_VERSION" ]]; then
+        echo "Wrong SDK version detected! (expected $SDK_VERSION, got $OECORE_SDK_VERSION)"
         print_sdk_urls_and_exit
     fi
 }
@@ -61,9 +87,14 @@ function compile {
 function print_sdk_urls_and_exit {
     echo ""
     echo "You can download an SDK from these URLs:"
+    echo "    MIPS - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-mips32r2-toolchain-ext-2.4.1.sh"
     echo "  MIPS64 - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-mips64-toolchain-ext-2.4.1.sh"
     echo "     ARM - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-cortexa8hf-neon-toolchain-ext-2.4.1.sh"
+    echo " AARCH64 - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-aarch64-toolchain-ext-2.4.1.sh"
+    echo "     X86 - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-i586-toolchain-ext-2.4.1.sh"
     echo "   AMD64 - http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/toolchain/x86_64/poky-glibc-x86_64-core-image-minimal-core2-64-toolchain-ext-2.4.1.sh"
+    echo ""
+    echo "The little endian MIPS and the RISC-V 64 SDKs have to be built locally; see tools/README.md."
     exit 1
 }
 
@@ -81,6 +112,30 @@ if [ ! -e "bin/$PREFIX-usleep_in_a_loop_external_info" ]; then
     $OBJCOPY --add-gnu-debuglink=bin/$PREFIX-usleep_in_a_loop_external_info.debug   bin/$PREFIX-usleep_in_a_loop_external_info
 fi
 
+if [ ! -e "bin/$PREFIX-usleep_in_a_loop_external_info_zlib.debug" ]; then
+    $OBJCOPY --compress-debug-sections=zlib     bin/$PREFIX-usleep_in_a_loop_external_info.debug   bin/$PREFIX-usleep_in_a_loop_external_info_zlib.debug
+    $OBJCOPY --compress-debug-sections=zlib-gnu bin/$PREFIX-usleep_in_a_loop_external_info.debug   bin/$PREFIX-usleep_in_a_loop_external_info_zlib_gnu.debug
+fi
+
+if [ ! -e "bin/$PREFIX-usleep_in_a_loop_mini_debuginfo" ]; then
+    compile usleep_in_a_loop    usleep_in_a_loop_mini_debuginfo
+
+    BINARY=bin/$PREFIX-usleep_in_a_loop_mini_debuginfo
+    TMP=`mktemp -d`
+
+    $NM -D $BINARY --format=posix --defined-only | awk '{ print $1 }' | sort > $TMP/dynsyms
+    $NM $BINARY --format=posix --defined-only | awk '{ if ($2 == "T" || $2 == "t" || $2 == "D") print $1 }' | sort > $TMP/funcsyms
+    comm -13 $TMP/dynsyms $TMP/funcsyms > $TMP/keep_symbols
+
+    $OBJCOPY --only-keep-debug $BINARY $TMP/debug
+    $OBJCOPY -S --remove-section .gdb_index --remove-section .comment --keep-symbols=$TMP/keep_symbols $TMP/debug $TMP/mini_debuginfo
+    xz $TMP/mini_debuginfo
+    $STRIP --strip-all -R .comment $BINARY
+    $OBJCOPY --add-section .gnu_debugdata=$TMP/mini_debuginfo.xz $BINARY
+
+    rm -Rf $TMP
+fi
+
 export RUSTFLAGS="-C linker=${CC%% *} -C link-arg=--sysroot=$OECORE_TARGET_SYSROOT $EXTRA_RUSTFLAGS"
 export CARGO_INCREMENTAL=0
 
@@ -24,6 +24,18 @@ function generate_test {
     generate_test_ex $1 $1 "--offline"
 }
 
+# For the architectures which we have to build the images for ourselves.
+function generate_local {
+    local DEPLOY_DIR_VARIABLE=$2
+
+    if [[ "${!DEPLOY_DIR_VARIABLE:-}" = "" ]]; then
+        echo "Skipping $1 since $DEPLOY_DIR_VARIABLE isn't set; see tools/README.md"
+        return
+    fi
+
+    generate $1
+}
+
 function generate {
     PREFIX=$1
     PENDING=0
@@ -58,4 +70,10 @@ function generate {
 
 generate amd64
 generate arm
+generate mips
+generate_local mipsel MIPSEL_DEPLOY_DIR
 generate mips64
+generate_local mips64el MIPS64EL_DEPLOY_DIR
+generate aarch64
+generate x86
+generate_local riscv64 RISCV64_DEPLOY_DIR
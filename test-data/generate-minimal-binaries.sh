@@ -0,0 +1,104 @@
+#!/bin/bash
+
+# Builds the small freestanding binaries which the unit tests load directly.
+# Unlike the ones from `generate-binaries.sh` these don't need an SDK, only LLVM.
+
+unset LD_PRELOAD
+set -euo pipefail
+
+LLC=${LLC:-llc}
+LLVM_MC=${LLVM_MC:-llvm-mc}
+LD_LLD=${LD_LLD:-ld.lld}
+
+# The 32-bit x86 ones and the amd64 one with inlined functions
+# are built with the host's GCC and GNU ld instead.
+CC=${CC:-gcc}
+LD=${LD:-ld}
+
+TMP=`mktemp -d`
+trap "rm -Rf $TMP" EXIT
+
+function assemble {
+    local NAME=$1
+    local TRIPLE=$2
+    local LD_ARGS=$3
+    shift
+    shift
+    shift
+    local MC_ARGS="$*"
+
+    if [[ ! -e bin/$NAME ]]; then
+        echo "Assembling $NAME..."
+        $LLVM_MC -triple=$TRIPLE $MC_ARGS -filetype=obj src/$NAME.s -o $TMP/$NAME.o
+        $LD_LLD -static --no-rosegment $LD_ARGS $TMP/$NAME.o -o bin/$NAME
+    fi
+}
+
+# Builds `src/$PREFIX-minimal_usleep_in_a_loop.ll` with and without frame pointers.
+function compile_minimal {
+    local PREFIX=$1
+    local TRIPLE=$2
+    local LD_ARGS=$3
+    local MC_ARGS=$4
+    shift
+    shift
+    shift
+    shift
+    local LLC_ARGS="$*"
+
+    for VARIANT in fp:all no_fp:none; do
+        local OUTPUT=$PREFIX-minimal_usleep_in_a_loop_${VARIANT%%:*}
+        if [[ ! -e bin/$OUTPUT ]]; then
+            echo "Compiling $OUTPUT..."
+            $LLVM_MC -triple=$TRIPLE $MC_ARGS -filetype=obj src/$PREFIX-minimal_start.s -o $TMP/$PREFIX-minimal_start.o
+            $LLC -O2 -mtriple=$TRIPLE $LLC_ARGS --frame-pointer=${VARIANT##*:} -filetype=obj src/$PREFIX-minimal_usleep_in_a_loop.ll -o $TMP/$OUTPUT.o
+            $LD_LLD -static --eh-frame-hdr --no-rosegment $LD_ARGS $TMP/$PREFIX-minimal_start.o $TMP/$OUTPUT.o -o bin/$OUTPUT
+        fi
+    done
+}
+
+# Builds `src/minimal_usleep_in_a_loop.c` with and without frame pointers.
+function compile_minimal_x86 {
+    local CFLAGS="-m32 -O2 -g0 -fasynchronous-unwind-tables -fno-pic -fno-stack-protector -ffreestanding"
+
+    for VARIANT in fp no_fp; do
+        local OUTPUT=x86-minimal_usleep_in_a_loop_$VARIANT
+        if [[ ! -e bin/$OUTPUT ]]; then
+            echo "Compiling $OUTPUT..."
+            if [[ "$VARIANT" = "fp" ]]; then
+                local FRAME_POINTER=-fno-omit-frame-pointer
+            else
+                local FRAME_POINTER=-fomit-frame-pointer
+            fi
+
+            $CC -m32 -c src/x86-minimal_start.s -o $TMP/x86-minimal_start.o
+            $CC $CFLAGS -fomit-frame-pointer -c src/x86-minimal_libc.c -o $TMP/x86-minimal_libc.o
+            $CC $CFLAGS $FRAME_POINTER -c src/minimal_usleep_in_a_loop.c -o $TMP/$OUTPUT.o
+            $LD -m elf_i386 -static --eh-frame-hdr -z noseparate-code -z noexecstack $TMP/x86-minimal_start.o $TMP/$OUTPUT.o $TMP/x86-minimal_libc.o -o bin/$OUTPUT
+        fi
+    done
+}
+
+compile_minimal aarch64     aarch64-unknown-linux-gnu   ""  ""
+compile_minimal mips        mips-unknown-linux-gnu      "-z noseparate-code -e __start" ""  -relocation-model=static
+compile_minimal mipsel      mipsel-unknown-linux-gnu    "-z noseparate-code -e __start" ""  -relocation-model=static
+compile_minimal mips64el    mips64el-unknown-linux-gnuabi64 "-z noseparate-code -e __start" ""  -relocation-model=static
+compile_minimal riscv64     riscv64-unknown-linux-gnu   "-z noseparate-code"    "-mattr=+m,-relax"  -mattr=+m,-relax
+compile_minimal_x86
+
+# This one has no `.ARM.exidx`, so it can only be unwound through its `.eh_frame`.
+if [[ ! -e bin/arm-minimal_usleep_in_a_loop_dwarf_only ]]; then
+    echo "Compiling arm-minimal_usleep_in_a_loop_dwarf_only..."
+    $LLVM_MC -triple=armv7-unknown-linux-gnueabihf -filetype=obj src/arm-minimal_start.s -o $TMP/arm-minimal_start.o
+    $LLC -O2 -mtriple=armv7-unknown-linux-gnueabihf -exception-model=dwarf --frame-pointer=none -filetype=obj src/arm-minimal_usleep_in_a_loop.ll -o $TMP/arm-minimal_usleep_in_a_loop_dwarf_only.o
+    $LD_LLD -static --eh-frame-hdr --no-rosegment -z noseparate-code $TMP/arm-minimal_start.o $TMP/arm-minimal_usleep_in_a_loop_dwarf_only.o -o bin/arm-minimal_usleep_in_a_loop_dwarf_only
+fi
+
+# This one has debug info for the functions which were inlined into it.
+if [[ ! -e bin/amd64-inlined_functions ]]; then
+    echo "Compiling amd64-inlined_functions..."
+    $CC -O2 -gdwarf-4 -fdebug-prefix-map=$PWD=. -fno-pic -no-pie -static -nostdlib -ffreestanding -fno-stack-protector -fasynchronous-unwind-tables -Wl,--build-id=none src/amd64-inlined_functions.c -o bin/amd64-inlined_functions
+fi
+
+assemble aarch64-saves_fp_registers     aarch64-linux-gnu       ""
+assemble arm-saves_fp_registers         armv7-linux-gnueabihf   ""
@@ -0,0 +1,40 @@
+; A freestanding `usleep_in_a_loop` which doesn't need a libc, so it can
+; be built with nothing but LLVM; see `generate-minimal-binaries.sh`.
+
+target datalayout = "e-m:e-i8:8:32-i16:16:32-i64:64-i128:128-n32:64-S128"
+target triple = "aarch64-unknown-linux-gnu"
+
+%struct.timespec = type { i64, i64 }
+
+define dso_local i32 @usleep(i32 %usec) #0 {
+  %ts = alloca %struct.timespec, align 8
+  %sec = udiv i32 %usec, 1000000
+  %rem = urem i32 %usec, 1000000
+  %nsec = mul i32 %rem, 1000
+  %sec64 = zext i32 %sec to i64
+  %nsec64 = zext i32 %nsec to i64
+  %p0 = getelementptr %struct.timespec, %struct.timespec* %ts, i32 0, i32 0
+  store i64 %sec64, i64* %p0
+  %p1 = getelementptr %struct.timespec, %struct.timespec* %ts, i32 0, i32 1
+  store i64 %nsec64, i64* %p1
+  %r = call i64 asm sideeffect "mov x8, #101\0Asvc #0", "={x0},{x0},{x1},~{x8},~{memory}"(%struct.timespec* %ts, i64 0)
+  %r32 = trunc i64 %r to i32
+  ret i32 %r32
+}
+
+define dso_local void @function() #1 {
+  call void asm sideeffect "", ""()
+  %r = call i32 @usleep(i32 1)
+  call void asm sideeffect "", ""()
+  ret void
+}
+
+define dso_local i32 @main() #0 {
+  br label %loop
+loop:
+  call void @function()
+  br label %loop
+}
+
+attributes #0 = { nounwind uwtable }
+attributes #1 = { noinline nounwind uwtable }
@@ -0,0 +1,42 @@
+// A program whose `main` saves the callee-saved `d8` register, which the CFI
+// describes as the DWARF register 72; built by `generate-minimal-binaries.sh`.
+
+    .text
+    .globl _start
+    .type _start, %function
+_start:
+    .cfi_startproc
+    .cfi_undefined x30
+    mov x29, #0
+    mov x30, #0
+    bl main
+    mov x8, #93
+    svc #0
+    .cfi_endproc
+    .size _start, .-_start
+
+    .globl main
+    .type main, %function
+main:
+    .cfi_startproc
+    stp x29, x30, [sp, #-32]!
+    .cfi_def_cfa_offset 32
+    .cfi_offset x30, -24
+    .cfi_offset x29, -32
+    str d8, [sp, #16]
+    .cfi_offset d8, -16
+    fmov d8, xzr
+1:
+    bl function
+    b 1b
+    .cfi_endproc
+    .size main, .-main
+
+    .globl function
+    .type function, %function
+function:
+    .cfi_startproc
+    nop
+    ret
+    .cfi_endproc
+    .size function, .-function
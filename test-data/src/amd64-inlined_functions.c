@@ -0,0 +1,36 @@
+// Built by `generate-minimal-binaries.sh` with optimizations, so the functions
+// marked with `always_inline` end up being inlined into their callers.
+
+#define INLINE static inline __attribute__((always_inline))
+
+volatile int counter;
+
+INLINE void inner( int value ) {
+    counter += value;
+}
+
+INLINE void outer( int count ) {
+    for( int i = 0; i < count; ++i ) {
+        // Gives us a lexical block between `outer` and `inner`.
+        int doubled = i * 2;
+        inner( doubled );
+    }
+}
+
+int main( void ) {
+    int base = counter;
+
+    // A nested function is a subprogram inside of another subprogram.
+    __attribute__((noinline)) void nested( int count ) {
+        outer( count + base );
+    }
+
+    for( ;; ) {
+        outer( counter & 7 );
+        nested( 3 );
+    }
+}
+
+void _start( void ) {
+    main();
+}
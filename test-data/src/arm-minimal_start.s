@@ -0,0 +1,14 @@
+    .text
+    .arm
+    .globl _start
+    .type _start, %function
+_start:
+    .cfi_startproc
+    .cfi_undefined lr
+    mov fp, #0
+    mov lr, #0
+    bl main
+    mov r7, #1
+    svc #0
+    .cfi_endproc
+    .size _start, .-_start
@@ -0,0 +1,53 @@
+@ A program without `.ARM.exidx` whose `main` saves the callee-saved `d8`
+@ register, which the CFI describes using the legacy VFP numbering where
+@ `s16` and `s17` are the DWARF registers 80 and 81. (The newer numbering
+@ where `d8` is 264 can't be parsed by the version of `gimli` we use.)
+@
+@ The `_start` also claims to preserve `r4` whose value we won't know,
+@ which shouldn't affect why the unwinding stops there.
+@
+@ Built by `generate-minimal-binaries.sh`.
+
+    .text
+    .arm
+    .fpu vfpv3
+    .globl _start
+    .type _start, %function
+_start:
+    .cfi_startproc
+    .cfi_undefined lr
+    .cfi_same_value r4
+    mov fp, #0
+    mov lr, #0
+    bl main
+    mov r7, #1
+    svc #0
+    .cfi_endproc
+    .size _start, .-_start
+
+    .globl main
+    .type main, %function
+main:
+    .cfi_startproc
+    push {r11, lr}
+    .cfi_def_cfa_offset 8
+    .cfi_offset lr, -4
+    .cfi_offset r11, -8
+    vpush {d8}
+    .cfi_def_cfa_offset 16
+    .cfi_offset 80, -16
+    .cfi_offset 81, -12
+1:
+    bl function
+    b 1b
+    .cfi_endproc
+    .size main, .-main
+
+    .globl function
+    .type function, %function
+function:
+    .cfi_startproc
+    nop
+    bx lr
+    .cfi_endproc
+    .size function, .-function
@@ -0,0 +1,14 @@
+// A freestanding `usleep_in_a_loop` which doesn't need a libc; see `generate-minimal-binaries.sh`.
+
+int usleep(unsigned usec);
+
+void __attribute__ ((noinline)) function() {
+    asm("");
+    usleep(1);
+    asm("");
+}
+
+int main() {
+    for (;;) { function(); }
+    return 0;
+}
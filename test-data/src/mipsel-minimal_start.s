@@ -0,0 +1,15 @@
+    .text
+    .globl __start
+    .type __start, @function
+    .set noreorder
+__start:
+    .cfi_startproc
+    .cfi_undefined $ra
+    move $fp, $zero
+    move $ra, $zero
+    jal main
+    nop
+    li $v0, 4001
+    syscall
+    .cfi_endproc
+    .size __start, .-__start
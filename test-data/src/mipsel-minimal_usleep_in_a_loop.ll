@@ -0,0 +1,40 @@
+; A freestanding `usleep_in_a_loop` which doesn't need a libc, so it can
+; be built with nothing but LLVM; see `generate-minimal-binaries.sh`.
+
+target datalayout = "e-m:m-p:32:32-i8:8:32-i16:16:32-i64:64-n32-S64"
+target triple = "mipsel-unknown-linux-gnu"
+
+%struct.timespec = type { i32, i32 }
+
+define dso_local i32 @usleep(i32 %usec) #0 {
+  %ts = alloca %struct.timespec, align 8
+  %sec = udiv i32 %usec, 1000000
+  %rem = urem i32 %usec, 1000000
+  %nsec = mul i32 %rem, 1000
+  %sec64 = add i32 %sec, 0
+  %nsec64 = add i32 %nsec, 0
+  %p0 = getelementptr %struct.timespec, %struct.timespec* %ts, i32 0, i32 0
+  store i32 %sec64, i32* %p0
+  %p1 = getelementptr %struct.timespec, %struct.timespec* %ts, i32 0, i32 1
+  store i32 %nsec64, i32* %p1
+  %r = call i32 asm sideeffect "li $$2, 4166\0Asyscall", "={$$2},{$$4},{$$5},~{memory}"(%struct.timespec* %ts, i32 0)
+  %r32 = add i32 %r, 0
+  ret i32 %r32
+}
+
+define dso_local void @function() #1 {
+  call void asm sideeffect "", ""()
+  %r = call i32 @usleep(i32 1)
+  call void asm sideeffect "", ""()
+  ret void
+}
+
+define dso_local i32 @main() #0 {
+  br label %loop
+loop:
+  call void @function()
+  br label %loop
+}
+
+attributes #0 = { nounwind uwtable }
+attributes #1 = { noinline nounwind uwtable }
@@ -0,0 +1,13 @@
+    .text
+    .globl _start
+    .type _start, %function
+_start:
+    .cfi_startproc
+    .cfi_undefined ra
+    li s0, 0
+    li ra, 0
+    call main
+    li a7, 93
+    ecall
+    .cfi_endproc
+    .size _start, .-_start
@@ -0,0 +1,10 @@
+// The parts of libc which `minimal_usleep_in_a_loop` needs, for 32-bit x86.
+
+struct timespec { long tv_sec; long tv_nsec; };
+
+int usleep(unsigned usec) {
+    struct timespec ts = { usec / 1000000, (usec % 1000000) * 1000 };
+    int result;
+    asm volatile( "int $0x80" : "=a"(result) : "0"(162), "b"(&ts), "c"(0) : "memory" );
+    return result;
+}
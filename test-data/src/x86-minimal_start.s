@@ -0,0 +1,12 @@
+    .text
+    .globl _start
+    .type _start, @function
+_start:
+    .cfi_startproc
+    .cfi_undefined eip
+    xorl %ebp, %ebp
+    call main
+    movl $1, %eax
+    int $0x80
+    .cfi_endproc
+    .size _start, .-_start
@@ -6,7 +6,7 @@ SELF_DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
 
 function syntax {
     echo ""
-    echo "Syntax: qemurun.sh [-w workdir] <-a amd64|arm|arm64|mips64> <-o output-directory> [-i input ...] [script-to-run]"
+    echo "Syntax: qemurun.sh [-w workdir] <-a amd64|x86|arm|arm64|aarch64|riscv64|mips|mipsel|mips64|mips64el> <-o output-directory> [-i input ...] [script-to-run]"
     exit 1
 }
 
@@ -71,8 +71,32 @@ QEMU_APPEND=""
 DTB_URL=""
 INITRD_URL=""
 KERNEL_URL=""
-
-if [[ "$ARCH" = "mips64" ]]; then
+KERNEL_GZIP=""
+YOCTO_RELEASE="2.4.1"
+YOCTO_DEPLOY_DIR=""
+DEPLOY_DIR_VARIABLE=""
+
+if [[ "$ARCH" = "mips" ]]; then
+    QEMU=qemu-system-mips
+    YOCTO_MACHINE=qemumips
+    MACHINE=malta
+    CPU=24Kf
+    KERNEL_FILENAME=vmlinux-qemumips.bin
+    KERNEL_URL="http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/machines/qemu/qemumips/$KERNEL_FILENAME"
+    KERNEL_APPEND="console=ttyS0"
+    IO_DEVICE=virtio-blk-pci
+elif [[ "$ARCH" = "mipsel" ]]; then
+    QEMU=qemu-system-mipsel
+    YOCTO_MACHINE=qemumips
+    MACHINE=malta
+    CPU=24Kf
+    # Yocto doesn't publish any little endian MIPS images,
+    # so these come from a local build; see README.md.
+    DEPLOY_DIR_VARIABLE=MIPSEL_DEPLOY_DIR
+    KERNEL_FILENAME=vmlinux-qemumips.bin
+    KERNEL_APPEND="console=ttyS0"
+    IO_DEVICE=virtio-blk-pci
+elif [[ "$ARCH" = "mips64" ]]; then
     QEMU=qemu-system-mips64
     YOCTO_MACHINE=qemumips64
     MACHINE=malta
@@ -81,6 +105,17 @@ if [[ "$ARCH" = "mips64" ]]; then
     KERNEL_FILENAME="vmlinux-mips64"
     KERNEL_APPEND="console=ttyS0"
     IO_DEVICE=virtio-blk-pci
+elif [[ "$ARCH" = "mips64el" ]]; then
+    QEMU=qemu-system-mips64el
+    YOCTO_MACHINE=qemumips64
+    MACHINE=malta
+    CPU=MIPS64R2-generic
+    # Just as for `mipsel` the images come from a local build; see README.md.
+    DEPLOY_DIR_VARIABLE=MIPS64EL_DEPLOY_DIR
+    KERNEL_GZIP="$SELF_DIR/vmlinux-mips64el.gz"
+    KERNEL_FILENAME="vmlinux-mips64el"
+    KERNEL_APPEND="console=ttyS0"
+    IO_DEVICE=virtio-blk-pci
 elif [[ "$ARCH" = "arm" ]]; then
     QEMU=qemu-system-arm
     YOCTO_MACHINE=qemuarm
@@ -94,7 +129,7 @@ elif [[ "$ARCH" = "arm" ]]; then
     INITRD_URL="http://ftp.debian.org/debian/dists/jessie/main/installer-armhf/current/images/netboot/initrd.gz"
     KERNEL_APPEND="console=ttyAMA0 earlycon"
     IO_DEVICE=virtio-blk-device
-elif [[ "$ARCH" = "arm64" ]]; then
+elif [[ "$ARCH" = "arm64" || "$ARCH" = "aarch64" ]]; then
     QEMU=qemu-system-aarch64
     YOCTO_MACHINE=qemuarm64
     MACHINE=virt
@@ -112,11 +147,40 @@ elif [[ "$ARCH" = "amd64" ]]; then
     KERNEL_URL="http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/machines/qemu/qemux86-64/$KERNEL_FILENAME"
     KERNEL_APPEND="console=ttyS0"
     IO_DEVICE=virtio-blk-pci
+elif [[ "$ARCH" = "x86" ]]; then
+    QEMU=qemu-system-i386
+    YOCTO_MACHINE=qemux86
+    MACHINE=pc
+    CPU=pentium3
+    KERNEL_FILENAME=bzImage-qemux86.bin
+    KERNEL_URL="http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/machines/qemu/qemux86/$KERNEL_FILENAME"
+    KERNEL_APPEND="console=ttyS0"
+    IO_DEVICE=virtio-blk-pci
+elif [[ "$ARCH" = "riscv64" ]]; then
+    QEMU=qemu-system-riscv64
+    YOCTO_MACHINE=qemuriscv64
+    MACHINE=virt
+    CPU=rv64
+    # Yocto 2.4.1 predates RISC-V, so the kernel and the images come from
+    # a local build of a later release; see README.md.
+    YOCTO_RELEASE="3.1"
+    DEPLOY_DIR_VARIABLE=RISCV64_DEPLOY_DIR
+    KERNEL_FILENAME=Image
+    KERNEL_APPEND="console=ttyS0"
+    IO_DEVICE=virtio-blk-device
 else
     echo "Unknown architecture: '$1'"
     exit 1
 fi
 
+if [[ "$DEPLOY_DIR_VARIABLE" != "" ]]; then
+    YOCTO_DEPLOY_DIR="${!DEPLOY_DIR_VARIABLE:-}"
+    if [[ "$YOCTO_DEPLOY_DIR" = "" ]]; then
+        echo "$DEPLOY_DIR_VARIABLE must point to the 'tmp/deploy/images/$YOCTO_MACHINE' directory of a Yocto $YOCTO_RELEASE build!"
+        exit 1
+    fi
+fi
+
 if [[ "$OUTPUT_DIR" != "" ]]; then
     mkdir -p "$OUTPUT_DIR"
     OUTPUT_DIR=$(realpath "$OUTPUT_DIR")
@@ -130,16 +194,20 @@ if [ ! -e "$KERNEL_FILENAME" ]; then
         wget "$KERNEL_URL"
     elif [[ "$KERNEL_GZIP" != "" ]]; then
         cat "$KERNEL_GZIP" | gzip -d > "$KERNEL_FILENAME"
+    elif [[ "$YOCTO_DEPLOY_DIR" != "" ]]; then
+        cp -L "$YOCTO_DEPLOY_DIR/$KERNEL_FILENAME" .
     fi
 fi
 
-if [ ! -e "core-image-minimal-$YOCTO_MACHINE.tar.bz2" ]; then
-    wget http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/machines/qemu/$YOCTO_MACHINE/core-image-minimal-$YOCTO_MACHINE.tar.bz2
-fi
-
-if [ ! -e "core-image-minimal-dev-$YOCTO_MACHINE.tar.bz2" ]; then
-    wget http://downloads.yoctoproject.org/releases/yocto/yocto-2.4.1/machines/qemu/$YOCTO_MACHINE/core-image-minimal-dev-$YOCTO_MACHINE.tar.bz2
-fi
+for IMAGE in core-image-minimal core-image-minimal-dev; do
+    if [ ! -e "$IMAGE-$YOCTO_MACHINE.tar.bz2" ]; then
+        if [[ "$YOCTO_DEPLOY_DIR" != "" ]]; then
+            cp -L "$YOCTO_DEPLOY_DIR/$IMAGE-$YOCTO_MACHINE.tar.bz2" .
+        else
+            wget http://downloads.yoctoproject.org/releases/yocto/yocto-$YOCTO_RELEASE/machines/qemu/$YOCTO_MACHINE/$IMAGE-$YOCTO_MACHINE.tar.bz2
+        fi
+    fi
+done
 
 if [[ "$DTB_URL" != "" ]]; then
     if [ ! -e "$DTB_FILENAME" ]; then